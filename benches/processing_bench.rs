@@ -1,6 +1,13 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use filereduce::core::{EdifactProcessor, Event};
+use filereduce::parallel::ParallelEvents;
+use filereduce::parser::segment_reader::SegmentReader;
+use filereduce::parser::tokenizer::{unescape, Delimiters, SegmentTokenizer};
 use filereduce::processor::{process, FileFormat};
 use filereduce::sink::file::FileDataSink;
+use filereduce::translations::TranslationRegistry;
+use std::borrow::Cow;
+use std::hint::black_box;
 use std::io::BufWriter;
 
 fn bench_process_small_edifact(c: &mut Criterion) {
@@ -20,5 +27,146 @@ fn bench_process_small_edifact(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_process_small_edifact);
+/// Single-line interchange with `messages` ORDERS messages of ten lines each
+fn synthetic_interchange(messages: usize) -> String {
+    let mut edi = String::from("UNA:+.? 'UNB+UNOC:3+SENDER:14+RECEIVER:14+260121:1200+REF1'");
+    for msg in 1..=messages {
+        edi.push_str(&format!(
+            "UNH+{msg}+ORDERS:D:96A:UN'BGM+220+PO{msg}+9'DTM+137:20260121:102'\
+             NAD+BY+5412345000013::9++O?'NEIL FOODS'NAD+SU+4012345500004::9'CUX+2:EUR:9'"
+        ));
+        for line in 1..=10 {
+            edi.push_str(&format!(
+                "LIN+{line}++SKU{line:05}:SA'QTY+21:{line}0:PCE'PRI+AAA:12.50'MOA+203:{line}25.00'"
+            ));
+        }
+        edi.push_str(&format!("UNS+S'CNT+2:10'UNT+{}+{msg}'", 7 + 10 * 4 + 3));
+    }
+    edi.push_str(&format!("UNZ+{messages}+REF1'"));
+    edi
+}
+
+/// The tokenizer as it was before [`SegmentTokenizer`]: a `Vec<Vec<_>>` per segment,
+/// kept here as the baseline
+fn tokenize_segment_vec<'a>(segment: &'a str, delimiters: &Delimiters) -> Vec<Vec<Cow<'a, str>>> {
+    let component = |raw: &'a str, escaped: bool| {
+        if escaped {
+            unescape(raw, delimiters)
+        } else {
+            Cow::Borrowed(raw)
+        }
+    };
+    let mut elements = Vec::new();
+    let mut components = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut chars = segment.char_indices();
+
+    while let Some((idx, c)) = chars.next() {
+        if c == delimiters.release {
+            escaped = true;
+            chars.next();
+        } else if c == delimiters.component || c == delimiters.element {
+            components.push(component(&segment[start..idx], escaped));
+            start = idx + c.len_utf8();
+            escaped = false;
+            if c == delimiters.element {
+                elements.push(std::mem::take(&mut components));
+            }
+        }
+    }
+    components.push(component(&segment[start..], escaped));
+    elements.push(components);
+    elements
+}
+
+fn bench_tokenizer(c: &mut Criterion) {
+    let data = synthetic_interchange(500);
+    let delimiters = Delimiters::default();
+    // Cut by the reader, so released terminators (`O?'NEIL`) stay inside their segment
+    let mut reader = SegmentReader::new(data.as_bytes());
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    while reader.read_segment(&mut segment).unwrap() {
+        segments.push(segment.clone());
+    }
+
+    let mut group = c.benchmark_group("edifact_tokenizer");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("tokenize_segment_vec", |b| {
+        b.iter(|| {
+            for segment in &segments {
+                black_box(tokenize_segment_vec(segment, &delimiters));
+            }
+        });
+    });
+
+    // Reuses the offset buffers across segments
+    group.bench_function("segment_tokenizer", |b| {
+        let mut tokenizer = SegmentTokenizer::new();
+        b.iter(|| {
+            for segment in &segments {
+                let view = tokenizer.tokenize(segment.as_bytes(), &delimiters);
+                black_box(view.value(1, 0));
+            }
+        });
+    });
+    group.finish();
+}
+
+fn bench_process_large_edifact(c: &mut Criterion) {
+    let data = synthetic_interchange(500);
+    let registry = TranslationRegistry::new().unwrap();
+
+    let mut group = c.benchmark_group("edifact_processor");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(20);
+    group.bench_function("process_to_writer", |b| {
+        // The UNH version dictionary is loaded once, on the first message
+        let mut processor = EdifactProcessor::with_registry(registry.clone());
+        b.iter(|| {
+            let mut output = Vec::with_capacity(data.len());
+            processor
+                .process_to_writer(data.as_bytes(), &mut output)
+                .unwrap();
+            black_box(output);
+        });
+    });
+    group.finish();
+}
+
+fn bench_process_parallel(c: &mut Criterion) {
+    let data = synthetic_interchange(2000);
+    let mut processor = EdifactProcessor::new();
+    // Loads the UNH version dictionary, which the workers then copy
+    processor.process_to_vec(data.as_bytes()).unwrap();
+
+    let mut group = c.benchmark_group("edifact_parallel");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8] {
+        group.bench_function(format!("threads_{}", threads), |b| {
+            b.iter(|| {
+                let events = ParallelEvents::new(&processor, data.as_bytes(), threads, true);
+                let mut documents = 0;
+                for event in events {
+                    if let Event::Message { .. } = event.unwrap() {
+                        documents += 1;
+                    }
+                }
+                black_box(documents);
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_process_small_edifact,
+    bench_tokenizer,
+    bench_process_large_edifact,
+    bench_process_parallel
+);
 criterion_main!(benches);
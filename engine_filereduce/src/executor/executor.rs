@@ -98,25 +98,19 @@ pub fn eval(expr: &Expr, row: &Row) -> bool {
     match expr {
         Expr::Eq(field, value) => row.fields.get(field) == Some(value),
 
-        Expr::Gt(field, value) => match (row.fields.get(field), value) {
-            (Some(Value::Number(v)), Value::Number(n)) => v > n,
-            _ => false,
-        },
+        Expr::Gt(field, value) => compare(row.fields.get(field), value) == Some(Ordering::Greater),
 
-        Expr::Lt(field, value) => match (row.fields.get(field), value) {
-            (Some(Value::Number(v)), Value::Number(n)) => v < n,
-            _ => false,
-        },
+        Expr::Lt(field, value) => compare(row.fields.get(field), value) == Some(Ordering::Less),
 
-        Expr::Gte(field, value) => match (row.fields.get(field), value) {
-            (Some(Value::Number(v)), Value::Number(n)) => v >= n,
-            _ => false,
-        },
+        Expr::Gte(field, value) => matches!(
+            compare(row.fields.get(field), value),
+            Some(Ordering::Greater | Ordering::Equal)
+        ),
 
-        Expr::Lte(field, value) => match (row.fields.get(field), value) {
-            (Some(Value::Number(v)), Value::Number(n)) => v <= n,
-            _ => false,
-        },
+        Expr::Lte(field, value) => matches!(
+            compare(row.fields.get(field), value),
+            Some(Ordering::Less | Ordering::Equal)
+        ),
 
         Expr::Like(field, pattern) => match row.fields.get(field) {
             Some(Value::Text(text)) => matches_like(text, pattern),
@@ -128,10 +122,16 @@ pub fn eval(expr: &Expr, row: &Row) -> bool {
             _ => false,
         },
 
-        Expr::Between(field, start, end) => match (row.fields.get(field), start, end) {
-            (Some(Value::Number(v)), Value::Number(s), Value::Number(e)) => v >= s && v <= e,
-            _ => false,
-        },
+        Expr::Between(field, start, end) => {
+            let field_val = row.fields.get(field);
+            matches!(
+                compare(field_val, start),
+                Some(Ordering::Greater | Ordering::Equal)
+            ) && matches!(
+                compare(field_val, end),
+                Some(Ordering::Less | Ordering::Equal)
+            )
+        }
 
         Expr::KindEq(kind) => &row.kind == kind,
 
@@ -143,6 +143,16 @@ pub fn eval(expr: &Expr, row: &Row) -> bool {
     }
 }
 
+/// Numbers compare as numbers and text lexically, so ISO-8601 dates
+/// (`2025-01-20`, `2025-01-20T10:30:00`) compare in date order
+fn compare(field_val: Option<&Value>, value: &Value) -> Option<Ordering> {
+    match (field_val?, value) {
+        (Value::Number(v), Value::Number(n)) => v.partial_cmp(n),
+        (Value::Text(v), Value::Text(t)) => Some(v.as_str().cmp(t)),
+        _ => None,
+    }
+}
+
 fn matches_like(text: &str, pattern: &str) -> bool {
     let pattern_regex = pattern.replace('%', ".*").replace('_', ".");
 
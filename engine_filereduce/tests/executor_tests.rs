@@ -62,3 +62,50 @@ fn test_select_projection() {
         "price should NOT be in projection"
     );
 }
+
+#[test]
+fn test_text_comparisons_order_iso_dates() {
+    use engine_filereduce::query::parser::Parser;
+
+    let rows: Vec<Row> = [
+        "2024-12-31",
+        "2025-01-10",
+        "2025-01-20T10:30:00",
+        "2025-02-01",
+    ]
+    .iter()
+    .map(|date| {
+        let mut row = Row::new(RowKind::UNH);
+        row.insert("date", Value::Text(date.to_string()));
+        row
+    })
+    .collect();
+
+    let dates = |filter: &str| -> Vec<String> {
+        let query = Query {
+            select: vec![],
+            filter: Some(Parser::new(filter).parse()),
+            limit: None,
+            order_by: None,
+            aggregates: vec![],
+        };
+        Executor::new(MockReader::new(rows.clone()), query)
+            .collect()
+            .iter()
+            .filter_map(|row| match row.get("date") {
+                Some(Value::Text(date)) => Some(date.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+
+    assert_eq!(
+        dates("date >= '2025-01-10'"),
+        ["2025-01-10", "2025-01-20T10:30:00", "2025-02-01"]
+    );
+    assert_eq!(dates("date < '2025-01-01'"), ["2024-12-31"]);
+    assert_eq!(
+        dates("date BETWEEN '2025-01-01' AND '2025-01-31'"),
+        ["2025-01-10", "2025-01-20T10:30:00"]
+    );
+}
@@ -1,4 +1,7 @@
-use filereduce::translations::{ElementConfig, Metadata, SegmentConfig, TranslationConfig};
+use filereduce::translations::{
+    CharClass, ComponentConfig, DataFormat, DataType, ElementConfig, Metadata, SegmentConfig,
+    TranslationConfig, TypedField,
+};
 use regex::Regex;
 use scraper::{Html, Selector};
 use std::collections::{BTreeMap, HashMap};
@@ -81,6 +84,7 @@ impl EdifactoryScraper {
                 description: format!("Auto‑scraped translation for EDIFACT version {}", version),
             },
             segments: segment_configs,
+            messages: BTreeMap::new(),
         })
     }
 
@@ -101,7 +105,9 @@ impl EdifactoryScraper {
         .collect();
 
         // Start with existing segments if provided
-        let mut segment_configs = existing.map(|c| c.segments).unwrap_or_else(BTreeMap::new);
+        let (mut segment_configs, messages) = existing
+            .map(|c| (c.segments, c.messages))
+            .unwrap_or_default();
 
         for (code, description) in segments {
             if whitelist.contains(&code.as_str()) {
@@ -126,6 +132,7 @@ impl EdifactoryScraper {
                 ),
             },
             segments: segment_configs,
+            messages,
         })
     }
 
@@ -207,6 +214,7 @@ impl EdifactoryScraper {
             use_qualifier,
             qualifiers: BTreeMap::new(), // TODO: scrape qualifier codes
             elements,
+            bindings: Vec::new(),
         })
     }
 
@@ -267,7 +275,11 @@ impl EdifactoryScraper {
                         eprintln!("    Component line: '{}'", comp_line);
                         if let Some(desc) = self.extract_description_from_component(comp_line) {
                             eprintln!("      -> desc '{}' at pos {}", desc, comp_pos);
-                            components.insert(comp_pos.to_string(), desc);
+                            let component = match self.typed_field(comp_line, &desc, desc.clone()) {
+                                Some(field) => ComponentConfig::Typed(field),
+                                None => ComponentConfig::Simple(desc),
+                            };
+                            components.insert(comp_pos.to_string(), component);
                             comp_pos += 1;
                         } else {
                             eprintln!("      -> no description extracted");
@@ -275,9 +287,14 @@ impl EdifactoryScraper {
                     }
                     let raw_label = self.extract_label_from_line(line);
                     let label = self.normalize_element_label(segment_code, position, &raw_label);
+                    let mandatory = self.is_mandatory(line);
                     elements.insert(
                         position.to_string(),
-                        ElementConfig::Composite { label, components },
+                        ElementConfig::Composite {
+                            label,
+                            components,
+                            mandatory,
+                        },
                     );
                     i = j;
                 } else {
@@ -289,7 +306,11 @@ impl EdifactoryScraper {
                     if raw_label.to_lowercase().contains("qualifier") && position == 1 {
                         use_qualifier = true;
                     }
-                    elements.insert(position.to_string(), ElementConfig::Simple(label));
+                    let element = match self.typed_field(line, &raw_label, label.clone()) {
+                        Some(field) => ElementConfig::Typed(field),
+                        None => ElementConfig::Simple(label),
+                    };
+                    elements.insert(position.to_string(), element);
                     i += 1;
                 }
                 position += 1;
@@ -316,6 +337,43 @@ impl EdifactoryScraper {
             .unwrap_or_else(|| "Unknown".to_string())
     }
 
+    /// Data type and format of a simple element or component line, from its
+    /// representation (`an..35`, `n..15`) and its name. Mandatory lines always get a
+    /// typed field, as a bare label cannot carry the flag.
+    fn typed_field(&self, line: &str, description: &str, label: String) -> Option<TypedField> {
+        let format = line
+            .split_whitespace()
+            .rev()
+            .find_map(|part| part.parse::<DataFormat>().ok());
+        let mandatory = self.is_mandatory(line);
+        if format.is_none() && !mandatory {
+            return None;
+        }
+        let description = description.to_lowercase();
+        let data_type = format.as_ref().map(|format| {
+            if description.contains("coded") || description.contains("qualifier") {
+                DataType::Code
+            } else if description.starts_with("date") {
+                DataType::Date
+            } else if format.char_class == CharClass::Numeric {
+                DataType::Numeric
+            } else {
+                DataType::Text
+            }
+        });
+        Some(TypedField {
+            label,
+            data_type,
+            format,
+            mandatory,
+        })
+    }
+
+    /// Whether the status column of an element or component line is `M`
+    fn is_mandatory(&self, line: &str) -> bool {
+        line.split_whitespace().any(|part| part == "M")
+    }
+
     fn is_format_specifier(&self, part: &str) -> bool {
         part == "C"
             || part == "M"
@@ -422,6 +480,7 @@ impl EdifactoryScraper {
             version: existing.version,
             metadata: new.metadata, // Use newer metadata
             segments: merged_segments,
+            messages: existing.messages,
         }
     }
 
@@ -449,6 +508,7 @@ impl EdifactoryScraper {
                                 ElementConfig::Simple("DocumentNumber".to_string()),
                             ),
                         ]),
+                        bindings: Vec::new(),
                     },
                 ),
                 (
@@ -467,9 +527,11 @@ impl EdifactoryScraper {
                             },
                         )]),
                         elements: BTreeMap::new(),
+                        bindings: Vec::new(),
                     },
                 ),
             ]),
+            messages: BTreeMap::new(),
         }
     }
 }
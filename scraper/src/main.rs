@@ -199,6 +199,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     description: format!("Single segment translation for {}", seg),
                 },
                 segments: segments_map,
+                messages: Default::default(),
             }
         } else if let Some(existing) = existing_config {
             scraper.scrape_version_update(&version, Some(existing))?
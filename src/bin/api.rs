@@ -1,11 +1,17 @@
 
 use bytes::Bytes;
 
+use filereduce::dedupe::{DedupeKey, DedupeStore, Deduplicator, DuplicatePolicy};
+use filereduce::parser::charset::Charset;
+use filereduce::parser::xml::{RecordPath, XmlOptions};
 use filereduce::serializer::EdifactSerializer;
 use filereduce::storage::{Storage, MemoryStorage, UploadRequest};
+use filereduce::telemetry::TelemetryCollector;
 #[cfg(feature = "gcs")]
 use filereduce::storage::GcsStorage;
 use filereduce::translations::TranslationRegistry;
+use filereduce::tree::OutputMode;
+use filereduce::validation::{PartnerStrictness, Strictness, StrictnessPolicy};
 use filereducelib::{FileReduceCompressor, FileReduceDecompressor};
 use std::collections::HashMap;
 use serde::Serialize;
@@ -43,10 +49,15 @@ struct AppState {
     storage: Arc<dyn Storage>,
     tasks: Arc<RwLock<HashMap<Uuid, TaskStatus>>>,
     broadcast_tx: broadcast::Sender<TaskEvent>,
+    /// Unknown segments and unmapped elements of every EDIFACT processed since startup
+    telemetry: Arc<RwLock<TelemetryCollector>>,
+    /// Keys of the EDIFACT messages written so far, when `DEDUPE_STORE` names a file
+    dedupe_store: Option<Arc<std::sync::Mutex<DedupeStore>>>,
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt().init();
     match dotenv::dotenv() {
         Ok(path) => println!("Loaded .env from {:?}", path),
         Err(e) => println!("Failed to load .env: {}", e),
@@ -79,12 +90,24 @@ async fn main() {
         }
     };
 
+    let dedupe_store = match env::var("DEDUPE_STORE") {
+        Ok(path) => {
+            let store = DedupeStore::open(&path)
+                .map_err(|e| format!("Failed to open the dedupe store {}: {}", path, e))?;
+            tracing::info!("Checking duplicates against {} ({} keys)", path, store.len());
+            Some(Arc::new(std::sync::Mutex::new(store)))
+        }
+        Err(_) => None,
+    };
+
     let (broadcast_tx, _) = broadcast::channel(100);
     let state = AppState {
         registry: Arc::new(RwLock::new(registry)),
         storage,
         tasks: Arc::new(RwLock::new(HashMap::new())),
         broadcast_tx,
+        telemetry: Arc::new(RwLock::new(TelemetryCollector::new())),
+        dedupe_store,
     };
 
     let health = warp::path!("health")
@@ -98,10 +121,17 @@ async fn main() {
 
     let process_edifact = warp::path!("process" / "edifact")
         .and(warp::post())
+        .and(warp::query::<EdifactParams>())
         .and(warp::body::bytes())
         .and(with_state(state.clone()))
         .and_then(process_edifact_handler);
 
+    let process_xml = warp::path!("process" / "xml")
+        .and(warp::post())
+        .and(warp::query::<XmlParams>())
+        .and(warp::body::bytes())
+        .and_then(process_xml_handler);
+
     let process_jsonl = warp::path!("process" / "jsonl")
         .and(warp::post())
         .and(warp::body::bytes())
@@ -138,6 +168,11 @@ async fn main() {
         .and(with_state(state.clone()))
         .and_then(status_handler);
 
+    let telemetry = warp::path!("telemetry")
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(telemetry_handler);
+
     let events = warp::path!("events")
         .and(warp::get())
         .and(with_state(state.clone()))
@@ -151,6 +186,7 @@ async fn main() {
     let routes = health
         .or(reload)
         .or(process_edifact)
+        .or(process_xml)
         .or(process_jsonl)
         .or(decompress_fra)
         .or(convert_json_to_edi)
@@ -158,6 +194,7 @@ async fn main() {
         .or(download)
         .or(data)
         .or(status)
+        .or(telemetry)
         .or(events)
         .or(process_cloud)
         .with(warp::cors()
@@ -171,6 +208,7 @@ async fn main() {
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string()).parse().unwrap();
     println!("API server starting on 0.0.0.0:{}", port);
     warp::serve(routes).run(([0, 0, 0, 0], port)).await;
+    Ok(())
 }
 
 fn with_state(state: AppState) -> impl Filter<Extract = (AppState,), Error = Infallible> + Clone {
@@ -225,6 +263,11 @@ async fn status_handler(task_id: Uuid, state: AppState) -> Result<impl Reply, Re
     }
 }
 
+async fn telemetry_handler(state: AppState) -> Result<impl Reply, Rejection> {
+    let telemetry = state.telemetry.read().await;
+    Ok(warp::reply::json(&telemetry.report()))
+}
+
 async fn events_handler(state: AppState) -> Result<impl Reply, Rejection> {
     let mut rx = state.broadcast_tx.subscribe();
     let stream = async_stream::stream! {
@@ -240,9 +283,31 @@ async fn events_handler(state: AppState) -> Result<impl Reply, Rejection> {
     Ok(sse::reply(stream))
 }
 
+/// Query string of `POST /process/edifact`, e.g. `?charset=UNOC&reject_invalid=true`
+#[derive(Debug, Clone, serde::Deserialize)]
+struct EdifactParams {
+    charset: Option<String>,
+    #[serde(default)]
+    reject_invalid: bool,
+    #[serde(default)]
+    source_positions: bool,
+    /// `document` (default) or `tree`
+    output_mode: Option<String>,
+    /// `lenient` (default), `warn` or `strict`
+    strictness: Option<String>,
+    /// Per-sender overrides, e.g. `SENDER1=strict,SENDER2=warn`
+    partner_strictness: Option<String>,
+    /// `skip` (default), `flag` or `replace` messages already written, with `DEDUPE_STORE`
+    on_duplicate: Option<String>,
+    /// Fields identifying a message, e.g. `sender,document_number`
+    dedupe_key: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, serde::Deserialize)]
 struct CloudProcessRequest {
     operation: String, // "edifact", "jsonl", "fra"
+    #[serde(default)]
+    charset: Option<String>, // solo "edifact": UNOC, latin1, utf-8...
 }
 
 async fn process_cloud_handler(file_id: Uuid, req: CloudProcessRequest, state: AppState) -> Result<impl Reply, Rejection> {
@@ -266,6 +331,7 @@ async fn process_cloud_handler(file_id: Uuid, req: CloudProcessRequest, state: A
     let registry = state.registry.clone();
     let broadcast_tx = state.broadcast_tx.clone();
     let tasks_map = state.tasks.clone();
+    let telemetry = state.telemetry.clone();
     
     // Spawn worker asíncrono
     tokio::spawn(async move {
@@ -283,7 +349,7 @@ async fn process_cloud_handler(file_id: Uuid, req: CloudProcessRequest, state: A
         });
         
         // Procesamiento real
-        let result = process_file_cloud(file_id, &req.operation, storage, registry).await;
+        let result = process_file_cloud(file_id, &req.operation, req.charset.as_deref(), storage, registry, telemetry).await;
         
         match result {
             Ok(result_file_id) => {
@@ -326,8 +392,10 @@ async fn process_cloud_handler(file_id: Uuid, req: CloudProcessRequest, state: A
 async fn process_file_cloud(
     file_id: Uuid,
     operation: &str,
+    charset: Option<&str>,
     storage: Arc<dyn Storage>,
     registry: Arc<RwLock<TranslationRegistry>>,
+    telemetry: Arc<RwLock<TelemetryCollector>>,
 ) -> Result<Uuid, Box<dyn Error + Send>> {
     // Descargar archivo del storage
     let bytes = storage.retrieve_bytes(file_id).await?;
@@ -337,8 +405,20 @@ async fn process_file_cloud(
             // EDIFACT -> JSONL
             let reader = std::io::Cursor::new(bytes.to_vec());
             let registry = registry.read().await;
-            let mut processor = filereduce::core::EdifactProcessor::with_registry(registry.clone());
-            let result = processor.process_to_vec(reader).map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+            let mut processor = filereduce::core::EdifactProcessor::with_registry(registry.clone())
+                .with_telemetry(true);
+            if let Some(charset) = charset {
+                let charset = charset.parse::<Charset>().map_err(|e| {
+                    Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)) as Box<dyn Error + Send>
+                })?;
+                processor = processor.with_charset(charset);
+            }
+            let mut result = Vec::new();
+            let mut collected = TelemetryCollector::new();
+            processor
+                .process_with_telemetry(reader, &mut result, &mut collected)
+                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+            telemetry.write().await.merge(collected);
             
             // Guardar resultado en storage
             let result_file_id = Uuid::new_v4();
@@ -419,10 +499,63 @@ async fn reload_translations_handler(state: AppState) -> Result<impl Reply, Reje
     }
 }
 
-async fn process_edifact_handler(body: Bytes, state: AppState) -> Result<impl Reply, Rejection> {
+fn strictness_policy(params: &EdifactParams) -> Result<StrictnessPolicy, String> {
+    let default = params.strictness.as_deref().map(str::parse::<Strictness>).transpose()?;
+    let mut policy = StrictnessPolicy::new(default.unwrap_or_default());
+    for partner in params.partner_strictness.iter().flat_map(|p| p.split(',')) {
+        let partner: PartnerStrictness = partner.trim().parse()?;
+        policy = policy.with_partner(partner.sender, partner.strictness);
+    }
+    Ok(policy)
+}
+
+async fn process_edifact_handler(params: EdifactParams, body: Bytes, state: AppState) -> Result<impl Reply, Rejection> {
     use filereduce::core::EdifactProcessor;
     use std::io::{BufReader, Cursor};
 
+    let charset = match params.charset.as_deref().map(str::parse::<Charset>).transpose() {
+        Ok(charset) => charset,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+    };
+    let output_mode = match params.output_mode.as_deref().map(str::parse::<OutputMode>).transpose() {
+        Ok(mode) => mode.unwrap_or_default(),
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+    };
+
+    let strictness = match strictness_policy(&params) {
+        Ok(policy) => policy,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+    };
+    let dedupe = params.on_duplicate.as_deref().map(str::parse::<DuplicatePolicy>).transpose()
+        .and_then(|policy| {
+            let key = params.dedupe_key.as_deref().map(str::parse::<DedupeKey>).transpose()?;
+            Ok((policy.unwrap_or_default(), key.unwrap_or_default()))
+        });
+    let (on_duplicate, dedupe_key) = match dedupe {
+        Ok(dedupe) => dedupe,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+    };
+    let dedupe_store = state.dedupe_store.clone();
     let task_id = Uuid::new_v4();
     let state = state.clone();
     {
@@ -441,18 +574,43 @@ async fn process_edifact_handler(body: Bytes, state: AppState) -> Result<impl Re
 
     // Spawn blocking processing
     let result = tokio::task::spawn_blocking(move || {
-        let mut processor = EdifactProcessor::with_registry(registry);
+        let mut processor = EdifactProcessor::with_registry(registry)
+            .with_reject_invalid(params.reject_invalid)
+            .with_strictness(strictness)
+            .with_telemetry(true)
+            .with_source_positions(params.source_positions)
+            .with_output_mode(output_mode);
+        if let Some(charset) = charset {
+            processor = processor.with_charset(charset);
+        }
         let reader = BufReader::new(Cursor::new(input));
-        processor.process_to_vec(reader)
+        let mut output = Vec::new();
+        let mut telemetry = TelemetryCollector::new();
+        let mut dedupe = dedupe_store.map(|store| Deduplicator::new(store, dedupe_key, on_duplicate));
+        match dedupe.as_mut() {
+            Some(dedupe) => processor.process_deduplicated(reader, &mut output, &mut telemetry, dedupe)?,
+            None => processor.process_with_telemetry(reader, &mut output, &mut telemetry)?,
+        }
+        Ok::<_, filereduce::error::FileReduceError>((output, telemetry, dedupe))
     }).await;
 
     // Handle result and store output in storage
     match result {
-        Ok(Ok(output)) => {
+        Ok(Ok((output, telemetry, dedupe))) => {
+            state.telemetry.write().await.merge(telemetry);
+            let duplicates = dedupe.as_ref().map_or(0, Deduplicator::duplicates);
             // Store output in storage
             let file_id = Uuid::new_v4();
-            match state.storage.store_bytes(file_id, output.into()).await {
-                Ok(_) => {
+            let stored = match state.storage.store_bytes(file_id, output.into()).await {
+                // The keys of the messages are remembered once the output is stored
+                Ok(_) => match dedupe {
+                    Some(mut dedupe) => dedupe.commit().map_err(|e| format!("Dedupe store error: {}", e)),
+                    None => Ok(()),
+                },
+                Err(e) => Err(format!("Storage error: {}", e)),
+            };
+            match stored {
+                Ok(()) => {
                     // Update task status
                     let mut tasks = state.tasks.write().await;
                     tasks.insert(task_id, TaskStatus::Completed { file_id });
@@ -462,15 +620,12 @@ async fn process_edifact_handler(body: Bytes, state: AppState) -> Result<impl Re
                             "task_id": task_id,
                             "status": "completed",
                             "download_url": format!("/download/{}", file_id),
+                            "duplicates": duplicates,
                         })),
                         warp::http::StatusCode::ACCEPTED,
                     ).into_response())
                 }
-                Err(e) => {
-                    let error_msg = {
-                        let e = e;
-                        format!("Storage error: {}", e)
-                    };
+                Err(error_msg) => {
                     eprintln!("{}", error_msg);
                     let mut tasks = state.tasks.write().await;
                     tasks.insert(task_id, TaskStatus::Failed { error: error_msg.clone() });
@@ -519,6 +674,74 @@ async fn process_edifact_handler(body: Bytes, state: AppState) -> Result<impl Re
     }
 }
 
+/// Query string of `POST /process/xml`, e.g. `?record_path=/Orders/Order&strip_namespaces=true&infer_types=true`
+#[derive(Debug, Clone, serde::Deserialize)]
+struct XmlParams {
+    /// Elements read as records; `record`, `item` and `row` when missing
+    record_path: Option<String>,
+    #[serde(default)]
+    strip_namespaces: bool,
+    /// Put before attribute names, `@` when missing
+    attribute_prefix: Option<String>,
+    #[serde(default)]
+    infer_types: bool,
+}
+
+async fn process_xml_handler(params: XmlParams, body: Bytes) -> Result<impl Reply, Rejection> {
+    use filereduce::parser::xml::process_xml_with_options;
+    use std::io::Cursor;
+
+    let record_path = match params.record_path.as_deref().map(str::parse::<RecordPath>).transpose() {
+        Ok(path) => path,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+    };
+    let mut options = XmlOptions {
+        record_path,
+        strip_namespaces: params.strip_namespaces,
+        infer_types: params.infer_types,
+        ..Default::default()
+    };
+    if let Some(prefix) = params.attribute_prefix {
+        options.attribute_prefix = prefix;
+    }
+
+    let input = body.to_vec();
+    match tokio::task::spawn_blocking(move || {
+        let mut output = Vec::new();
+        process_xml_with_options(Cursor::new(input), &mut output, None, &options).map(|()| output)
+    })
+    .await
+    {
+        Ok(Ok(output)) => Ok(warp::reply::with_header(
+            output,
+            warp::http::header::CONTENT_TYPE,
+            "application/jsonl",
+        )
+        .into_response()),
+        Ok(Err(e)) => {
+            eprintln!("XML error: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": format!("{}", e) })),
+                warp::http::StatusCode::BAD_REQUEST,
+            )
+            .into_response())
+        }
+        Err(join_err) => {
+            eprintln!("Join error: {}", join_err);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": "Internal server error" })),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response())
+        }
+    }
+}
+
 async fn process_jsonl_handler(body: Bytes) -> Result<impl Reply, Rejection> {
     use filereducelib::FileReduceCompressor;
     use std::io::Cursor;
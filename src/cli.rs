@@ -1,3 +1,8 @@
+use crate::dedupe::{DedupeKey, DuplicatePolicy};
+use crate::parser::charset::Charset;
+use crate::parser::xml::RecordPath;
+use crate::tree::OutputMode;
+use crate::validation::{PartnerStrictness, Strictness};
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
@@ -21,6 +26,24 @@ pub enum Commands {
         #[arg(short = 'f', long)]
         format: Option<String>,
 
+        /// With `--format xml`, the elements read as records: `/Orders/Order` from the
+        /// root, `Order` at any depth, `ns:Order` for one prefix only. Defaults to
+        /// `record`, `item` and `row` elements
+        #[arg(long)]
+        xml_record_path: Option<RecordPath>,
+
+        /// With `--format xml`, drop namespace prefixes from field names
+        #[arg(long)]
+        xml_strip_namespaces: bool,
+
+        /// With `--format xml`, put this before attribute names in the output
+        #[arg(long, default_value = "@")]
+        xml_attribute_prefix: String,
+
+        /// With `--format xml`, write booleans and numbers instead of text
+        #[arg(long)]
+        xml_infer_types: bool,
+
         #[arg(short = 'q', long)]
         query: Option<String>,
 
@@ -29,6 +52,72 @@ pub enum Commands {
 
         #[arg(long)]
         fra: bool,
+
+        /// Charset to decode EDIFACT with (UNOC, latin1, utf-8...), overriding UNB
+        #[arg(long)]
+        charset: Option<Charset>,
+
+        /// Write the envelope validation report (JSONL, one line per message) here
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        /// Drop messages whose control counts or references do not match
+        #[arg(long)]
+        reject_invalid: bool,
+
+        /// Set messages that fail aside and keep going, ending with a summary
+        #[arg(long)]
+        tolerant: bool,
+
+        /// Write the messages set aside (JSONL: raw EDIFACT, reason, position) here;
+        /// implies --tolerant
+        #[arg(long)]
+        dead_letter: Option<PathBuf>,
+
+        /// `lenient`, `warn` or `strict` about segments missing from the dictionary
+        /// and empty mandatory elements; strict stops at the first such message
+        #[arg(long, default_value_t = Strictness::Lenient)]
+        strictness: Strictness,
+
+        /// Strictness for the interchanges of one UNB sender, as SENDER=LEVEL; repeatable
+        #[arg(long)]
+        partner_strictness: Vec<PartnerStrictness>,
+
+        /// Write a JSON report of unknown segments and unmapped elements (by version,
+        /// message type, sender and segment, with samples) here
+        #[arg(long)]
+        telemetry: Option<PathBuf>,
+
+        /// Remember the key of every message written in this file (JSONL) and check
+        /// later messages, of this run or the next ones, against it
+        #[arg(long)]
+        dedupe_store: Option<PathBuf>,
+
+        /// `skip`, `flag` (adds `_duplicate`) or `replace` messages already in the
+        /// --dedupe-store
+        #[arg(long, default_value_t = DuplicatePolicy::Skip)]
+        on_duplicate: DuplicatePolicy,
+
+        /// Comma separated fields identifying a message, among sender, receiver,
+        /// interchange_id, doc_type, document_number, document_date, buyer and seller
+        #[arg(long, default_value_t = DedupeKey::default())]
+        dedupe_key: DedupeKey,
+
+        /// Add a `_source` field (byte offset, segment and message index) to the output
+        #[arg(long)]
+        source_positions: bool,
+
+        /// `document` (header fields and lines) or `tree` (every segment, by segment group)
+        #[arg(long, default_value_t = OutputMode::Document)]
+        output_mode: OutputMode,
+
+        /// Parse EDIFACT messages on this many threads (1 reads sequentially)
+        #[arg(long, default_value_t = 1)]
+        threads: usize,
+
+        /// With --threads, write messages as they finish instead of in input order
+        #[arg(long)]
+        unordered: bool,
     },
 
     Query {
@@ -58,5 +147,111 @@ pub enum Commands {
 
         #[arg(long)]
         fra: bool,
+
+        /// Charset to decode EDIFACT with (UNOC, latin1, utf-8...), overriding UNB
+        #[arg(long)]
+        charset: Option<Charset>,
+
+        /// Write the envelope validation report (JSONL, one line per message) here
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        /// Drop messages whose control counts or references do not match
+        #[arg(long)]
+        reject_invalid: bool,
+
+        /// Set messages that fail aside and keep going, ending with a summary
+        #[arg(long)]
+        tolerant: bool,
+
+        /// Write the messages set aside (JSONL: raw EDIFACT, reason, position) here;
+        /// implies --tolerant
+        #[arg(long)]
+        dead_letter: Option<PathBuf>,
+
+        /// `lenient`, `warn` or `strict` about segments missing from the dictionary
+        /// and empty mandatory elements; strict stops at the first such message
+        #[arg(long, default_value_t = Strictness::Lenient)]
+        strictness: Strictness,
+
+        /// Strictness for the interchanges of one UNB sender, as SENDER=LEVEL; repeatable
+        #[arg(long)]
+        partner_strictness: Vec<PartnerStrictness>,
+
+        /// Write a JSON report of unknown segments and unmapped elements (by version,
+        /// message type, sender and segment, with samples) here
+        #[arg(long)]
+        telemetry: Option<PathBuf>,
+
+        /// Remember the key of every message written in this file (JSONL) and check
+        /// later messages, of this run or the next ones, against it
+        #[arg(long)]
+        dedupe_store: Option<PathBuf>,
+
+        /// `skip` messages already in the --dedupe-store; `flag` and `replace` are
+        /// refused, as the database only takes the first copy
+        #[arg(long, default_value_t = DuplicatePolicy::Skip, value_parser = database_duplicate_policy)]
+        on_duplicate: DuplicatePolicy,
+
+        /// Comma separated fields identifying a message, among sender, receiver,
+        /// interchange_id, doc_type, document_number, document_date, buyer and seller
+        #[arg(long, default_value_t = DedupeKey::default())]
+        dedupe_key: DedupeKey,
+
+        /// Add a `_source` field (byte offset, segment and message index) to the
+        /// documents sent to the database
+        #[arg(long)]
+        source_positions: bool,
     },
 }
+
+/// `--on-duplicate` of `insert`. The database takes a single copy of each message
+/// (UQ_EDI_Order), so a duplicate that is written, flagged or not, fails its batch.
+fn database_duplicate_policy(s: &str) -> Result<DuplicatePolicy, String> {
+    match s.parse()? {
+        DuplicatePolicy::Skip => Ok(DuplicatePolicy::Skip),
+        policy => Err(format!(
+            "{} is not supported by insert: the database rejects a second copy of a message",
+            policy
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_only_skips_duplicates() {
+        let insert = |policy: &str| {
+            Cli::try_parse_from([
+                "filereduce",
+                "insert",
+                "in.edi",
+                "--config",
+                "ingest.yaml",
+                "--on-duplicate",
+                policy,
+            ])
+        };
+        let Commands::Insert { on_duplicate, .. } = insert("skip").unwrap().command else {
+            panic!("not an insert");
+        };
+        assert_eq!(on_duplicate, DuplicatePolicy::Skip);
+        for policy in ["flag", "replace"] {
+            let err = insert(policy).unwrap_err().to_string();
+            assert!(err.contains("not supported by insert"), "{err}");
+        }
+
+        // `process` writes to a file, where flagged copies are fine
+        let process = Cli::try_parse_from([
+            "filereduce",
+            "process",
+            "in.edi",
+            "out.jsonl",
+            "--on-duplicate",
+            "flag",
+        ]);
+        assert!(process.is_ok());
+    }
+}
@@ -1,53 +1,184 @@
-use crate::error::Result;
-use crate::parser::edifact::parse_segment_with_registry;
-use crate::parser::segment::Segment;
-use crate::translations::{ElementConfig, TranslationRegistry};
-use crate::version_detector::extract_version_from_unh;
-use std::collections::HashMap;
+use crate::dedupe::Deduplicator;
+use crate::error::{FileReduceError, Result};
+use crate::messages::{DetailsCollector, MessageDetails};
+use crate::parser::charset::Charset;
+use crate::parser::datetime::normalize_or_raw;
+use crate::parser::edifact::parse_segment_view;
+use crate::parser::segment::{DynamicElements, Segment};
+use crate::parser::segment_reader::{SegmentReader, SegmentSource, SourcePosition};
+use crate::parser::structure::{Placement, StructureTracker};
+use crate::parser::tokenizer::{Delimiters, SegmentTokenizer, SegmentView};
+use crate::telemetry::{TelemetryCollector, UnmappedSegment};
+use crate::translations::{
+    convert_value, BindingTarget, BindingTransform, ComponentConfig, ElementConfig, FieldBinding,
+    LinePrimary, SegmentConfig, TranslationRegistry, TypedField,
+};
+use crate::tree::{tree_segment, MessageTree, OutputMode, TreeBuilder};
+use crate::validation::{
+    EnvelopeValidator, Finding, Status, Strictness, StrictnessPolicy, ValidationReport,
+};
+use crate::version_detector::extract_version_from_unh_with;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{BufRead, Write};
 
 // Re-export the document structures
-pub use crate::model::streaming::{StreamingDocument, StreamingLine};
+pub use crate::model::streaming::{
+    FunctionalGroup, Party, Price, Quantity, SegmentGroup, StreamingDocument, StreamingLine,
+};
 
 /// Synchronous EDIFACT processor that writes to any Write implementor
 pub struct EdifactProcessor {
     registry: Option<TranslationRegistry>,
+    /// The registry the processor was created with, which version registries inherit from
+    base_registry: Option<TranslationRegistry>,
     version: Option<String>,
+    /// Versions whose registry could not be loaded, not to be scraped again
+    failed_versions: HashSet<String>,
+    charset: Option<Charset>,
+    reject_invalid: bool,
+    tolerant: bool,
+    strictness: StrictnessPolicy,
+    telemetry: bool,
+    source_positions: bool,
+    output_mode: OutputMode,
 }
 
 impl EdifactProcessor {
     pub fn new() -> Self {
+        let registry = TranslationRegistry::new().ok();
         Self {
-            registry: TranslationRegistry::new().ok(),
+            base_registry: registry.clone(),
+            registry,
             version: None,
+            failed_versions: HashSet::new(),
+            charset: None,
+            reject_invalid: false,
+            tolerant: false,
+            strictness: StrictnessPolicy::default(),
+            telemetry: false,
+            source_positions: false,
+            output_mode: OutputMode::Document,
         }
     }
 
     pub fn with_registry(registry: TranslationRegistry) -> Self {
         Self {
+            base_registry: Some(registry.clone()),
             registry: Some(registry),
             version: None,
+            failed_versions: HashSet::new(),
+            charset: None,
+            reject_invalid: false,
+            tolerant: false,
+            strictness: StrictnessPolicy::default(),
+            telemetry: false,
+            source_positions: false,
+            output_mode: OutputMode::Document,
         }
     }
 
+    /// Decodes input with `charset` instead of the one declared in UNB
+    pub fn with_charset(mut self, charset: Charset) -> Self {
+        self.charset = Some(charset);
+        self
+    }
+
+    /// Drops messages whose envelope validation ends in an error instead of writing them
+    pub fn with_reject_invalid(mut self, reject: bool) -> Self {
+        self.reject_invalid = reject;
+        self
+    }
+
+    /// Keeps going past segments that cannot be read, and turns every message whose
+    /// validation ends in an error into an [`Event::Rejected`] with its raw segments
+    /// instead of a document
+    pub fn with_tolerant(mut self, tolerant: bool) -> Self {
+        self.tolerant = tolerant;
+        self
+    }
+
+    /// Treatment of segments missing from the dictionary and of empty mandatory
+    /// elements, per UNB sender. Under [`Strictness::Strict`] a message with such
+    /// segments fails the run with [`FileReduceError::StrictViolation`], or is
+    /// rejected like any other failing message in tolerant mode.
+    pub fn with_strictness(mut self, strictness: StrictnessPolicy) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Also reports, as [`Event::Unmapped`], dictionary segments whose elements carry
+    /// values at positions the dictionary does not describe. Unknown segments are
+    /// always reported.
+    pub fn with_telemetry(mut self, enabled: bool) -> Self {
+        self.telemetry = enabled;
+        self
+    }
+
+    /// Adds a `_source` field with the input position of UNH (documents) and LIN (lines)
+    pub fn with_source_positions(mut self, enabled: bool) -> Self {
+        self.source_positions = enabled;
+        self
+    }
+
+    /// Writes a [`MessageTree`] per message instead of a [`StreamingDocument`]
+    pub fn with_output_mode(mut self, mode: OutputMode) -> Self {
+        self.output_mode = mode;
+        self
+    }
+
     pub fn version(&self) -> Option<&String> {
         self.version.as_ref()
     }
 
+    /// Charset forced with [`EdifactProcessor::with_charset`]
+    pub fn charset(&self) -> Option<Charset> {
+        self.charset
+    }
+
+    /// A processor with the same settings and dictionary, for another thread
+    pub(crate) fn worker(&self) -> Self {
+        Self {
+            registry: self.registry.clone(),
+            base_registry: self.base_registry.clone(),
+            version: self.version.clone(),
+            failed_versions: self.failed_versions.clone(),
+            charset: self.charset,
+            reject_invalid: self.reject_invalid,
+            tolerant: self.tolerant,
+            strictness: self.strictness.clone(),
+            telemetry: self.telemetry,
+            source_positions: self.source_positions,
+            output_mode: self.output_mode,
+        }
+    }
+
     fn load_version_registry(&mut self, version: &str) -> Result<()> {
+        if self.failed_versions.contains(version) {
+            return Ok(());
+        }
         match TranslationRegistry::from_version_or_scrape(version) {
             Ok(registry) => {
+                // Inherit from the original registry, not from the one of the previous
+                // version, whose segments would otherwise leak into this one
+                if let Some(base) = &self.base_registry {
+                    registry.inherit(base);
+                }
                 self.registry = Some(registry);
                 self.version = Some(version.to_string());
                 Ok(())
             }
             Err(e) => {
-                // If we can't load version-specific registry, keep current one
-                // Log warning
+                // If we can't load version-specific registry, keep current one and
+                // don't try again for the rest of the run
                 eprintln!(
                     "Warning: Failed to load translation registry for version {}: {}",
                     version, e
                 );
+                self.failed_versions.insert(version.to_string());
+                self.version = Some(version.to_string());
                 Ok(())
             }
         }
@@ -58,304 +189,1086 @@ impl EdifactProcessor {
         reader: R,
         writer: &mut W,
     ) -> Result<()> {
-        let mut current_doc: Option<StreamingDocument> = None;
-        let mut current_line: Option<StreamingLine> = None;
-        let mut interchange_id = String::new();
-        let mut sender_id = String::new();
-        let mut receiver_id = String::new();
-
-        for line in reader.lines() {
-            let raw = line?;
-            if raw.trim().is_empty() {
-                continue;
-            }
-            let segment = parse_segment_with_registry(&raw, self.registry.as_ref());
-
-            match segment {
-                Segment::UNB(s, r, id) => {
-                    sender_id = s.to_string();
-                    receiver_id = r.to_string();
-                    interchange_id = id.to_string();
-                }
-                Segment::UNH => {
-                    // Extract version from UNH segment and load appropriate translation registry
-                    if let Some(detected_version) = extract_version_from_unh(&raw) {
-                        if self.version.as_ref() != Some(&detected_version) {
-                            // Try to load version-specific registry
-                            let _ = self.load_version_registry(&detected_version);
+        self.process_with_report(reader, writer, &mut std::io::sink())
+    }
+
+    /// Like [`EdifactProcessor::process_to_writer`], also writing one JSON line per
+    /// message and per interchange with the envelope validation result to `report`
+    pub fn process_with_report<R: BufRead, W: Write>(
+        &mut self,
+        reader: R,
+        writer: &mut W,
+        report: &mut dyn Write,
+    ) -> Result<()> {
+        self.write_events(reader, writer, report, None, None)
+    }
+
+    /// Like [`EdifactProcessor::process_to_writer`], also counting unknown segments in
+    /// `telemetry`, and unmapped elements when built [`with_telemetry`](Self::with_telemetry)
+    pub fn process_with_telemetry<R: BufRead, W: Write>(
+        &mut self,
+        reader: R,
+        writer: &mut W,
+        telemetry: &mut TelemetryCollector,
+    ) -> Result<()> {
+        self.write_events(reader, writer, &mut std::io::sink(), Some(telemetry), None)
+    }
+
+    /// Like [`EdifactProcessor::process_with_telemetry`], writing only the messages
+    /// `dedupe` admits. Their keys stay pending: call [`Deduplicator::commit`] once the
+    /// output is safely stored.
+    pub fn process_deduplicated<R: BufRead, W: Write>(
+        &mut self,
+        reader: R,
+        writer: &mut W,
+        telemetry: &mut TelemetryCollector,
+        dedupe: &mut Deduplicator,
+    ) -> Result<()> {
+        self.write_events(
+            reader,
+            writer,
+            &mut std::io::sink(),
+            Some(telemetry),
+            Some(dedupe),
+        )
+    }
+
+    fn write_events<R: BufRead, W: Write>(
+        &mut self,
+        reader: R,
+        writer: &mut W,
+        report: &mut dyn Write,
+        mut telemetry: Option<&mut TelemetryCollector>,
+        mut dedupe: Option<&mut Deduplicator>,
+    ) -> Result<()> {
+        let mut events = self.documents(reader);
+        while let Some(event) = events.next_event() {
+            match event? {
+                Event::Message {
+                    mut document,
+                    mut tree,
+                } => {
+                    if let Some(dedupe) = dedupe.as_deref_mut() {
+                        if !dedupe.admit(&mut document, tree.as_mut())? {
+                            continue;
                         }
                     }
-
-                    current_doc = Some(StreamingDocument {
-                        interchange_id: interchange_id.clone(),
-                        sender: sender_id.clone(),
-                        receiver: receiver_id.clone(),
-                        ..Default::default()
-                    });
-                }
-                Segment::BGM(code, num) => {
-                    if let Some(doc) = current_doc.as_mut() {
-                        doc.document_number = num.to_string();
-                        doc.doc_type = match code {
-                            "220" => "ORDERS".to_string(),
-                            _ => code.to_string(),
-                        };
+                    match tree {
+                        Some(tree) => serde_json::to_writer(&mut *writer, &tree)?,
+                        None => serde_json::to_writer(&mut *writer, &document)?,
                     }
+                    writer.write_all(b"\n")?;
                 }
-                Segment::DTM(qualifier, date) => {
-                    if let Some(doc) = current_doc.as_mut() {
-                        match qualifier {
-                            "137" => doc.document_date = Some(date.to_string()),
-                            "2" => doc.requested_delivery_date = Some(date.to_string()),
-                            _ => {}
-                        }
-                    }
+                Event::Report(entry) => {
+                    serde_json::to_writer(&mut *report, &entry)?;
+                    report.write_all(b"\n")?;
                 }
-                Segment::NAD("BY", id) => {
-                    if let Some(doc) = current_doc.as_mut() {
-                        doc.buyer = Some(id.to_string());
+                Event::Unmapped(segment) => {
+                    if let Some(telemetry) = telemetry.as_deref_mut() {
+                        telemetry.record(&segment);
                     }
                 }
-                Segment::NAD("SU", id) => {
-                    if let Some(doc) = current_doc.as_mut() {
-                        doc.seller = Some(id.to_string());
-                    }
+                // The message report says why
+                Event::Rejected(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `reader` message by message, yielding each document as its UNT (or the end
+    /// of the input) is reached. Messages rejected by
+    /// [`EdifactProcessor::with_reject_invalid`] are skipped; the validation reports are
+    /// available from [`Documents::take_reports`]. [`Documents::next_event`] gives every
+    /// event instead.
+    pub fn documents<R: BufRead>(&mut self, reader: R) -> Documents<'_, SegmentReader<R>> {
+        let segments = match self.charset {
+            Some(charset) => SegmentReader::with_charset(reader, charset),
+            None => SegmentReader::new(reader),
+        };
+        self.documents_from(segments)
+    }
+
+    /// Like [`EdifactProcessor::documents`], taking segments that are already cut
+    pub(crate) fn documents_from<S: SegmentSource>(&mut self, segments: S) -> Documents<'_, S> {
+        Documents {
+            processor: self,
+            segments,
+            tokenizer: SegmentTokenizer::new(),
+            raw: String::new(),
+            validator: EnvelopeValidator::new(),
+            current_doc: None,
+            current_line: None,
+            interchange_id: String::new(),
+            sender_id: String::new(),
+            receiver_id: String::new(),
+            group: None,
+            message_version: None,
+            structure: StructureTracker::default(),
+            details: DetailsCollector::new(),
+            tree: None,
+            events: VecDeque::new(),
+            message_raw: Vec::new(),
+            violations: Vec::new(),
+            reports: Vec::new(),
+            dead_letters: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Async counterpart of [`EdifactProcessor::documents`]. Parsing runs on a blocking
+    /// task, a few documents ahead of the consumer.
+    #[cfg(feature = "full")]
+    pub fn into_document_stream<R: BufRead + Send + 'static>(
+        mut self,
+        reader: R,
+    ) -> impl futures::Stream<Item = Result<StreamingDocument>> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        tokio::task::spawn_blocking(move || {
+            for document in self.documents(reader) {
+                // The stream was dropped
+                if tx.blocking_send(document).is_err() {
+                    break;
                 }
-                Segment::LIN(line_num, sku) => {
-                    if let Some(line) = current_line.take() {
-                        if let Some(doc) = current_doc.as_mut() {
-                            doc.lines.push(line);
-                        }
-                    }
-                    current_line = Some(StreamingLine {
-                        line_no: line_num.parse().unwrap_or(0),
-                        sku: sku.to_string(),
-                        ..Default::default()
-                    });
+            }
+        });
+        async_stream::stream! {
+            while let Some(document) = rx.recv().await {
+                yield document;
+            }
+        }
+    }
+
+    /// Process EDIFACT data and return as a Vec of bytes (JSONL)
+    pub fn process_to_vec<R: BufRead>(&mut self, reader: R) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        self.process_to_writer(reader, &mut output)?;
+        Ok(output)
+    }
+
+    /// Process EDIFACT string and return JSONL string
+    pub fn process_to_string(&mut self, input: &str) -> Result<String> {
+        let reader = std::io::Cursor::new(input);
+        let bytes = self.process_to_vec(reader)?;
+        String::from_utf8(bytes).map_err(|e| crate::error::FileReduceError::Parse(e.to_string()))
+    }
+}
+
+/// What reading an EDIFACT input produces, in input order. Every front end (writer,
+/// iterator, async sinks, API, WASM) is driven by these.
+pub enum Event {
+    /// A finished message: its document, and its tree in [`OutputMode::Tree`]
+    Message {
+        document: Box<StreamingDocument>,
+        tree: Option<MessageTree>,
+    },
+    /// Envelope validation result of a message or an interchange
+    Report(ValidationReport),
+    /// A segment that neither the parser nor the dictionary knows, or, with
+    /// [`EdifactProcessor::with_telemetry`], a dictionary segment with unmapped elements
+    Unmapped(UnmappedSegment),
+    /// A message that failed, in [`EdifactProcessor::with_tolerant`] runs
+    Rejected(DeadLetter),
+}
+
+/// A message set aside by a tolerant run, written as one JSON line to the dead-letter
+/// output
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub interchange_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_number: Option<String>,
+    /// Errors of the message, separated by `; `
+    pub reason: String,
+    /// Where the first error was found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<SourcePosition>,
+    /// The segments of the message as read, UNH to UNT, with their terminators
+    pub raw: String,
+}
+
+impl DeadLetter {
+    /// Dead letter for the message of `report`, from its error findings
+    fn new(report: &ValidationReport, segments: &[String], delimiters: &Delimiters) -> Self {
+        let errors: Vec<&Finding> = report
+            .findings
+            .iter()
+            .filter(|finding| finding.status == Status::Error)
+            .collect();
+        let mut raw = String::new();
+        for segment in segments {
+            raw.push_str(segment);
+            raw.push(delimiters.segment);
+        }
+        Self {
+            interchange_id: report.interchange_id.clone(),
+            message_reference: report.message_reference.clone(),
+            document_number: report.document_number.clone().filter(|n| !n.is_empty()),
+            reason: errors
+                .iter()
+                .map(|finding| finding.reason.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+            position: errors.first().and_then(|finding| finding.position),
+            raw,
+        }
+    }
+}
+
+/// Messages written and set aside by a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// Messages written to the sink
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Messages that did not match the query
+    #[serde(default)]
+    pub filtered: u64,
+    /// Messages whose key was seen before, see [`Deduplicator`]; they count as
+    /// accepted too when they are flagged or replaced rather than skipped
+    #[serde(default)]
+    pub duplicates: u64,
+}
+
+impl RunSummary {
+    pub fn record(&mut self, event: &Event) {
+        match event {
+            Event::Message { .. } => self.accepted += 1,
+            Event::Rejected(_) => self.rejected += 1,
+            _ => {}
+        }
+    }
+}
+
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} messages accepted, {} rejected",
+            self.accepted, self.rejected
+        )?;
+        if self.filtered > 0 {
+            write!(f, ", {} filtered", self.filtered)?;
+        }
+        if self.duplicates > 0 {
+            write!(f, ", {} duplicates", self.duplicates)?;
+        }
+        Ok(())
+    }
+}
+
+/// Documents of an EDIFACT input, see [`EdifactProcessor::documents`]
+pub struct Documents<'p, S: SegmentSource> {
+    processor: &'p mut EdifactProcessor,
+    segments: S,
+    tokenizer: SegmentTokenizer,
+    raw: String,
+    validator: EnvelopeValidator,
+    current_doc: Option<StreamingDocument>,
+    current_line: Option<StreamingLine>,
+    interchange_id: String,
+    sender_id: String,
+    receiver_id: String,
+    group: Option<FunctionalGroup>,
+    /// Directory declared by the UNH of the open message
+    message_version: Option<String>,
+    structure: StructureTracker,
+    details: DetailsCollector,
+    tree: Option<TreeBuilder>,
+    events: VecDeque<Event>,
+    /// Segments of the open message, kept in tolerant runs for the dead letter
+    message_raw: Vec<String>,
+    /// Strict policy breaches of the open message, with their positions
+    violations: Vec<String>,
+    /// Reports and dead letters met while iterating documents, until taken
+    reports: Vec<ValidationReport>,
+    dead_letters: Vec<DeadLetter>,
+    done: bool,
+}
+
+impl<S: SegmentSource> Documents<'_, S> {
+    /// Validation reports of the messages and interchanges whose documents were
+    /// iterated so far
+    pub fn take_reports(&mut self) -> Vec<ValidationReport> {
+        std::mem::take(&mut self.reports)
+    }
+
+    /// Messages rejected so far by a tolerant run
+    pub fn take_dead_letters(&mut self) -> Vec<DeadLetter> {
+        std::mem::take(&mut self.dead_letters)
+    }
+
+    /// Next event of the input, reading segments until there is one
+    pub fn next_event(&mut self) -> Option<Result<Event>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.done {
+                return None;
+            }
+            let applied = match self.segments.read_segment(&mut self.raw) {
+                Ok(true) => self.segment(),
+                Ok(false) => self.finish(),
+                Err(FileReduceError::Io(e)) => Err(FileReduceError::Io(e)),
+                Err(e) if self.processor.tolerant => {
+                    self.unreadable(e);
+                    Ok(())
                 }
-                Segment::QTY(_, qty, unit) => {
-                    if let Some(line) = current_line.as_mut() {
-                        line.qty = qty.parse().ok();
-                        line.uom = if !unit.is_empty() {
-                            Some(unit.to_string())
-                        } else {
-                            None
-                        };
-                    }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = applied {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// A segment that could not be read fails its message; outside a message it is
+    /// set aside on its own
+    fn unreadable(&mut self, error: FileReduceError) {
+        let position = self.segments.position();
+        self.validator.segment(position);
+        if self.current_doc.is_some() {
+            self.message_raw.push(std::mem::take(&mut self.raw));
+            self.validator
+                .message_finding(Status::Error, error.reason());
+            return;
+        }
+        let mut raw = std::mem::take(&mut self.raw);
+        raw.push(self.segments.delimiters().segment);
+        self.events.push_back(Event::Rejected(DeadLetter {
+            interchange_id: self.interchange_id.clone(),
+            reason: error.reason(),
+            position: Some(position),
+            raw,
+            ..Default::default()
+        }));
+    }
+
+    /// Flushes the line and document left open at the end of the input
+    fn finish(&mut self) -> Result<()> {
+        self.done = true;
+        if let Some(line) = self.current_line.take() {
+            if let Some(doc) = self.current_doc.as_mut() {
+                doc.lines.push(line);
+            }
+        }
+        let status = self.validator.finish(self.current_doc.as_ref());
+        let reports = self.validator.take_reports();
+        strict_violation(&reports, &mut self.violations, self.processor.tolerant)?;
+        let rejected = failed_message(&reports)
+            .filter(|_| self.processor.tolerant)
+            .map(|report| DeadLetter::new(report, &self.message_raw, self.segments.delimiters()));
+        self.events.extend(reports.into_iter().map(Event::Report));
+        if let Some(document) = self.current_doc.take() {
+            if let Some(letter) = rejected {
+                self.events.push_back(Event::Rejected(letter));
+            } else if !(self.processor.reject_invalid && status == Status::Error) {
+                self.events.push_back(Event::Message {
+                    document: Box::new(document),
+                    tree: self.tree.take().map(TreeBuilder::finish),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the segment in `raw`, queueing the events it produces
+    fn segment(&mut self) -> Result<()> {
+        let Self {
+            processor,
+            segments,
+            tokenizer,
+            raw,
+            validator,
+            current_doc,
+            current_line,
+            interchange_id,
+            sender_id,
+            receiver_id,
+            group,
+            message_version,
+            structure,
+            details,
+            tree,
+            events,
+            message_raw,
+            violations,
+            ..
+        } = self;
+        if raw.trim().is_empty() {
+            return Ok(());
+        }
+        let delimiters = *segments.delimiters();
+        let position = segments.position();
+        let view = tokenizer.tokenize(raw.as_bytes(), &delimiters);
+        let mut finished = None;
+        let tag = view.tag();
+        // UNH is kept once the message it may close has been dealt with
+        if processor.tolerant && tag != "UNH" {
+            message_raw.push(raw.clone());
+        }
+        let segment = parse_segment_view(&view, processor.registry.as_ref());
+        validator.segment(position);
+        let placement = structure.place(&tag);
+        enter_placement(&placement, current_doc, current_line);
+        if processor.output_mode == OutputMode::Tree {
+            if tag == "UNH" {
+                *tree = Some(TreeBuilder::new(MessageTree {
+                    interchange_id: interchange_id.clone(),
+                    sender: sender_id.clone(),
+                    receiver: receiver_id.clone(),
+                    group: group.clone(),
+                    message_reference: view.value(1, 0).into_owned(),
+                    message_type: view.value(2, 0).into_owned(),
+                    content: Vec::new(),
+                    duplicate: None,
+                }));
+            }
+            if let Some(builder) = tree.as_mut() {
+                let mut node = tree_segment(&view, &segment, processor.registry.as_ref());
+                if processor.source_positions {
+                    node.source = Some(position);
                 }
-                Segment::MOA(_, amt) => {
-                    if let Some(line) = current_line.as_mut() {
-                        line.amount = amt.parse().ok();
-                    }
+                builder.push(&structure.open_groups(), structure.opened(), node);
+            }
+        }
+
+        let strictness = processor.strictness.for_sender(sender_id);
+        match segment {
+            Segment::UNB(s, r, id) => {
+                validator.begin_interchange(&id);
+                *sender_id = s.to_string();
+                *receiver_id = r.to_string();
+                *interchange_id = id.to_string();
+                *group = None;
+            }
+            Segment::UNG(message_type, app_sender, app_receiver, reference) => {
+                validator.begin_group(&reference);
+                *group = Some(FunctionalGroup {
+                    reference: reference.to_string(),
+                    message_type: message_type.to_string(),
+                    application_sender: app_sender.to_string(),
+                    application_receiver: app_receiver.to_string(),
+                    date: view.value(4, 0).into_owned(),
+                    time: view.value(4, 1).into_owned(),
+                });
+            }
+            Segment::UNH(reference, message_type) => {
+                validator.begin_message(&reference, current_doc.as_ref());
+                for reason in service_missing_mandatory(&view, processor, strictness) {
+                    violation(strictness, reason, position, validator, violations);
                 }
-                Segment::CNT(code, val) => {
-                    if code == "2" {
-                        if let Some(doc) = current_doc.as_mut() {
-                            doc.line_count_check = val.parse().ok();
-                        }
+                // Extract version from UNH segment and load appropriate translation registry
+                *message_version = extract_version_from_unh_with(raw, &delimiters);
+                if let Some(detected_version) = message_version.as_ref() {
+                    if processor.version.as_ref() != Some(detected_version) {
+                        // Try to load version-specific registry
+                        let _ = processor.load_version_registry(detected_version);
                     }
                 }
-                Segment::CUX(curr) => {
+                *structure = StructureTracker::new(
+                    processor
+                        .registry
+                        .as_ref()
+                        .and_then(|r| r.get_message_structure(&message_type)),
+                );
+
+                *current_doc = Some(StreamingDocument {
+                    interchange_id: interchange_id.clone(),
+                    sender: sender_id.clone(),
+                    receiver: receiver_id.clone(),
+                    group: group.clone(),
+                    message_type: message_type.to_string(),
+                    details: MessageDetails::for_message_type(&message_type),
+                    ..Default::default()
+                });
+            }
+            Segment::LIN(line_num, sku) => {
+                if let Some(line) = current_line.take() {
                     if let Some(doc) = current_doc.as_mut() {
-                        doc.currency = curr.to_string();
+                        doc.lines.push(line);
                     }
                 }
-                Segment::UNT => {
-                    if let Some(line) = current_line.take() {
-                        if let Some(doc) = current_doc.as_mut() {
-                            doc.lines.push(line);
-                        }
+                *current_line = Some(StreamingLine {
+                    line_no: line_num.parse().unwrap_or(0),
+                    sku: sku.to_string(),
+                    ..Default::default()
+                });
+            }
+            Segment::QTY(qualifier, qty, unit) if placement.in_line_group() => {
+                if let (Some(line), Ok(value)) = (
+                    current_line.as_mut(),
+                    delimiters.normalize_decimal(&qty).parse(),
+                ) {
+                    let quantity = Quantity {
+                        value,
+                        unit: Some(unit.to_string()).filter(|u| !u.is_empty()),
+                    };
+                    line.add_quantity(&qualifier, quantity, &structure.line_primary().quantity);
+                }
+            }
+            Segment::MOA(qualifier, amt) if placement.in_line_group() => {
+                if let (Some(line), Ok(amount)) = (
+                    current_line.as_mut(),
+                    delimiters.normalize_decimal(&amt).parse(),
+                ) {
+                    line.add_amount(&qualifier, amount, &structure.line_primary().amount);
+                }
+            }
+            Segment::UNT(count, reference) => {
+                for reason in service_missing_mandatory(&view, processor, strictness) {
+                    violation(strictness, reason, position, validator, violations);
+                }
+                if let Some(line) = current_line.take() {
+                    if let Some(doc) = current_doc.as_mut() {
+                        doc.lines.push(line);
                     }
+                }
 
-                    if let Some(doc) = current_doc.take() {
-                        serde_json::to_writer(&mut *writer, &doc)?;
-                        writer.write_all(b"\n")?;
+                let status =
+                    validator.end_message(Some((&count, &reference)), current_doc.as_ref());
+                let message_tree = tree.take();
+                if let Some(doc) = current_doc.take() {
+                    if !(processor.reject_invalid && status == Status::Error) {
+                        finished = Some(Event::Message {
+                            document: Box::new(doc),
+                            tree: message_tree.map(TreeBuilder::finish),
+                        });
                     }
                 }
-                Segment::UNZ => {}
-                Segment::Dynamic {
-                    code,
-                    qualifier,
-                    elements: element_groups,
-                } => {
-                    if let Some(reg) = &self.registry {
-                        apply_dynamic_segment(
-                            code,
-                            qualifier,
-                            &element_groups,
-                            reg,
-                            &mut current_doc,
-                            &mut current_line,
-                        );
+            }
+            Segment::UNE(count, reference) => {
+                validator.end_group(&count, &reference);
+                *group = None;
+            }
+            Segment::UNZ(count, reference) => {
+                validator.end_interchange(&count, &reference);
+                // Next interchange in the same file starts from a clean envelope
+                interchange_id.clear();
+                sender_id.clear();
+                receiver_id.clear();
+                *group = None;
+            }
+            Segment::Dynamic {
+                code,
+                qualifier,
+                elements,
+            } => {
+                if let Some(reg) = &processor.registry {
+                    let failures = apply_dynamic_segment(
+                        &code,
+                        qualifier.as_deref(),
+                        &elements,
+                        reg,
+                        &delimiters,
+                        &placement,
+                        structure.line_primary(),
+                        current_doc,
+                        current_line,
+                    );
+                    for reason in failures {
+                        validator.message_finding(Status::Warn, reason);
+                    }
+                    if strictness != Strictness::Lenient && current_doc.is_some() {
+                        for reason in missing_mandatory(&code, qualifier.as_deref(), &elements, reg)
+                        {
+                            violation(strictness, reason, position, validator, violations);
+                        }
+                    }
+                    if processor.telemetry && current_doc.is_some() {
+                        let unmapped =
+                            unmapped_elements(&code, qualifier.as_deref(), &elements, reg);
+                        if !unmapped.is_empty() {
+                            events.push_back(Event::Unmapped(UnmappedSegment {
+                                unknown: false,
+                                elements: unmapped,
+                                ..unmapped_segment(
+                                    &code,
+                                    raw,
+                                    position,
+                                    message_version,
+                                    current_doc,
+                                    sender_id,
+                                )
+                            }));
+                        }
                     }
                 }
-                Segment::Unknown(code) => {
-                    // Log unknown segments (could be captured for telemetry)
-                    eprintln!("Unknown segment encountered: {}", code);
+            }
+            // UNS is a service segment, outside any dictionary
+            Segment::Unknown(code) if code == "UNS" => {}
+            Segment::Unknown(code) => {
+                if strictness != Strictness::Lenient && current_doc.is_some() {
+                    let reason = format!("segment {} is not in the dictionary", code);
+                    violation(strictness, reason, position, validator, violations);
                 }
-                _ => {}
+                events.push_back(Event::Unmapped(unmapped_segment(
+                    &code,
+                    raw,
+                    position,
+                    message_version,
+                    current_doc,
+                    sender_id,
+                )))
+            }
+            _ => {}
+        }
+
+        for reason in details.segment(&view, &placement, &delimiters, current_doc.as_mut()) {
+            validator.message_finding(Status::Warn, reason);
+        }
+
+        if processor.source_positions {
+            attach_source(&tag, position, current_doc, current_line);
+        }
+
+        // The report of a message comes before the message
+        let reports = validator.take_reports();
+        strict_violation(&reports, violations, processor.tolerant)?;
+        if processor.tolerant {
+            if let Some(report) = failed_message(&reports) {
+                finished = Some(Event::Rejected(DeadLetter::new(
+                    report,
+                    message_raw,
+                    &delimiters,
+                )));
+            }
+            if tag == "UNH" || tag == "UNT" {
+                message_raw.clear();
+            }
+            if tag == "UNH" {
+                message_raw.push(raw.clone());
+            }
+        }
+        events.extend(reports.into_iter().map(Event::Report));
+        events.extend(finished);
+        Ok(())
+    }
+}
+
+/// Reports a breach of the strictness policy on the open message: a warning under
+/// [`Strictness::Warn`], an error that is also kept for the run error under
+/// [`Strictness::Strict`]
+fn violation(
+    strictness: Strictness,
+    reason: String,
+    position: SourcePosition,
+    validator: &mut EnvelopeValidator,
+    violations: &mut Vec<String>,
+) {
+    match strictness {
+        Strictness::Lenient => {}
+        Strictness::Warn => validator.message_finding(Status::Warn, reason),
+        Strictness::Strict => {
+            violations.push(format!("{} at {}", reason, position));
+            validator.message_finding(Status::Error, reason);
+        }
+    }
+}
+
+/// Fails the run when the message reported among `reports` broke the strict policy.
+/// Tolerant runs reject the message instead, as its report ends in an error.
+fn strict_violation(
+    reports: &[ValidationReport],
+    violations: &mut Vec<String>,
+    tolerant: bool,
+) -> Result<()> {
+    let Some(reference) = reports
+        .iter()
+        .find_map(|report| report.message_reference.as_ref())
+    else {
+        return Ok(());
+    };
+    let violations = std::mem::take(violations);
+    if violations.is_empty() || tolerant {
+        return Ok(());
+    }
+    Err(FileReduceError::StrictViolation {
+        message_reference: reference.clone(),
+        violations,
+    })
+}
+
+/// Report of the message among `reports` whose validation ended in an error
+fn failed_message(reports: &[ValidationReport]) -> Option<&ValidationReport> {
+    reports
+        .iter()
+        .find(|report| report.message_reference.is_some() && report.status == Status::Error)
+}
+
+impl<S: SegmentSource> Iterator for Documents<'_, S> {
+    type Item = Result<StreamingDocument>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_event()? {
+                Ok(Event::Message { document, .. }) => return Some(Ok(*document)),
+                Ok(Event::Report(report)) => self.reports.push(report),
+                Ok(Event::Unmapped(_)) => {}
+                Ok(Event::Rejected(letter)) => self.dead_letters.push(letter),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Writes a bound value into its field. Document fields only take values from segments
+/// at document level.
+fn bind(
+    binding: &FieldBinding,
+    value: String,
+    placement: &Placement,
+    current_doc: &mut Option<StreamingDocument>,
+    current_line: &mut Option<StreamingLine>,
+) {
+    let if_empty = binding.if_empty;
+    let set = |field: &mut String, value: String| {
+        if !if_empty || field.is_empty() {
+            *field = value;
+        }
+    };
+    let set_option = |field: &mut Option<String>, value: String| {
+        if !if_empty || field.is_none() {
+            *field = Some(value);
+        }
+    };
+    let doc = current_doc.as_mut().filter(|_| placement.in_document());
+    match (binding.target, doc, current_line.as_mut()) {
+        (BindingTarget::DocumentNumber, Some(doc), _) => set(&mut doc.document_number, value),
+        (BindingTarget::DocType, Some(doc), _) => set(&mut doc.doc_type, value),
+        (BindingTarget::DocumentDate, Some(doc), _) => set_option(&mut doc.document_date, value),
+        (BindingTarget::RequestedDeliveryDate, Some(doc), _) => {
+            set_option(&mut doc.requested_delivery_date, value)
+        }
+        (BindingTarget::Buyer, Some(doc), _) => set_option(&mut doc.buyer, value),
+        (BindingTarget::Seller, Some(doc), _) => set_option(&mut doc.seller, value),
+        (BindingTarget::Currency, Some(doc), _) => set(&mut doc.currency, value),
+        (BindingTarget::LineCountCheck, Some(doc), _)
+            if !if_empty || doc.line_count_check.is_none() =>
+        {
+            doc.line_count_check = value.parse().ok().or(doc.line_count_check);
+        }
+        (BindingTarget::LineNo, _, Some(line)) => {
+            line.line_no = value.parse().unwrap_or(line.line_no);
+        }
+        (BindingTarget::Sku, _, Some(line)) => set(&mut line.sku, value),
+        _ => {}
+    }
+}
+
+/// Records where the current document (UNH) or line (LIN) starts in the input
+fn attach_source(
+    tag: &str,
+    position: SourcePosition,
+    current_doc: &mut Option<StreamingDocument>,
+    current_line: &mut Option<StreamingLine>,
+) {
+    match tag {
+        "UNH" => {
+            if let Some(doc) = current_doc.as_mut() {
+                doc.source = Some(position);
             }
         }
+        "LIN" => {
+            if let Some(line) = current_line.as_mut() {
+                line.source = Some(position);
+            }
+        }
+        _ => {}
+    }
+}
 
-        // Flush any remaining line and document at end of file
+/// Structural effect of a segment, applied before its fields are mapped: leaving the
+/// line item group closes the current line, and a group trigger starts a new
+/// occurrence of its group
+fn enter_placement(
+    placement: &Placement,
+    current_doc: &mut Option<StreamingDocument>,
+    current_line: &mut Option<StreamingLine>,
+) {
+    if !placement.in_line() {
         if let Some(line) = current_line.take() {
             if let Some(doc) = current_doc.as_mut() {
                 doc.lines.push(line);
             }
         }
-        if let Some(doc) = current_doc.take() {
-            serde_json::to_writer(&mut *writer, &doc)?;
-            writer.write_all(b"\n")?;
+    }
+    if let Placement::Group {
+        depth,
+        in_line,
+        opens: Some(name),
+    } = placement
+    {
+        if let Some(groups) = open_groups(depth - 1, *in_line, current_doc, current_line) {
+            groups.push(SegmentGroup {
+                group: name.clone(),
+                ..Default::default()
+            });
         }
+    }
+}
 
-        Ok(())
+/// Group occurrences `levels` below the owner of a segment (the line or the document)
+fn open_groups<'a>(
+    levels: usize,
+    in_line: bool,
+    current_doc: &'a mut Option<StreamingDocument>,
+    current_line: &'a mut Option<StreamingLine>,
+) -> Option<&'a mut Vec<SegmentGroup>> {
+    let mut groups = if in_line {
+        &mut current_line.as_mut()?.groups
+    } else {
+        &mut current_doc.as_mut()?.groups
+    };
+    for _ in 0..levels {
+        groups = &mut groups.last_mut()?.groups;
     }
+    Some(groups)
+}
 
-    /// Process EDIFACT data and return as a Vec of bytes (JSONL)
-    pub fn process_to_vec<R: BufRead>(&mut self, reader: R) -> Result<Vec<u8>> {
-        let mut output = Vec::new();
-        self.process_to_writer(reader, &mut output)?;
-        Ok(output)
+/// Dictionary entry of a segment, with the element layout of its qualifier when the
+/// segment is keyed by qualifier
+fn segment_config(
+    segment_code: &str,
+    qualifier: Option<&str>,
+    registry: &TranslationRegistry,
+) -> Option<SegmentConfig> {
+    let mut segment_config = registry.get_segment(segment_code)?;
+    let qualified = qualifier
+        .filter(|_| segment_config.use_qualifier)
+        .and_then(|q| segment_config.qualifiers.remove(q));
+    if let Some(sub) = qualified {
+        segment_config.elements = sub.elements;
     }
+    Some(segment_config)
+}
 
-    /// Process EDIFACT string and return JSONL string
-    pub fn process_to_string(&mut self, input: &str) -> Result<String> {
-        let reader = std::io::Cursor::new(input);
-        let bytes = self.process_to_vec(reader)?;
-        String::from_utf8(bytes).map_err(|e| crate::error::FileReduceError::Parse(e.to_string()))
+/// Positions of the elements of a dictionary segment that carry a value but have no
+/// entry in the dictionary
+fn unmapped_elements(
+    segment_code: &str,
+    qualifier: Option<&str>,
+    elements: &DynamicElements<'_>,
+    registry: &TranslationRegistry,
+) -> Vec<usize> {
+    let Some(SegmentConfig {
+        elements: elements_config,
+        ..
+    }) = segment_config(segment_code, qualifier, registry)
+    else {
+        return Vec::new();
+    };
+    (1..=elements.len())
+        .filter(|&pos| elements.is_filled(pos))
+        .filter(|pos| !elements_config.contains_key(&pos.to_string()))
+        .collect()
+}
+
+/// Telemetry of an unknown segment of the open message
+fn unmapped_segment(
+    segment_code: &str,
+    raw: &str,
+    position: SourcePosition,
+    message_version: &Option<String>,
+    current_doc: &Option<StreamingDocument>,
+    sender: &str,
+) -> UnmappedSegment {
+    UnmappedSegment {
+        version: message_version.clone(),
+        message_type: current_doc
+            .as_ref()
+            .map(|doc| doc.message_type.clone())
+            .unwrap_or_default(),
+        sender: sender.to_string(),
+        segment: segment_code.to_string(),
+        unknown: true,
+        elements: Vec::new(),
+        raw: raw.trim().to_string(),
+        position,
+    }
+}
+
+/// Mandatory elements, and mandatory components of present composites, that a
+/// dictionary segment leaves empty
+fn missing_mandatory(
+    segment_code: &str,
+    qualifier: Option<&str>,
+    elements: &DynamicElements<'_>,
+    registry: &TranslationRegistry,
+) -> Vec<String> {
+    let Some(SegmentConfig {
+        elements: elements_config,
+        ..
+    }) = segment_config(segment_code, qualifier, registry)
+    else {
+        return Vec::new();
+    };
+    let mut missing = Vec::new();
+    for (pos_str, config) in &elements_config {
+        let pos_num: usize = pos_str.parse().unwrap_or(0);
+        if pos_num == 0 {
+            continue;
+        }
+        if !elements.is_filled(pos_num) {
+            if config.mandatory() {
+                missing.push(format!(
+                    "{} mandatory element {} ({}) is missing",
+                    segment_code,
+                    pos_num,
+                    config.label()
+                ));
+            }
+            continue;
+        }
+        if let ElementConfig::Composite { components, .. } = config {
+            for (sub_pos_str, sub_config) in components {
+                let sub_pos: usize = sub_pos_str.parse().unwrap_or(0);
+                if sub_pos > 0
+                    && sub_config.mandatory()
+                    && elements
+                        .component(pos_num, sub_pos - 1)
+                        .is_none_or(|v| v.is_empty())
+                {
+                    missing.push(format!(
+                        "{} mandatory component {}.{} ({}) is missing",
+                        segment_code,
+                        pos_num,
+                        sub_pos,
+                        sub_config.label()
+                    ));
+                }
+            }
+        }
     }
+    missing
 }
 
-pub(crate) fn apply_dynamic_segment(
+/// Mandatory elements that a service segment (UNH, UNT) leaves empty, when the
+/// dictionary describes the segment and the policy checks them
+fn service_missing_mandatory(
+    view: &SegmentView<'_, '_>,
+    processor: &EdifactProcessor,
+    strictness: Strictness,
+) -> Vec<String> {
+    match &processor.registry {
+        Some(registry) if strictness != Strictness::Lenient => missing_mandatory(
+            &view.tag(),
+            None,
+            &DynamicElements::new(*view, false),
+            registry,
+        ),
+        _ => Vec::new(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn apply_dynamic_segment(
     segment_code: &str,
     qualifier: Option<&str>,
-    element_groups: &[Vec<&str>],
+    elements: &DynamicElements<'_>,
     registry: &TranslationRegistry,
+    delimiters: &Delimiters,
+    placement: &Placement,
+    primary: &LinePrimary,
     current_doc: &mut Option<StreamingDocument>,
     current_line: &mut Option<StreamingLine>,
-) {
-    // Determine if segment affects document or line
-    // Line-level segments: LIN (line item), QTY (quantity), MOA (monetary amount), PRI (price)
-    // Document-level segments: BGM, DTM, NAD, CNT, CUX, etc.
-    let is_line_segment = matches!(segment_code, "LIN" | "QTY" | "MOA" | "PRI");
-
-    let Some(segment_config) = registry.get_segment(segment_code) else {
-        return;
+) -> Vec<String> {
+    let Some(SegmentConfig {
+        elements: elements_config,
+        bindings,
+        ..
+    }) = segment_config(segment_code, qualifier, registry)
+    else {
+        return Vec::new();
     };
 
     let mut field_values = HashMap::new();
+    // Element and component each label was read from, for the bindings
+    let mut field_positions = HashMap::new();
 
-    // Get appropriate elements configuration based on qualifier
-    let elements_config = if segment_config.use_qualifier {
-        if let Some(q) = qualifier {
-            // Try to get qualifier-specific elements
-            registry
-                .get_qualifier(segment_code, q)
-                .map(|sub| sub.elements)
-                .unwrap_or_else(|| segment_config.elements.clone())
-        } else {
-            segment_config.elements.clone()
-        }
-    } else {
-        segment_config.elements.clone()
+    // Raw values feed the bindings and line values below; the extras get them
+    // converted to the declared data type, keeping the raw text when that fails
+    let mut typed_values = HashMap::new();
+    let mut failures = Vec::new();
+    let mut put = |label: &str, at: (usize, usize), raw: &str, typed: Option<&TypedField>| {
+        field_values.insert(label.to_string(), raw.to_string());
+        field_positions.insert(label.to_string(), at);
+        let value = match typed.filter(|_| !raw.is_empty()) {
+            Some(field) => convert_value(raw, field.data_type, field.format, delimiters)
+                .unwrap_or_else(|reason| {
+                    failures.push(format!("{} {} '{}' {}", segment_code, label, raw, reason));
+                    Value::from(raw)
+                }),
+            None => Value::from(raw),
+        };
+        typed_values.insert(label.to_string(), value);
     };
 
-    // Build map from position to component group
-    let mut pos_to_group = std::collections::HashMap::new();
-    for (idx, group) in element_groups.iter().enumerate() {
-        let pos = idx + 1;
-        pos_to_group.insert(pos, group);
-    }
-
     for (pos_str, config) in elements_config.iter() {
         let pos_num: usize = pos_str.parse().unwrap_or(0);
         if pos_num == 0 {
             continue;
         }
-        let Some(components) = pos_to_group.get(&pos_num) else {
+        if pos_num > elements.len() {
             continue;
-        };
+        }
         match config {
-            ElementConfig::Simple(label) => {
+            ElementConfig::Simple(_) | ElementConfig::Typed(_) => {
                 // Take first component
-                if let Some(&value) = components.first() {
-                    field_values.insert(label.clone(), value.to_string());
+                if let Some(value) = elements.component(pos_num, 0) {
+                    put(config.label(), (pos_num, 1), &value, config.typed());
                 }
             }
             ElementConfig::Composite {
                 label: comp_label,
                 components: comp_map,
+                ..
             } => {
                 // Also map the composite label to the first component (typical usage)
-                if let Some(&first_value) = components.first() {
-                    field_values.insert(comp_label.clone(), first_value.to_string());
+                if let Some(first_value) = elements.component(pos_num, 0) {
+                    let typed = comp_map.get("1").and_then(ComponentConfig::typed);
+                    put(comp_label, (pos_num, 1), &first_value, typed);
                 }
                 // Map each subcomponent according to comp_map
-                for (sub_pos_str, sub_label) in comp_map.iter() {
+                for (sub_pos_str, sub_config) in comp_map.iter() {
                     let sub_pos: usize = sub_pos_str.parse().unwrap_or(0);
-                    if sub_pos == 0 || sub_pos > components.len() {
+                    let Some(value) = sub_pos
+                        .checked_sub(1)
+                        .and_then(|idx| elements.component(pos_num, idx))
+                    else {
                         continue;
-                    }
-                    let value = components[sub_pos - 1];
-                    field_values.insert(sub_label.clone(), value.to_string());
+                    };
+                    put(
+                        sub_config.label(),
+                        (pos_num, sub_pos),
+                        &value,
+                        sub_config.typed(),
+                    );
                 }
             }
         }
     }
 
-    // Special handling for known segment types to populate fixed fields
+    let number = |label: &str| -> Option<f64> {
+        field_values
+            .get(label)
+            .and_then(|v| delimiters.normalize_decimal(v).parse().ok())
+    };
+    // Components of the first element, for values the dictionary does not label
+    let composite = |idx: usize| {
+        elements
+            .component(1, idx)
+            .filter(|v| !v.is_empty())
+            .map(Cow::into_owned)
+    };
+    let decimal = |value: String| delimiters.normalize_decimal(&value).parse::<f64>().ok();
+
+    // Line items and the qualifier-keyed line values are handled here; plain fields
+    // come from the bindings of the dictionary below
     match segment_code {
-        "BGM" => {
-            if let Some(doc) = current_doc.as_mut() {
-                if let Some(num) = field_values.get("DocumentNumber") {
-                    doc.document_number = num.clone();
-                }
-                if let Some(msg) = field_values.get("MessageName") {
-                    doc.doc_type = match msg.as_str() {
-                        "220" => "ORDERS".to_string(),
-                        _ => msg.clone(),
-                    };
-                }
-            }
-        }
-        "DTM" => {
-            if let Some(doc) = current_doc.as_mut() {
-                if let Some(qual) = qualifier {
-                    match qual {
-                        "137" => {
-                            if let Some(date) = field_values.get("Value") {
-                                doc.document_date = Some(date.clone());
-                            }
-                        }
-                        "2" => {
-                            if let Some(date) = field_values.get("Value") {
-                                doc.requested_delivery_date = Some(date.clone());
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
-        "NAD" => {
-            if let Some(doc) = current_doc.as_mut() {
-                if let Some(qual) = qualifier {
-                    match qual {
-                        "BY" => {
-                            if let Some(id) = field_values.get("PartyId") {
-                                doc.buyer = Some(id.clone());
-                            }
-                        }
-                        "SU" => {
-                            if let Some(id) = field_values.get("PartyId") {
-                                doc.seller = Some(id.clone());
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
         "LIN" => {
             // Push previous line to document and start a new line
             if let Some(prev_line) = current_line.take() {
@@ -363,77 +1276,156 @@ pub(crate) fn apply_dynamic_segment(
                     doc.lines.push(prev_line);
                 }
             }
-            let mut new_line = StreamingLine::default();
-            if let Some(line_no) = field_values.get("LineNumber") {
-                new_line.line_no = line_no.parse().unwrap_or(0);
-            }
-            if let Some(sku) = field_values.get("ProductId") {
-                new_line.sku = sku.clone();
-            }
-            *current_line = Some(new_line);
+            *current_line = Some(StreamingLine::default());
         }
-        "QTY" => {
-            if let Some(line) = current_line.as_mut() {
-                if let Some(qty) = field_values.get("Value") {
-                    line.qty = qty.parse().ok();
-                }
-                if let Some(uom) = field_values.get("Format") {
-                    line.uom = Some(uom.clone());
-                }
+        "QTY" if placement.in_line_group() => {
+            // Dictionaries that keep the qualifier in the composite (`QTY+21:10:PCE`
+            // read as one element) are read by position
+            let (qualifier, qty, unit) = match qualifier {
+                Some(qualifier) => (
+                    qualifier.to_string(),
+                    number("Value"),
+                    field_values.get("Format").cloned(),
+                ),
+                None => (
+                    composite(0).unwrap_or_default(),
+                    composite(1).and_then(decimal),
+                    composite(2),
+                ),
+            };
+            if let (Some(line), Some(qty)) = (current_line.as_mut(), qty) {
+                let quantity = Quantity {
+                    value: qty,
+                    unit: unit.filter(|u| !u.is_empty()),
+                };
+                line.add_quantity(&qualifier, quantity, &primary.quantity);
             }
         }
-        "MOA" => {
-            if let Some(line) = current_line.as_mut() {
-                if let Some(amt) = field_values.get("Value") {
-                    line.amount = amt.parse().ok();
-                }
+        "MOA" if placement.in_line_group() => {
+            let (qualifier, amount) = match qualifier {
+                Some(qualifier) => (qualifier.to_string(), number("Value")),
+                None => (
+                    composite(0).unwrap_or_default(),
+                    composite(1).and_then(decimal),
+                ),
+            };
+            if let (Some(line), Some(amount)) = (current_line.as_mut(), amount) {
+                line.add_amount(&qualifier, amount, &primary.amount);
             }
         }
-        "PRI" => {
-            if let Some(line) = current_line.as_mut() {
-                if let Some(price) = field_values.get("Value") {
-                    line.amount = price.parse().ok();
-                }
+        "PRI" if placement.in_line() => {
+            // Amount, price type, specification, unit price basis and unit, read by
+            // position for price qualifiers the dictionary does not describe
+            let value = number("Value").or_else(|| composite(0).and_then(decimal));
+            if let (Some(line), Some(value)) = (current_line.as_mut(), value) {
+                let price = Price {
+                    value,
+                    price_type: composite(1),
+                    basis: composite(3).and_then(decimal),
+                    unit: composite(4),
+                };
+                line.add_price(qualifier.unwrap_or_default(), price, &primary.price);
             }
         }
-        "CNT" => {
-            if let Some(doc) = current_doc.as_mut() {
-                if let Some(code) = qualifier {
-                    if code == "2" {
-                        if let Some(val) = field_values.get("Value") {
-                            doc.line_count_check = val.parse().ok();
-                        }
-                    }
-                }
-            }
+        _ => {}
+    }
+
+    for binding in &bindings {
+        if binding
+            .qualifier
+            .as_deref()
+            .is_some_and(|q| Some(q) != qualifier)
+        {
+            continue;
         }
-        "CUX" => {
-            if let Some(doc) = current_doc.as_mut() {
-                if let Some(curr) = field_values.get("Currency") {
-                    doc.currency = curr.clone();
-                }
+        let at = match (&binding.from, binding.element) {
+            (Some(label), _) => field_positions.get(label.as_str()).copied(),
+            (None, Some(element)) => Some((element, binding.component.unwrap_or(1))),
+            (None, None) => None,
+        };
+        let Some((element, component)) =
+            at.and_then(|(element, component)| Some((element, component.checked_sub(1)?)))
+        else {
+            continue;
+        };
+        let Some(raw) = elements
+            .component(element, component)
+            .filter(|v| !v.is_empty())
+        else {
+            continue;
+        };
+        let value = match &binding.transform {
+            None => raw.to_string(),
+            Some(BindingTransform::Map(codes)) => codes
+                .get(raw.as_ref())
+                .cloned()
+                .unwrap_or_else(|| raw.to_string()),
+            Some(BindingTransform::Date) => {
+                let format = elements
+                    .component(element, component + 1)
+                    .unwrap_or_default();
+                normalize_or_raw(&raw, &format)
             }
+        };
+        bind(binding, value, placement, current_doc, current_line);
+    }
+
+    // Fields of document-level groups (a NAD with its RFF/CTA, ...) are also kept in the
+    // document extras, and LIN, QTY, MOA and PRI of line-level groups in the line extras,
+    // where they were before segment groups; a later occurrence overwrites them there
+    let legacy = match placement {
+        Placement::Group { in_line: false, .. } => current_doc
+            .as_mut()
+            .map(|doc| (&mut doc.extra, &mut doc.typed_extra)),
+        Placement::Group { in_line: true, .. }
+            if matches!(segment_code, "LIN" | "QTY" | "MOA" | "PRI") =>
+        {
+            current_line
+                .as_mut()
+                .map(|line| (&mut line.extra, &mut line.typed_extra))
         }
-        _ => {}
+        _ => None,
+    };
+    if let Some((extra, typed_extra)) = legacy {
+        extra.extend(field_values.clone());
+        typed_extra.extend(typed_values.clone());
     }
 
-    // Apply extra fields
-    if is_line_segment {
-        if let Some(line) = current_line.as_mut() {
-            for (key, val) in &field_values {
-                line.extra.insert(key.clone(), val.clone());
-            }
+    // Apply extra fields where the segment sits in the message structure. Without one,
+    // LIN, QTY, MOA and PRI go to the line and everything else to the document.
+    let extras = match placement {
+        Placement::Unstructured if matches!(segment_code, "LIN" | "QTY" | "MOA" | "PRI") => {
+            current_line
+                .as_mut()
+                .map(|line| (&mut line.extra, &mut line.typed_extra))
         }
-    } else if let Some(doc) = current_doc.as_mut() {
-        for (key, val) in &field_values {
-            doc.extra.insert(key.clone(), val.clone());
+        Placement::Unstructured | Placement::Message => current_doc
+            .as_mut()
+            .map(|doc| (&mut doc.extra, &mut doc.typed_extra)),
+        Placement::Line => current_line
+            .as_mut()
+            .map(|line| (&mut line.extra, &mut line.typed_extra)),
+        Placement::Group { depth, in_line, .. } => {
+            if let Some(group) = open_groups(depth - 1, *in_line, current_doc, current_line)
+                .and_then(|groups| groups.last_mut())
+            {
+                group.fields.extend(typed_values);
+            }
+            return failures;
         }
+    };
+    if let Some((extra, typed_extra)) = extras {
+        extra.extend(field_values);
+        typed_extra.extend(typed_values);
     }
+    failures
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::validation::ValidationReport;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_version_detection_and_loading() {
@@ -446,4 +1438,617 @@ mod tests {
         // Output should contain JSONL
         assert!(output.contains("\"document_number\":\"12345\""));
     }
+
+    #[test]
+    fn test_failed_version_is_loaded_once() {
+        let input = "UNH+1+ORDERS:D:00X:UN\nBGM+220+A1\nUNT+2+1\n\
+                     UNH+2+ORDERS:D:00X:UN\nBGM+220+A2\nUNT+2+2";
+        let mut processor = EdifactProcessor::new();
+        let output = processor.process_to_string(input).unwrap();
+        // The base registry still maps the messages
+        assert!(output.contains("\"document_number\":\"A2\""));
+        assert_eq!(processor.version(), Some(&"D00X".to_string()));
+        assert!(processor.failed_versions.contains("D00X"));
+    }
+
+    #[test]
+    fn test_una_custom_delimiters() {
+        let input = "UNA|*,? ~\nUNB*UNOC|3*SENDER*RECEIVER*260121|1200*REF1~\nUNH*1*ORDERS~\nBGM*220*PO777~\nLIN*1***SKU9~\nQTY*1*12,5*PCE~\nPRI*AAA|4,25~\nUNT*6*1~";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(doc.sender, "SENDER");
+        assert_eq!(doc.interchange_id, "REF1");
+        assert_eq!(doc.document_number, "PO777");
+        assert_eq!(doc.lines.len(), 1);
+        assert_eq!(doc.lines[0].sku, "SKU9");
+        assert_eq!(doc.lines[0].qty, Some(12.5));
+        assert_eq!(doc.lines[0].amount, Some(4.25));
+    }
+
+    #[test]
+    fn test_release_character_in_party_name() {
+        let input = "UNH+1+ORDERS'\nBGM+220+PO?+1'\nNAD+BY+BUYER1++O?'NEIL FOODS'\nUNT+3+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(doc.document_number, "PO+1");
+        assert_eq!(doc.buyer.as_deref(), Some("BUYER1"));
+        assert_eq!(
+            doc.groups[0]
+                .fields
+                .get("PartyName")
+                .and_then(Value::as_str),
+            Some("O'NEIL FOODS")
+        );
+    }
+
+    #[test]
+    fn test_segment_groups_follow_message_structure() {
+        let input = "UNH+1+ORDERS'\nBGM+220+PO1'\nNAD+BY+BUYER1'\nRFF+VA:ES123'\nNAD+SU+SELLER1'\nRFF+VA:FR456'\nLIN+1++SKU1'\nPRI+AAA:4.25'\nRFF+LI:77'\nUNS+S'\nMOA+86:999'\nUNT+12+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+
+        assert_eq!(doc.buyer.as_deref(), Some("BUYER1"));
+        assert_eq!(doc.seller.as_deref(), Some("SELLER1"));
+        let parties: Vec<_> = doc
+            .groups
+            .iter()
+            .map(|g| {
+                (
+                    g.group.as_str(),
+                    g.groups[0].fields["ReferenceNumber"].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parties,
+            vec![("SG2", Some("ES123")), ("SG2", Some("FR456"))]
+        );
+
+        // The price and the line reference stay with the line; the summary MOA does not
+        // overwrite the line amount
+        assert_eq!(doc.lines.len(), 1);
+        let line = &doc.lines[0];
+        assert_eq!(line.amount, Some(4.25));
+        assert_eq!(line.groups[0].group, "SG28");
+        assert_eq!(line.groups[1].group, "SG29");
+        assert_eq!(line.groups[1].fields["ReferenceNumber"], "77");
+        assert_eq!(line.extra["Value"], "4.25");
+
+        // The document extras keep the fields of its groups, as before segment groups,
+        // but not those of the line
+        assert_eq!(doc.extra["PartyId"], "SELLER1");
+        assert_eq!(doc.extra["ReferenceNumber"], "FR456");
+    }
+
+    #[test]
+    fn test_parties_references_and_dates() {
+        let input = "UNH+1+ORDERS'BGM+220+PO1'DTM+137:20250110:102'DTM+63:20250120:102'RFF+CT:K-77'DTM+171:20241201:102'NAD+BY+BUYER1::9'RFF+VA:ES123'NAD+DP+STORE7::9++STORE 7:BACKDOOR+C/ MAYOR 1+MADRID++28001+ES'NAD+IV+ACC1::9'LIN+1++SKU1'DTM+2:20250115:102'UNS+S'UNT+14+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+
+        assert_eq!(
+            doc.parties.keys().collect::<Vec<_>>(),
+            vec!["BY", "DP", "IV"]
+        );
+        assert_eq!(doc.parties["BY"][0].references["VA"], "ES123");
+        let delivery = &doc.parties["DP"][0];
+        assert_eq!(delivery.id.as_deref(), Some("STORE7"));
+        assert_eq!(delivery.name.as_deref(), Some("STORE 7 BACKDOOR"));
+        assert_eq!(delivery.street.as_deref(), Some("C/ MAYOR 1"));
+        assert_eq!(delivery.city.as_deref(), Some("MADRID"));
+        assert_eq!(delivery.postal_code.as_deref(), Some("28001"));
+        assert_eq!(delivery.country.as_deref(), Some("ES"));
+        assert_eq!(doc.references["CT"], "K-77");
+        assert_eq!(doc.document_date.as_deref(), Some("2025-01-10"));
+        assert_eq!(doc.dates["137"].value, "2025-01-10");
+        assert_eq!(doc.dates["137"].raw, "20250110");
+        assert_eq!(doc.dates["137"].format.as_deref(), Some("102"));
+        assert_eq!(doc.dates["63"].value, "2025-01-20");
+        assert_eq!(doc.dates["171"].value, "2024-12-01");
+        // Line dates belong to the line
+        assert!(!doc.dates.contains_key("2"));
+    }
+
+    #[test]
+    fn test_repeated_party_qualifier() {
+        let input = "UNH+1+ORDERS'BGM+220+PO1'NAD+BY+BUYER1::9'NAD+DP+STORE7::9'RFF+API:D7'NAD+DP+STORE8::9'RFF+API:D8'LIN+1++SKU1'UNS+S'UNT+9+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+
+        let delivery: Vec<_> = doc.parties["DP"]
+            .iter()
+            .map(|party| (party.id.as_deref(), party.references["API"].as_str()))
+            .collect();
+        assert_eq!(
+            delivery,
+            vec![(Some("STORE7"), "D7"), (Some("STORE8"), "D8")]
+        );
+        assert_eq!(doc.parties["BY"].len(), 1);
+    }
+
+    #[test]
+    fn test_typed_extra_values() {
+        let input = "UNH+1+ZZZZZZ'BGM+220+PO1'LIN+1++SKU1:EN'PRI+AAA:4,25'LIN+X2++SKU2:EN'UNT+6+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let mut output = Vec::new();
+        let mut report = Vec::new();
+        processor
+            .process_with_report(input.as_bytes(), &mut output, &mut report)
+            .unwrap();
+        let doc: StreamingDocument = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(doc.typed_extra["MessageName"], Value::from("220"));
+        assert_eq!(doc.lines[0].typed_extra["LineNumber"], Value::from(1));
+        assert_eq!(doc.lines[0].typed_extra["Value"], Value::from(4.25));
+        assert_eq!(doc.lines[0].typed_extra["Type"], Value::from("EN"));
+        // `extra` keeps the text as it was sent
+        assert_eq!(doc.lines[0].extra["LineNumber"], "1");
+        assert_eq!(doc.lines[0].extra["Value"], "4,25");
+        // Kept as text and reported
+        assert_eq!(doc.lines[1].typed_extra["LineNumber"], Value::from("X2"));
+        let report: ValidationReport = serde_json::from_slice(&report).unwrap();
+        assert_eq!(report.status, Status::Warn);
+        assert_eq!(
+            report.findings[0].reason,
+            "LIN LineNumber 'X2' is not a number"
+        );
+        assert_eq!(report.findings[0].position.unwrap().segment_index, 5);
+    }
+
+    #[test]
+    fn test_documents_iterator() {
+        let input = "UNB+UNOC:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS'BGM+220+PO1'UNT+3+1'UNH+2+ORDERS'BGM+220+PO2'UNT+9+2'UNH+3+ORDERS'BGM+220+PO3'UNT+3+3'UNZ+3+REF1'";
+        let mut processor = EdifactProcessor::new().with_reject_invalid(true);
+        let mut documents = processor.documents(input.as_bytes());
+
+        let first = documents.next().unwrap().unwrap();
+        assert_eq!(first.document_number, "PO1");
+        assert_eq!(first.interchange_id, "REF1");
+        assert_eq!(documents.take_reports().len(), 1);
+        // PO2 has a wrong segment count and is skipped
+        let numbers: Vec<String> = documents
+            .by_ref()
+            .map(|doc| doc.unwrap().document_number)
+            .collect();
+        assert_eq!(numbers, ["PO3"]);
+        let statuses: Vec<Status> = documents
+            .take_reports()
+            .iter()
+            .map(|report| report.status)
+            .collect();
+        assert_eq!(statuses, [Status::Error, Status::Ok, Status::Ok]);
+    }
+
+    #[test]
+    fn test_tolerant_dead_letters() {
+        // UTF-8 interchange: PO2 holds a byte that is not UTF-8, PO3 a wrong UNT count
+        let mut input = b"UNB+UNOY:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS'BGM+220+PO1'UNT+3+1'UNH+2+ORDERS'BGM+220+PO2'FTX+AAI+++CAF".to_vec();
+        input.push(0xC9);
+        input.extend_from_slice(b"'UNT+4+2'UNH+3+ORDERS'BGM+220+PO3'UNT+9+3'UNZ+3+REF1'");
+
+        let mut processor = EdifactProcessor::new();
+        let result: Result<Vec<_>> = processor.documents(input.as_slice()).collect();
+        assert!(result.is_err());
+
+        let mut processor = EdifactProcessor::new().with_tolerant(true);
+        let mut documents = processor.documents(input.as_slice());
+        let mut summary = RunSummary::default();
+        let mut letters = Vec::new();
+        while let Some(event) = documents.next_event() {
+            let event = event.unwrap();
+            summary.record(&event);
+            if let Event::Rejected(letter) = event {
+                letters.push(letter);
+            }
+        }
+        assert_eq!(summary.to_string(), "1 messages accepted, 2 rejected");
+
+        assert_eq!(letters[0].message_reference.as_deref(), Some("2"));
+        assert_eq!(letters[0].document_number.as_deref(), Some("PO2"));
+        assert!(letters[0].reason.contains("invalid UTF-8"));
+        assert_eq!(letters[0].position.unwrap().segment_index, 7);
+        assert_eq!(
+            letters[0].raw,
+            "UNH+2+ORDERS'BGM+220+PO2'FTX+AAI+++CAF\u{FFFD}'UNT+4+2'"
+        );
+        assert_eq!(letters[1].message_reference.as_deref(), Some("3"));
+        assert_eq!(letters[1].reason, "UNT declares 9 segments, found 3");
+    }
+
+    #[test]
+    fn test_strictness_policy() {
+        // The shipped dictionary marks the BGM document number mandatory
+        let registry = TranslationRegistry::new().unwrap();
+        let input = "UNB+UNOC:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS:D:96A:UN'BGM+220+PO1'UNT+3+1'UNH+2+ORDERS:D:96A:UN'BGM+220'IMD+F++:::FRAGILE'UNS+S'UNT+5+2'UNZ+2+REF1'";
+        let run = |policy: StrictnessPolicy, tolerant: bool| {
+            let mut processor = EdifactProcessor::with_registry(registry.clone())
+                .with_strictness(policy)
+                .with_tolerant(tolerant);
+            let mut documents = processor.documents(input.as_bytes());
+            let result: Result<Vec<_>> = documents.by_ref().collect();
+            (
+                result,
+                documents.take_reports(),
+                documents.take_dead_letters(),
+            )
+        };
+
+        let (documents, _, _) = run(StrictnessPolicy::default(), false);
+        assert_eq!(documents.unwrap().len(), 2);
+
+        let (documents, reports, _) = run(StrictnessPolicy::new(Strictness::Warn), false);
+        assert_eq!(documents.unwrap().len(), 2);
+        let reasons: Vec<_> = reports[1].findings.iter().map(|f| &f.reason).collect();
+        assert_eq!(reports[1].status, Status::Warn);
+        assert_eq!(
+            reasons,
+            [
+                "BGM mandatory element 2 (DocumentNumber) is missing",
+                "segment IMD is not in the dictionary"
+            ]
+        );
+
+        // Only the partner is strict
+        let policy = StrictnessPolicy::default().with_partner("SENDER", Strictness::Strict);
+        let (documents, _, _) = run(policy, false);
+        match documents {
+            Err(FileReduceError::StrictViolation {
+                message_reference,
+                violations,
+            }) => {
+                assert_eq!(message_reference, "2");
+                assert_eq!(violations.len(), 2);
+                assert!(violations[1].starts_with("segment IMD is not in the dictionary at "));
+            }
+            other => panic!(
+                "expected a strict violation, got {:?}",
+                other.map(|d| d.len())
+            ),
+        }
+        let policy =
+            StrictnessPolicy::new(Strictness::Strict).with_partner("SENDER", Strictness::Lenient);
+        assert!(run(policy, false).0.is_ok());
+
+        let (documents, _, letters) = run(StrictnessPolicy::new(Strictness::Strict), true);
+        assert_eq!(documents.unwrap().len(), 1);
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].message_reference.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_shipped_mandatory_elements() {
+        let input = "UNH+1+ORDERS'BGM+220'UNT+3'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap())
+            .with_strictness(StrictnessPolicy::new(Strictness::Warn));
+        let mut documents = processor.documents(input.as_bytes());
+        assert_eq!(documents.by_ref().count(), 1);
+        let reports = documents.take_reports();
+        let reasons: Vec<_> = reports[0].findings.iter().map(|f| &f.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                "UNH mandatory component 2.2 (VersionNumber) is missing",
+                "UNH mandatory component 2.3 (ReleaseNumber) is missing",
+                "UNH mandatory component 2.4 (ControllingAgency) is missing",
+                "BGM mandatory element 2 (DocumentNumber) is missing",
+                "UNT mandatory element 2 (MessageReference) is missing",
+                "UNT message reference  does not match UNH reference 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_telemetry_of_unmapped_segments() {
+        let input = "UNB+UNOC:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS'BGM+220+PO1+9+EXTRA'FTX+AAI+++FRAGILE'UNS+S'UNT+5+1'UNH+2+ORDERS'BGM+220+PO2+9'FTX+AAI+++FRAGILE'UNT+4+2'UNZ+2+REF1'";
+        let mut telemetry = TelemetryCollector::new();
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        processor
+            .process_with_telemetry(input.as_bytes(), &mut Vec::new(), &mut telemetry)
+            .unwrap();
+        let report = telemetry.report();
+        assert_eq!(report.unknown_segments, 2);
+        // Element positions are only checked when asked for
+        assert_eq!(report.unmapped_elements, 0);
+        assert_eq!(report.segments[0].segment, "FTX");
+        assert_eq!(report.segments[0].message_type, "ORDERS");
+        assert_eq!(report.segments[0].sender, "SENDER");
+        assert_eq!(report.segments[0].samples, ["FTX+AAI+++FRAGILE"]);
+
+        let mut telemetry = TelemetryCollector::new();
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap())
+            .with_telemetry(true);
+        processor
+            .process_with_telemetry(input.as_bytes(), &mut Vec::new(), &mut telemetry)
+            .unwrap();
+        let report = telemetry.report();
+        assert_eq!(report.unmapped_elements, 1);
+        let bgm = report.segments.iter().find(|s| s.segment == "BGM").unwrap();
+        assert_eq!(bgm.unmapped_elements, BTreeMap::from([(4, 1)]));
+        assert_eq!(bgm.samples, ["BGM+220+PO1+9+EXTRA"]);
+    }
+
+    #[test]
+    fn test_field_bindings_from_dictionary() {
+        let mut config: crate::translations::TranslationConfig =
+            serde_json::from_str(include_str!("../translations.json")).unwrap();
+        config.segments.get_mut("BGM").unwrap().bindings = serde_json::from_str(
+            r#"[
+                { "target": "document.doc_type", "element": 1, "transform": { "map": { "380": "INVOIC" } } },
+                { "target": "document.document_number", "from": "DocumentNumber" }
+            ]"#,
+        )
+        .unwrap();
+        config.segments.insert(
+            "PIA".to_string(),
+            serde_json::from_str(
+                r#"{
+                    "label": "AdditionalProductId",
+                    "use_qualifier": true,
+                    "bindings": [{ "target": "line.sku", "qualifier": "5", "element": 1 }]
+                }"#,
+            )
+            .unwrap(),
+        );
+        let input = "UNH+1'BGM+380+INV1'LIN+1++EAN1:EN'PIA+1+OTHER:SA'PIA+5+SKU9:SA'LIN+2++EAN2:EN'UNT+7+1'";
+        let mut processor =
+            EdifactProcessor::with_registry(TranslationRegistry::from_config(config));
+        let output = processor.process_to_string(input).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(doc.doc_type, "INVOIC");
+        assert_eq!(doc.document_number, "INV1");
+        assert_eq!(doc.lines[0].sku, "SKU9");
+        assert_eq!(doc.lines[1].sku, "EAN2");
+        assert_eq!(doc.lines[1].line_no, 2);
+    }
+
+    #[test]
+    fn test_config_moves_document_fields() {
+        let input = "UNH+1+ORDERS:D:96A:UN'BGM+220+PO1'DTM+137:20250110:102'DTM+4:20250105:102'NAD+BY+BUYER1::9'NAD+IV+ACC1::9'UNT+7+1'";
+        let config: crate::translations::TranslationConfig =
+            serde_json::from_str(include_str!("../translations.json")).unwrap();
+        let process = |config| {
+            let mut processor =
+                EdifactProcessor::with_registry(TranslationRegistry::from_config(config));
+            let output = processor.process_to_string(input).unwrap();
+            serde_json::from_str::<StreamingDocument>(output.trim()).unwrap()
+        };
+
+        let doc = process(config.clone());
+        assert_eq!(doc.doc_type, "220");
+        assert_eq!(doc.message_type, "ORDERS");
+        assert_eq!(doc.buyer.as_deref(), Some("BUYER1"));
+        assert_eq!(doc.document_date.as_deref(), Some("2025-01-10"));
+
+        // Only the dictionary changes: the buyer is the invoicee, the date the order date
+        let mut moved = config;
+        moved.segments.get_mut("NAD").unwrap().bindings = serde_json::from_str(
+            r#"[{ "target": "document.buyer", "qualifier": "IV", "element": 1 }]"#,
+        )
+        .unwrap();
+        moved.segments.get_mut("DTM").unwrap().bindings = serde_json::from_str(
+            r#"[{ "target": "document.document_date", "qualifier": "4", "element": 1, "transform": "date" }]"#,
+        )
+        .unwrap();
+        let doc = process(moved);
+        assert_eq!(doc.buyer.as_deref(), Some("ACC1"));
+        assert_eq!(doc.document_date.as_deref(), Some("2025-01-05"));
+        assert_eq!(doc.seller, None);
+    }
+
+    #[test]
+    fn test_line_values_by_qualifier() {
+        let input = "UNH+1+ORDERS'BGM+220+PO1'LIN+1++SKU1'QTY+21:10:PCE'QTY+12:8'MOA+203:50'PRI+AAB:6'PRI+AAA:5:CA::1:PCE'LIN+2++SKU2'QTY+59:3'PRI+AAB:7'PRI+AAA:6,5'UNS+S'UNT+15+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+
+        let line = &doc.lines[0];
+        // The ordered quantity is primary for ORDERS even when another one follows
+        assert_eq!((line.qty, line.uom.as_deref()), (Some(10.0), Some("PCE")));
+        assert_eq!(line.quantities["12"].value, 8.0);
+        assert_eq!(line.amounts["203"], 50.0);
+        // Prices do not replace the line amount
+        assert_eq!(line.amount, Some(50.0));
+        assert_eq!(
+            line.prices["AAA"],
+            Price {
+                value: 5.0,
+                price_type: Some("CA".to_string()),
+                basis: Some(1.0),
+                unit: Some("PCE".to_string()),
+            }
+        );
+        assert_eq!(line.prices["AAB"].value, 6.0);
+
+        // Without primary qualifiers the last quantity wins, and without MOA the
+        // primary price fills the amount
+        let line = &doc.lines[1];
+        assert_eq!(line.qty, Some(3.0));
+        assert_eq!(line.amount, Some(6.5));
+    }
+
+    #[test]
+    fn test_dates_normalized_to_iso() {
+        let input = "UNH+1+ORDERS'BGM+220+PO1'DTM+137:202501101430:203'DTM+2:20250230:102'DTM+324:20250101-20250131:718'UNT+6+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let mut output = Vec::new();
+        let mut report = Vec::new();
+        processor
+            .process_with_report(input.as_bytes(), &mut output, &mut report)
+            .unwrap();
+        let doc: StreamingDocument = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(doc.document_date.as_deref(), Some("2025-01-10T14:30:00"));
+        assert_eq!(doc.dates["324"].value, "2025-01-01/2025-01-31");
+        // Not a date: kept as it was and reported
+        assert_eq!(doc.requested_delivery_date.as_deref(), Some("20250230"));
+        assert_eq!(doc.dates["2"].raw, "20250230");
+        let report: ValidationReport = serde_json::from_slice(&report).unwrap();
+        assert_eq!(report.status, Status::Warn);
+        assert_eq!(
+            report.findings[0].reason,
+            "DTM value '20250230' is not a valid 102 date"
+        );
+    }
+
+    #[test]
+    fn test_tree_output_mode() {
+        let input = "UNB+UNOC:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS:D:96A:UN'BGM+220+PO1'NAD+BY+BUYER1'RFF+VA:ES123'LIN+1++SKU1'FTX+AAA+++FRAGILE'UNS+S'UNT+8+1'UNZ+1+REF1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap())
+            .with_output_mode(OutputMode::Tree);
+        let output = processor.process_to_string(input).unwrap();
+        let tree: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+
+        assert_eq!(tree["interchange_id"], "REF1");
+        assert_eq!(tree["message_type"], "ORDERS");
+        let content = tree["content"].as_array().unwrap();
+        assert_eq!(content.len(), 6);
+        assert_eq!(content[0]["segment"]["tag"], "UNH");
+        assert_eq!(content[2]["group"]["group"], "SG2");
+        assert_eq!(content[2]["group"]["content"][1]["group"]["group"], "SG3");
+        // Segments the document mode drops are kept, inside the line item group
+        let line = &content[3]["group"];
+        assert_eq!(line["group"], "SG25");
+        assert_eq!(line["content"][1]["segment"]["tag"], "FTX");
+        assert_eq!(content[5]["segment"]["tag"], "UNT");
+    }
+
+    #[test]
+    fn test_single_line_interchange() {
+        let input = "UNB+UNOC:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS'BGM+220+PO1'UNT+3+1'UNH+2+ORDERS'BGM+220+PO2'UNT+3+2'UNZ+2+REF1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let docs: Vec<StreamingDocument> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].document_number, "PO1");
+        assert_eq!(docs[1].document_number, "PO2");
+        assert_eq!(docs[1].interchange_id, "REF1");
+    }
+
+    #[test]
+    fn test_latin1_interchange_is_output_as_utf8() {
+        let input: &[u8] =
+            b"UNB+UNOC:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS'BGM+220+PO1'NAD+BY+BUYER1++M\xdcLLER GMBH'UNT+4+1'UNZ+1+REF1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = String::from_utf8(processor.process_to_vec(input).unwrap()).unwrap();
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(
+            doc.groups[0]
+                .fields
+                .get("PartyName")
+                .and_then(Value::as_str),
+            Some("MÜLLER GMBH")
+        );
+
+        // Declared as UNOC but actually UTF-8: the override wins
+        let input = "UNB+UNOC:3+SENDER+RECEIVER+260121:1200+REF1'UNH+1+ORDERS'NAD+BY+BUYER1++MÜLLER GMBH'UNT+3+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap())
+            .with_charset(Charset::Utf8);
+        let output = processor.process_to_string(input).unwrap();
+        assert!(output.contains("MÜLLER GMBH"));
+    }
+
+    #[test]
+    fn test_functional_groups_and_back_to_back_interchanges() {
+        let input = "UNB+UNOC:3+SENDER1+RECEIVER1+260121:1200+ICR1'\
+UNG+ORDERS+APPSENDER:ZZ+APPRECEIVER:ZZ+260121:1200+GRP1+UN+D:96A'\
+UNH+1+ORDERS'BGM+220+PO1'UNT+3+1'\
+UNE+1+GRP1'\
+UNH+2+ORDERS'BGM+220+PO2'UNT+3+2'\
+UNZ+2+ICR1'\
+UNB+UNOC:3+SENDER2+RECEIVER2+260122:0800+ICR2'\
+UNH+1+ORDERS'BGM+220+PO3'UNT+3+1'\
+UNZ+1+ICR2'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        let docs: Vec<StreamingDocument> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(docs.len(), 3);
+
+        let group = docs[0].group.as_ref().unwrap();
+        assert_eq!(group.reference, "GRP1");
+        assert_eq!(group.message_type, "ORDERS");
+        assert_eq!(group.application_sender, "APPSENDER");
+        assert_eq!(group.application_receiver, "APPRECEIVER");
+        assert_eq!(
+            (group.date.as_str(), group.time.as_str()),
+            ("260121", "1200")
+        );
+
+        // Outside UNG..UNE and in the next interchange there is no group
+        assert!(docs[1].group.is_none());
+        assert_eq!(docs[1].interchange_id, "ICR1");
+        assert_eq!(docs[2].interchange_id, "ICR2");
+        assert_eq!(docs[2].sender, "SENDER2");
+        assert_eq!(docs[2].receiver, "RECEIVER2");
+        assert!(docs[2].group.is_none());
+        assert!(!output.lines().nth(2).unwrap().contains("\"group\""));
+    }
+
+    #[test]
+    fn test_validation_report_and_rejection() {
+        let input = "UNB+UNOC:3+SENDER+RECEIVER+260121:1200+ICR1'\
+UNH+1+ORDERS'BGM+220+PO1'CNT+2:0'UNT+4+1'\
+UNH+2+ORDERS'BGM+220+PO2'UNT+5+2'\
+UNZ+2+ICR1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap())
+            .with_reject_invalid(true);
+        let mut output = Vec::new();
+        let mut report = Vec::new();
+        processor
+            .process_with_report(input.as_bytes(), &mut output, &mut report)
+            .unwrap();
+
+        let docs: Vec<StreamingDocument> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].document_number, "PO1");
+
+        let reports: Vec<crate::validation::ValidationReport> = String::from_utf8(report)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let statuses: Vec<Status> = reports.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![Status::Ok, Status::Error, Status::Ok]);
+        assert_eq!(reports[1].document_number.as_deref(), Some("PO2"));
+        assert_eq!(
+            reports[1].findings[0].reason,
+            "UNT declares 5 segments, found 3"
+        );
+    }
+
+    #[test]
+    fn test_source_positions_in_output() {
+        let input = "UNH+1+ORDERS'\nBGM+220+PO1'\nLIN+1+++SKU1'\nQTY+1+2+PCE'\nUNT+5+1'";
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap())
+            .with_source_positions(true);
+        let output = processor.process_to_string(input).unwrap();
+        assert!(output.contains("\"_source\""));
+        let doc: StreamingDocument = serde_json::from_str(output.trim()).unwrap();
+        let source = doc.source.unwrap();
+        assert_eq!((source.byte_offset, source.segment_index), (0, 1));
+        let line_source = doc.lines[0].source.unwrap();
+        assert_eq!(line_source.byte_offset, 27);
+        assert_eq!(line_source.segment_index, 3);
+        assert_eq!(line_source.message_index, 1);
+
+        // Off by default
+        let mut processor = EdifactProcessor::with_registry(TranslationRegistry::new().unwrap());
+        let output = processor.process_to_string(input).unwrap();
+        assert!(!output.contains("_source"));
+    }
 }
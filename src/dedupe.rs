@@ -0,0 +1,430 @@
+use crate::error::{FileReduceError, Result};
+use crate::model::streaming::StreamingDocument;
+use crate::tree::MessageTree;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Document field that identifies a message across runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyField {
+    Sender,
+    Receiver,
+    InterchangeId,
+    DocType,
+    DocumentNumber,
+    DocumentDate,
+    Buyer,
+    Seller,
+}
+
+impl KeyField {
+    fn value(&self, doc: &StreamingDocument) -> String {
+        match self {
+            KeyField::Sender => doc.sender.clone(),
+            KeyField::Receiver => doc.receiver.clone(),
+            KeyField::InterchangeId => doc.interchange_id.clone(),
+            KeyField::DocType => doc.doc_type.clone(),
+            KeyField::DocumentNumber => doc.document_number.clone(),
+            KeyField::DocumentDate => doc.document_date.clone().unwrap_or_default(),
+            KeyField::Buyer => doc.buyer.clone().unwrap_or_default(),
+            KeyField::Seller => doc.seller.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for KeyField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyField::Sender => "sender",
+            KeyField::Receiver => "receiver",
+            KeyField::InterchangeId => "interchange_id",
+            KeyField::DocType => "doc_type",
+            KeyField::DocumentNumber => "document_number",
+            KeyField::DocumentDate => "document_date",
+            KeyField::Buyer => "buyer",
+            KeyField::Seller => "seller",
+        })
+    }
+}
+
+impl FromStr for KeyField {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "sender" => Ok(KeyField::Sender),
+            "receiver" => Ok(KeyField::Receiver),
+            "interchange_id" => Ok(KeyField::InterchangeId),
+            "doc_type" => Ok(KeyField::DocType),
+            "document_number" => Ok(KeyField::DocumentNumber),
+            "document_date" => Ok(KeyField::DocumentDate),
+            "buyer" => Ok(KeyField::Buyer),
+            "seller" => Ok(KeyField::Seller),
+            other => Err(format!(
+                "unknown key field '{}' (expected sender, receiver, interchange_id, doc_type, document_number, document_date, buyer or seller)",
+                other
+            )),
+        }
+    }
+}
+
+/// Fields whose values, together, identify a message. Matches the `UQ_EDI_Order`
+/// constraint by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupeKey(pub Vec<KeyField>);
+
+impl DedupeKey {
+    pub fn values(&self, doc: &StreamingDocument) -> Vec<String> {
+        self.0.iter().map(|field| field.value(doc)).collect()
+    }
+}
+
+impl Default for DedupeKey {
+    fn default() -> Self {
+        DedupeKey(vec![
+            KeyField::Sender,
+            KeyField::DocumentNumber,
+            KeyField::InterchangeId,
+        ])
+    }
+}
+
+impl FromStr for DedupeKey {
+    type Err = String;
+
+    /// Comma separated fields, e.g. `sender,document_number`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let fields = s
+            .split(',')
+            .map(str::parse)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(DedupeKey(fields))
+    }
+}
+
+impl fmt::Display for DedupeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, field) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", field)?;
+        }
+        Ok(())
+    }
+}
+
+/// What happens to a message whose key was seen before
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    /// Not written
+    #[default]
+    Skip,
+    /// Written with a `_duplicate` field saying when the key was last written
+    Flag,
+    /// Written as new, and remembered as the latest copy
+    Replace,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(DuplicatePolicy::Skip),
+            "flag" => Ok(DuplicatePolicy::Flag),
+            "replace" => Ok(DuplicatePolicy::Replace),
+            other => Err(format!(
+                "unknown duplicate policy '{}' (expected skip, flag or replace)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DuplicatePolicy::Skip => "skip",
+            DuplicatePolicy::Flag => "flag",
+            DuplicatePolicy::Replace => "replace",
+        })
+    }
+}
+
+/// When a message key was last written, as kept in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeenMessage {
+    /// Seconds since the Unix epoch
+    pub seen_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoreLine {
+    key: Vec<String>,
+    #[serde(flatten)]
+    seen: SeenMessage,
+}
+
+/// Keys of the messages written so far, persisted as one JSON line per key in a local
+/// file. Later lines for the same key win.
+#[derive(Debug, Default)]
+pub struct DedupeStore {
+    seen: HashMap<Vec<String>, SeenMessage>,
+    file: Option<File>,
+}
+
+impl DedupeStore {
+    /// Loads the store at `path`, creating it when missing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut seen = HashMap::new();
+        if path.exists() {
+            for (idx, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry: StoreLine = serde_json::from_str(&line).map_err(|e| {
+                    FileReduceError::Parse(format!("{} line {}: {}", path.display(), idx + 1, e))
+                })?;
+                seen.insert(entry.key, entry.seen);
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            seen,
+            file: Some(file),
+        })
+    }
+
+    /// A store that forgets everything at the end of the run
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &[String]) -> Option<SeenMessage> {
+        self.seen.get(key).copied()
+    }
+
+    pub fn insert(&mut self, key: Vec<String>, seen: SeenMessage) -> Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            let line = StoreLine { key, seen };
+            serde_json::to_writer(&mut *file, &line)?;
+            file.write_all(b"\n")?;
+            self.seen.insert(line.key, seen);
+        } else {
+            self.seen.insert(key, seen);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+/// Checks the messages of a run against a [`DedupeStore`]. The keys of the messages
+/// it admits stay pending until they are safely written: [`Deduplicator::commit_stored`]
+/// as the sink stores them, [`Deduplicator::commit`] for the rest, so that a failed run
+/// does not mark its unwritten messages as seen. The store is only locked while a key
+/// is looked up and while pending keys are committed.
+pub struct Deduplicator {
+    store: Arc<Mutex<DedupeStore>>,
+    key: DedupeKey,
+    policy: DuplicatePolicy,
+    pending: HashMap<Vec<String>, SeenMessage>,
+    /// Admitted messages in the order they were written, with the key each one leaves
+    /// pending (none for a flagged copy)
+    admitted: VecDeque<Option<Vec<String>>>,
+    /// Admitted messages already taken off `admitted`
+    committed: u64,
+    duplicates: u64,
+}
+
+impl Deduplicator {
+    pub fn new(store: Arc<Mutex<DedupeStore>>, key: DedupeKey, policy: DuplicatePolicy) -> Self {
+        Self {
+            store,
+            key,
+            policy,
+            pending: HashMap::new(),
+            admitted: VecDeque::new(),
+            committed: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// Messages seen so far whose key was already in the store or admitted before
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Whether `doc` should be written. A flagged duplicate gets its `duplicate` field,
+    /// and that of its `tree`, set to when the key was last written.
+    pub fn admit(
+        &mut self,
+        doc: &mut StreamingDocument,
+        tree: Option<&mut MessageTree>,
+    ) -> Result<bool> {
+        let key = self.key.values(doc);
+        let now = SeenMessage {
+            seen_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        };
+        let previous = match self.pending.get(&key) {
+            Some(seen) => Some(*seen),
+            None => lock(&self.store).get(&key),
+        };
+        let Some(previous) = previous else {
+            self.pending.insert(key.clone(), now);
+            self.admitted.push_back(Some(key));
+            return Ok(true);
+        };
+        self.duplicates += 1;
+        match self.policy {
+            DuplicatePolicy::Skip => Ok(false),
+            DuplicatePolicy::Flag => {
+                doc.duplicate = Some(previous);
+                if let Some(tree) = tree {
+                    tree.duplicate = Some(previous);
+                }
+                self.admitted.push_back(None);
+                Ok(true)
+            }
+            DuplicatePolicy::Replace => {
+                self.pending.insert(key.clone(), now);
+                self.admitted.push_back(Some(key));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Records the keys of the messages admitted since the last commit in the store
+    pub fn commit(&mut self) -> Result<()> {
+        let mut store = lock(&self.store);
+        for (key, seen) in self.pending.drain() {
+            store.insert(key, seen)?;
+        }
+        self.committed += self.admitted.len() as u64;
+        self.admitted.clear();
+        Ok(())
+    }
+
+    /// Records the keys of the first `stored` admitted messages, counted from the start
+    /// of the run, once the sink has them stored for good (see
+    /// [`DataSink::stored`](crate::sink::DataSink::stored))
+    pub fn commit_stored(&mut self, stored: u64) -> Result<()> {
+        let mut store = None;
+        while self.committed < stored {
+            let Some(admitted) = self.admitted.pop_front() else {
+                break;
+            };
+            self.committed += 1;
+            // A key admitted again (replace) was committed with its latest date
+            let Some((key, seen)) = admitted.and_then(|key| self.pending.remove_entry(&key)) else {
+                continue;
+            };
+            store
+                .get_or_insert_with(|| lock(&self.store))
+                .insert(key, seen)?;
+        }
+        Ok(())
+    }
+}
+
+/// A panic while the store was locked leaves it usable: its map and file are only
+/// changed one whole key at a time
+fn lock(store: &Mutex<DedupeStore>) -> MutexGuard<'_, DedupeStore> {
+    store
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(number: &str) -> StreamingDocument {
+        StreamingDocument {
+            interchange_id: "REF1".to_string(),
+            sender: "SENDER".to_string(),
+            document_number: number.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_policies_across_runs() {
+        let path = std::env::temp_dir().join(format!("dedupe-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = Arc::new(Mutex::new(DedupeStore::open(&path).unwrap()));
+        let mut dedupe =
+            Deduplicator::new(store.clone(), DedupeKey::default(), DuplicatePolicy::Skip);
+        assert!(dedupe.admit(&mut doc("PO1"), None).unwrap());
+        assert!(!dedupe.admit(&mut doc("PO1"), None).unwrap());
+        assert!(dedupe.admit(&mut doc("PO2"), None).unwrap());
+        assert_eq!(dedupe.duplicates(), 1);
+        // Nothing is remembered until the messages are committed
+        assert!(store.lock().unwrap().is_empty());
+        dedupe.commit().unwrap();
+        assert_eq!(store.lock().unwrap().len(), 2);
+        drop((dedupe, store));
+
+        // A later run sees what the first one wrote
+        let store = Arc::new(Mutex::new(DedupeStore::open(&path).unwrap()));
+        assert_eq!(store.lock().unwrap().len(), 2);
+        let mut dedupe =
+            Deduplicator::new(store.clone(), DedupeKey::default(), DuplicatePolicy::Flag);
+        let mut flagged = doc("PO2");
+        assert!(dedupe.admit(&mut flagged, None).unwrap());
+        assert!(flagged.duplicate.is_some());
+        // A run that fails before committing leaves the store as it was
+        assert!(dedupe.admit(&mut doc("PO3"), None).unwrap());
+        drop(dedupe);
+        assert_eq!(store.lock().unwrap().len(), 2);
+
+        let key: DedupeKey = "sender,document_number".parse().unwrap();
+        let mut dedupe = Deduplicator::new(store.clone(), key, DuplicatePolicy::Replace);
+        assert!(dedupe.admit(&mut doc("PO1"), None).unwrap());
+        assert!(dedupe.admit(&mut doc("PO1"), None).unwrap());
+        dedupe.commit().unwrap();
+        assert_eq!(store.lock().unwrap().len(), 3);
+
+        assert!("sender,nope".parse::<DedupeKey>().is_err());
+
+        // Keys are committed as the sink stores their messages
+        let store = Arc::new(Mutex::new(DedupeStore::in_memory()));
+        let mut dedupe =
+            Deduplicator::new(store.clone(), DedupeKey::default(), DuplicatePolicy::Flag);
+        for number in ["PO1", "PO1", "PO2", "PO3"] {
+            assert!(dedupe.admit(&mut doc(number), None).unwrap());
+        }
+        dedupe.commit_stored(2).unwrap();
+        assert_eq!(store.lock().unwrap().len(), 1);
+        dedupe.commit_stored(3).unwrap();
+        assert_eq!(store.lock().unwrap().len(), 2);
+        assert!(store
+            .lock()
+            .unwrap()
+            .get(&DedupeKey::default().values(&doc("PO3")))
+            .is_none());
+        dedupe.commit().unwrap();
+        assert_eq!(store.lock().unwrap().len(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
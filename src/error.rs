@@ -1,3 +1,4 @@
+use crate::parser::segment_reader::SourcePosition;
 use std::io;
 use thiserror::Error;
 
@@ -22,7 +23,7 @@ pub enum FileReduceError {
     #[error("Serialization error: {0}")]
     Serialization(serde_json::Error),
 
-    #[cfg(feature = "full")]
+    #[cfg(any(feature = "cli", feature = "full"))]
     #[error("XML error: {0}")]
     Xml(#[from] quick_xml::Error),
 
@@ -35,6 +36,18 @@ pub enum FileReduceError {
     #[error("Document is incomplete")]
     IncompleteDocument,
 
+    #[error("Message {message_reference} breaks the strict policy: {}", .violations.join("; "))]
+    StrictViolation {
+        message_reference: String,
+        violations: Vec<String>,
+    },
+
+    #[error("{source} at {position}")]
+    AtPosition {
+        position: SourcePosition,
+        source: Box<FileReduceError>,
+    },
+
     #[cfg(feature = "db")]
     #[error("Database error: {0}")]
     Db(#[from] tiberius::error::Error),
@@ -48,6 +61,26 @@ pub enum FileReduceError {
     Pool(#[from] RunError<bb8_tiberius::Error>),
 }
 
+impl FileReduceError {
+    /// Attaches the input position where the error was found
+    pub fn at(self, position: SourcePosition) -> Self {
+        FileReduceError::AtPosition {
+            position,
+            source: Box::new(self),
+        }
+    }
+
+    /// What went wrong, without the position attached by [`FileReduceError::at`]
+    pub fn reason(&self) -> String {
+        match self {
+            FileReduceError::AtPosition { source, .. } => source.reason(),
+            FileReduceError::Io(e) => e.to_string(),
+            FileReduceError::Parse(reason) => reason.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
 impl From<serde_json::Error> for FileReduceError {
     fn from(err: serde_json::Error) -> Self {
         FileReduceError::Serialization(err)
@@ -2,18 +2,28 @@ pub mod error;
 pub mod translations;
 
 pub mod parser {
+    pub mod charset;
+    pub mod datetime;
     pub mod edifact;
     #[cfg(feature = "full")]
     pub mod json;
     pub mod parser;
     pub mod segment;
+    pub mod segment_reader;
+    pub mod structure;
     pub mod tokenizer;
-    #[cfg(feature = "full")]
+    #[cfg(any(feature = "cli", feature = "full"))]
     pub mod xml;
 }
 
 pub mod core;
+pub mod dedupe;
+pub mod messages;
+pub mod parallel;
 pub mod serializer;
+pub mod telemetry;
+pub mod tree;
+pub mod validation;
 pub mod version_detector;
 
 #[cfg(feature = "full")]
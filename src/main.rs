@@ -1,10 +1,16 @@
 use clap::Parser;
 use engine_filereduce::query::parser::Parser as QueryParser;
 use filereduce::cli::{Cli, Commands};
+use filereduce::dedupe::{DedupeStore, Deduplicator};
 use filereduce::error::Result;
-use filereduce::processor::{process, FileFormat};
+use filereduce::parser::xml::XmlOptions;
+use filereduce::processor::{process_with_options, FileFormat, ProcessOptions, ProcessOutputs};
+use filereduce::sink::file::FileDataSink;
+use filereduce::telemetry::TelemetryCollector;
+use filereduce::validation::{PartnerStrictness, Strictness, StrictnessPolicy};
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
+use std::sync::{Arc, Mutex};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,9 +25,28 @@ async fn main() -> Result<()> {
             input,
             output,
             format,
+            xml_record_path,
+            xml_strip_namespaces,
+            xml_attribute_prefix,
+            xml_infer_types,
             query,
             limit: _,
             fra,
+            charset,
+            report,
+            reject_invalid,
+            tolerant,
+            dead_letter,
+            strictness,
+            partner_strictness,
+            telemetry,
+            dedupe_store,
+            on_duplicate,
+            dedupe_key,
+            source_positions,
+            output_mode,
+            threads,
+            unordered,
         } => {
             let input_file = File::open(&input)?;
             let output_file = File::create(&output)?;
@@ -36,19 +61,58 @@ async fn main() -> Result<()> {
             };
 
             let mut sink = filereduce::sink::file::FileDataSink::new(BufWriter::new(output_file));
+            let options = ProcessOptions {
+                charset,
+                reject_invalid,
+                source_positions,
+                output_mode,
+                threads,
+                unordered,
+                tolerant: tolerant || dead_letter.is_some(),
+                strictness: strictness_policy(strictness, partner_strictness),
+                xml: XmlOptions {
+                    record_path: xml_record_path,
+                    strip_namespaces: xml_strip_namespaces,
+                    attribute_prefix: xml_attribute_prefix,
+                    infer_types: xml_infer_types,
+                },
+            };
+            let mut report_sink = create_report_sink(report.as_deref())?;
+            let mut dead_letter_sink = create_report_sink(dead_letter.as_deref())?;
+            let mut telemetry_collector = telemetry.as_ref().map(|_| TelemetryCollector::new());
+            let store = dedupe_store.as_deref().map(DedupeStore::open).transpose()?;
 
-            process(
+            use filereduce::sink::DataSink; // Import trait
+            let summary = process_with_options(
                 BufReader::new(input_file),
                 &mut sink,
                 file_format,
                 expr.as_ref(),
+                &options,
+                ProcessOutputs {
+                    reports: report_sink.as_mut().map(|s| s as &mut dyn DataSink),
+                    dead_letters: dead_letter_sink.as_mut().map(|s| s as &mut dyn DataSink),
+                    telemetry: telemetry_collector.as_mut(),
+                    dedupe: store.map(|store| {
+                        Deduplicator::new(Arc::new(Mutex::new(store)), dedupe_key, on_duplicate)
+                    }),
+                },
             )
             .await?;
 
-            use filereduce::sink::DataSink; // Import trait
             sink.flush().await?;
+            if let Some(report_sink) = report_sink.as_mut() {
+                report_sink.flush().await?;
+            }
+            if let Some(dead_letter_sink) = dead_letter_sink.as_mut() {
+                dead_letter_sink.flush().await?;
+            }
+            write_telemetry(telemetry.as_deref(), telemetry_collector.as_ref())?;
 
             println!("Processed {} to {}", input.display(), output.display());
+            if options.tolerant || dedupe_store.is_some() {
+                println!("{}", summary);
+            }
 
             if fra {
                 println!("Compressing to .fra...");
@@ -64,7 +128,23 @@ async fn main() -> Result<()> {
             }
         }
 
-        Commands::Insert { input, config, fra } => {
+        Commands::Insert {
+            input,
+            config,
+            fra,
+            charset,
+            report,
+            reject_invalid,
+            tolerant,
+            dead_letter,
+            strictness,
+            partner_strictness,
+            telemetry,
+            dedupe_store,
+            on_duplicate,
+            dedupe_key,
+            source_positions,
+        } => {
             let config_content = std::fs::read_to_string(&config)?;
             let ingest_config: filereduce::config::IngestConfig =
                 serde_yaml::from_str(&config_content)
@@ -72,16 +152,47 @@ async fn main() -> Result<()> {
 
             let mut sink = filereduce::sink::db::DbDataSink::new(ingest_config.ingest).await?;
             let input_file = File::open(&input)?;
+            let options = ProcessOptions {
+                charset,
+                reject_invalid,
+                source_positions,
+                tolerant: tolerant || dead_letter.is_some(),
+                strictness: strictness_policy(strictness, partner_strictness),
+                ..Default::default()
+            };
+            let mut report_sink = create_report_sink(report.as_deref())?;
+            let mut dead_letter_sink = create_report_sink(dead_letter.as_deref())?;
+            let mut telemetry_collector = telemetry.as_ref().map(|_| TelemetryCollector::new());
+            let store = dedupe_store.as_deref().map(DedupeStore::open).transpose()?;
 
             use filereduce::sink::DataSink;
-            process(
+            let summary = process_with_options(
                 BufReader::new(input_file),
                 &mut sink,
                 FileFormat::Edifact,
                 None,
+                &options,
+                ProcessOutputs {
+                    reports: report_sink.as_mut().map(|s| s as &mut dyn DataSink),
+                    dead_letters: dead_letter_sink.as_mut().map(|s| s as &mut dyn DataSink),
+                    telemetry: telemetry_collector.as_mut(),
+                    dedupe: store.map(|store| {
+                        Deduplicator::new(Arc::new(Mutex::new(store)), dedupe_key, on_duplicate)
+                    }),
+                },
             )
             .await?;
             sink.flush().await?;
+            if let Some(report_sink) = report_sink.as_mut() {
+                report_sink.flush().await?;
+            }
+            if let Some(dead_letter_sink) = dead_letter_sink.as_mut() {
+                dead_letter_sink.flush().await?;
+            }
+            write_telemetry(telemetry.as_deref(), telemetry_collector.as_ref())?;
+            if options.tolerant || dedupe_store.is_some() {
+                println!("{}", summary);
+            }
 
             if fra {
                 println!("Generating optional .fra output...");
@@ -93,11 +204,13 @@ async fn main() -> Result<()> {
                         filereduce::sink::file::FileDataSink::new(BufWriter::new(temp_file));
                     let input_file_2 = File::open(&input)?;
 
-                    process(
+                    process_with_options(
                         BufReader::new(input_file_2),
                         &mut temp_sink,
                         FileFormat::Edifact,
                         None,
+                        &options,
+                        ProcessOutputs::default(),
                     )
                     .await?;
                     temp_sink.flush().await?;
@@ -141,6 +254,34 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+fn create_report_sink(
+    path: Option<&std::path::Path>,
+) -> Result<Option<FileDataSink<BufWriter<File>>>> {
+    match path {
+        Some(path) => Ok(Some(FileDataSink::new(BufWriter::new(File::create(path)?)))),
+        None => Ok(None),
+    }
+}
+
+fn write_telemetry(
+    path: Option<&std::path::Path>,
+    collector: Option<&TelemetryCollector>,
+) -> Result<()> {
+    if let (Some(path), Some(collector)) = (path, collector) {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, &collector.report())?;
+    }
+    Ok(())
+}
+
+fn strictness_policy(default: Strictness, partners: Vec<PartnerStrictness>) -> StrictnessPolicy {
+    partners
+        .into_iter()
+        .fold(StrictnessPolicy::new(default), |policy, partner| {
+            policy.with_partner(partner.sender, partner.strictness)
+        })
+}
+
 fn determine_format(path: &std::path::Path, format: Option<&str>) -> FileFormat {
     if let Some(fmt) = format {
         match fmt.to_lowercase().as_str() {
@@ -0,0 +1,417 @@
+use crate::model::streaming::{Party, StreamingDocument};
+use crate::parser::datetime::{parse_dtm, DateTimeValue};
+use crate::parser::structure::Placement;
+use crate::parser::tokenizer::{Delimiters, SegmentView};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Data specific to a message type, selected from the UNH message type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageDetails {
+    /// INVOIC
+    Invoice(InvoiceDetails),
+    /// DESADV
+    DespatchAdvice(DespatchDetails),
+    /// ORDRSP
+    OrderResponse(OrderResponseDetails),
+    /// PRICAT
+    PriceCatalogue(PriceCatalogueDetails),
+}
+
+impl MessageDetails {
+    /// Details for a UNH message type (`INVOIC` in `UNH+1+INVOIC:D:96A:UN`), `None`
+    /// for types whose data fits the common document fields (ORDERS)
+    pub fn for_message_type(message_type: &str) -> Option<Self> {
+        match message_type {
+            "INVOIC" => Some(MessageDetails::Invoice(Default::default())),
+            "DESADV" => Some(MessageDetails::DespatchAdvice(Default::default())),
+            "ORDRSP" => Some(MessageDetails::OrderResponse(Default::default())),
+            "PRICAT" => Some(MessageDetails::PriceCatalogue(Default::default())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InvoiceDetails {
+    /// MOA 77 (invoice amount), or 86 (message total) when 77 is missing
+    pub total_amount: Option<f64>,
+    /// MOA 79: total of the line items
+    pub line_total: Option<f64>,
+    /// MOA 125: taxable amount
+    pub taxable_amount: Option<f64>,
+    /// MOA 176 (total tax), or 124 outside a TAX group
+    pub tax_amount: Option<f64>,
+    /// Every message level MOA by qualifier
+    pub amounts: BTreeMap<String, f64>,
+    pub taxes: Vec<Tax>,
+    pub payment_terms: Vec<PaymentTerms>,
+}
+
+/// TAX with the MOA segments of its group
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tax {
+    /// `VAT`, `GST`, ...
+    pub tax_type: String,
+    /// `S` standard, `Z` zero rated, `E` exempt, ...
+    pub category: Option<String>,
+    pub rate: Option<f64>,
+    /// MOA 124
+    pub amount: Option<f64>,
+    /// MOA 125
+    pub taxable_amount: Option<f64>,
+}
+
+/// PAT with the DTM, PCD and MOA segments of its group
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PaymentTerms {
+    /// `1` basic, `22` discount, `20` penalty, ...
+    pub terms_type: String,
+    /// Reference the period counts from, e.g. `5` (date of invoice)
+    pub time_reference: Option<String>,
+    /// `D` days, `M` months, ...
+    pub period_type: Option<String>,
+    pub period_count: Option<u32>,
+    /// DTM 13
+    pub due_date: Option<String>,
+    /// PCD 12
+    pub discount_percent: Option<f64>,
+    pub amount: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DespatchDetails {
+    /// DTM 11
+    pub despatch_date: Option<String>,
+    /// DTM 17 (estimated delivery)
+    pub delivery_date: Option<String>,
+    pub packages: Vec<Package>,
+}
+
+/// One level of the packing hierarchy (CPS) with its packaging and contents
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub parent: Option<String>,
+    pub packaging: Vec<Packaging>,
+    /// GIN+BJ serial shipping container codes
+    pub sscc: Vec<String>,
+    /// LIN numbers packed at this level
+    pub line_numbers: Vec<u64>,
+}
+
+/// PAC
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Packaging {
+    pub count: Option<u64>,
+    /// `CT` carton, `PX` pallet, ...
+    pub package_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrderResponseDetails {
+    /// BGM message function: `29` accepted, `4` changed, `27` not accepted, ...
+    pub response_code: Option<String>,
+    /// RFF+ON: the order being answered
+    pub order_number: Option<String>,
+    /// DTM 171 of that reference
+    pub order_date: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceCatalogueDetails {
+    /// DTM 194
+    pub validity_start: Option<String>,
+    /// DTM 206
+    pub validity_end: Option<String>,
+}
+
+/// Fills the parties, references and dates of a document and its [`MessageDetails`]
+/// from its segments.
+///
+/// Works on the tokenized segment rather than on registry labels, so the result does
+/// not change with the translation dictionary of the message version.
+#[derive(Debug, Default)]
+pub struct DetailsCollector {
+    open: Option<OpenGroup>,
+    /// Trigger tag of the open top-level group (`NAD` for SG2, `RFF` for SG1, ...)
+    top: Option<String>,
+    /// Qualifier of the NAD whose group is open
+    party: Option<String>,
+}
+
+/// Document level group whose MOA/DTM/PCD segments belong to the last entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpenGroup {
+    Tax,
+    PaymentTerms,
+}
+
+impl OpenGroup {
+    fn contains(self, tag: &str) -> bool {
+        match self {
+            OpenGroup::Tax => matches!(tag, "MOA" | "LOC"),
+            OpenGroup::PaymentTerms => matches!(tag, "DTM" | "PCD" | "MOA"),
+        }
+    }
+}
+
+impl DetailsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the reasons DTM values of the message could not be normalised; their raw
+    /// text is kept.
+    pub fn segment(
+        &mut self,
+        view: &SegmentView<'_, '_>,
+        placement: &Placement,
+        delimiters: &Delimiters,
+        doc: Option<&mut StreamingDocument>,
+    ) -> Vec<String> {
+        let tag = view.tag();
+        let open = self.open.filter(|group| group.contains(&tag));
+        self.open = open;
+        match placement {
+            Placement::Message => self.top = None,
+            Placement::Group {
+                depth: 1,
+                in_line: false,
+                opens: Some(_),
+            } => self.top = Some(tag.to_string()),
+            _ => {}
+        }
+
+        let Some(doc) = doc else {
+            return Vec::new();
+        };
+        let mut failures = Vec::new();
+        let date = (tag == "DTM")
+            .then(|| Some(view.value(1, 1)).filter(|v| !v.is_empty()))
+            .flatten()
+            .map(|raw| {
+                let format = view.value(1, 2);
+                parse_dtm(&raw, &format).unwrap_or_else(|reason| {
+                    failures.push(reason);
+                    DateTimeValue {
+                        value: raw.to_string(),
+                        raw: raw.to_string(),
+                        format: Some(format.into_owned()).filter(|f| !f.is_empty()),
+                    }
+                })
+            });
+        if placement.in_document() {
+            self.common(view, placement, date.as_ref(), doc);
+        }
+        let Some(details) = doc.details.as_mut() else {
+            return failures;
+        };
+        let date = date.map(|date| date.value);
+        let number = |element: usize, component: usize| -> Option<f64> {
+            delimiters
+                .normalize_decimal(&view.value(element, component))
+                .parse()
+                .ok()
+        };
+        let text = |element: usize, component: usize| -> Option<String> {
+            Some(view.value(element, component).into_owned()).filter(|v| !v.is_empty())
+        };
+        let qualifier = view.value(1, 0);
+
+        if tag == "LIN" {
+            if let MessageDetails::DespatchAdvice(despatch) = details {
+                if let Some(package) = despatch.packages.last_mut() {
+                    package
+                        .line_numbers
+                        .extend(view.value(1, 0).parse::<u64>().ok());
+                }
+            }
+            return failures;
+        }
+        if !placement.in_document() {
+            return failures;
+        }
+
+        match details {
+            MessageDetails::Invoice(invoice) => match tag.as_ref() {
+                "TAX" => {
+                    invoice.taxes.push(Tax {
+                        tax_type: view.value(2, 0).into_owned(),
+                        category: text(6, 0),
+                        rate: number(5, 3),
+                        ..Default::default()
+                    });
+                    self.open = Some(OpenGroup::Tax);
+                }
+                "PAT" => {
+                    invoice.payment_terms.push(PaymentTerms {
+                        terms_type: qualifier.into_owned(),
+                        time_reference: text(3, 0),
+                        period_type: text(3, 2),
+                        period_count: view.value(3, 3).parse().ok(),
+                        ..Default::default()
+                    });
+                    self.open = Some(OpenGroup::PaymentTerms);
+                }
+                "MOA" => {
+                    let Some(amount) = number(1, 1) else {
+                        return failures;
+                    };
+                    match (open, qualifier.as_ref()) {
+                        (Some(OpenGroup::Tax), "124" | "125") => {
+                            if let Some(tax) = invoice.taxes.last_mut() {
+                                if qualifier == "124" {
+                                    tax.amount = Some(amount);
+                                } else {
+                                    tax.taxable_amount = Some(amount);
+                                }
+                            }
+                        }
+                        (Some(OpenGroup::PaymentTerms), _) => {
+                            if let Some(terms) = invoice.payment_terms.last_mut() {
+                                terms.amount = Some(amount);
+                            }
+                        }
+                        (_, code) => {
+                            match code {
+                                "77" => invoice.total_amount = Some(amount),
+                                "86" => {
+                                    invoice.total_amount.get_or_insert(amount);
+                                }
+                                "79" => invoice.line_total = Some(amount),
+                                "125" => invoice.taxable_amount = Some(amount),
+                                "176" => invoice.tax_amount = Some(amount),
+                                "124" => {
+                                    invoice.tax_amount.get_or_insert(amount);
+                                }
+                                _ => {}
+                            }
+                            invoice.amounts.insert(code.to_string(), amount);
+                        }
+                    }
+                }
+                "DTM" if open == Some(OpenGroup::PaymentTerms) && qualifier == "13" => {
+                    if let Some(terms) = invoice.payment_terms.last_mut() {
+                        terms.due_date = date;
+                    }
+                }
+                "PCD" if open == Some(OpenGroup::PaymentTerms) && qualifier == "12" => {
+                    if let Some(terms) = invoice.payment_terms.last_mut() {
+                        terms.discount_percent = number(1, 1);
+                    }
+                }
+                _ => {}
+            },
+            MessageDetails::DespatchAdvice(despatch) => match tag.as_ref() {
+                "DTM" => match qualifier.as_ref() {
+                    "11" => despatch.despatch_date = date,
+                    "17" => despatch.delivery_date = date,
+                    _ => {}
+                },
+                "CPS" => despatch.packages.push(Package {
+                    id: qualifier.into_owned(),
+                    parent: text(2, 0),
+                    ..Default::default()
+                }),
+                "PAC" => {
+                    if let Some(package) = despatch.packages.last_mut() {
+                        package.packaging.push(Packaging {
+                            count: view.value(1, 0).parse().ok(),
+                            package_type: text(3, 0),
+                        });
+                    }
+                }
+                "GIN" if qualifier == "BJ" => {
+                    if let Some(package) = despatch.packages.last_mut() {
+                        package
+                            .sscc
+                            .extend((2..view.element_count()).filter_map(|e| text(e, 0)));
+                    }
+                }
+                _ => {}
+            },
+            MessageDetails::OrderResponse(response) => match tag.as_ref() {
+                "BGM" => response.response_code = text(3, 0),
+                "RFF" if qualifier == "ON" => response.order_number = text(1, 1),
+                "DTM" if qualifier == "171" => response.order_date = date,
+                _ => {}
+            },
+            MessageDetails::PriceCatalogue(catalogue) if tag == "DTM" => match qualifier.as_ref() {
+                "194" => catalogue.validity_start = date,
+                "206" => catalogue.validity_end = date,
+                _ => {}
+            },
+            MessageDetails::PriceCatalogue(_) => {}
+        }
+        failures
+    }
+
+    /// Parties, references and dates shared by all message types
+    fn common(
+        &mut self,
+        view: &SegmentView<'_, '_>,
+        placement: &Placement,
+        date: Option<&DateTimeValue>,
+        doc: &mut StreamingDocument,
+    ) {
+        let text = |element: usize, component: usize| -> Option<String> {
+            Some(view.value(element, component).into_owned()).filter(|v| !v.is_empty())
+        };
+        let joined = |element: usize| -> Option<String> {
+            let parts: Vec<_> = view.components(element).filter(|c| !c.is_empty()).collect();
+            Some(parts.join(" ")).filter(|v| !v.is_empty())
+        };
+        let qualifier = view.value(1, 0);
+        if qualifier.is_empty() {
+            return;
+        }
+        let nested = matches!(placement, Placement::Group { depth, .. } if *depth > 1);
+
+        match view.tag().as_ref() {
+            "NAD" => {
+                doc.parties
+                    .entry(qualifier.to_string())
+                    .or_default()
+                    .push(Party {
+                        id: text(2, 0),
+                        agency: text(2, 2),
+                        name: joined(4).or_else(|| joined(3)),
+                        street: joined(5),
+                        city: text(6, 0),
+                        region: text(7, 0),
+                        postal_code: text(8, 0),
+                        country: text(9, 0),
+                        references: Default::default(),
+                    });
+                self.party = Some(qualifier.into_owned());
+            }
+            "RFF" => {
+                let Some(value) = text(1, 1) else {
+                    return;
+                };
+                let party = self
+                    .party
+                    .as_ref()
+                    .filter(|_| nested && self.top.as_deref() == Some("NAD"))
+                    .and_then(|party| doc.parties.get_mut(party))
+                    .and_then(|parties| parties.last_mut());
+                match party {
+                    Some(party) => party.references.insert(qualifier.into_owned(), value),
+                    None => doc.references.insert(qualifier.into_owned(), value),
+                };
+            }
+            "DTM" => {
+                // Dates of other groups (payment terms, transport, ...) stay there
+                let message_level =
+                    matches!(placement, Placement::Unstructured | Placement::Message)
+                        || self.top.as_deref() == Some("RFF");
+                if let Some(date) = date.filter(|_| message_level) {
+                    doc.dates.insert(qualifier.into_owned(), date.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+}
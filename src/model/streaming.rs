@@ -1,22 +1,66 @@
+use crate::dedupe::SeenMessage;
+use crate::messages::MessageDetails;
+use crate::parser::datetime::DateTimeValue;
+use crate::parser::segment_reader::SourcePosition;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Serialize, Deserialize)]
 pub struct StreamingDocument {
     pub interchange_id: String,
     pub sender: String,
     pub receiver: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<FunctionalGroup>,
+    /// Document name code of the BGM (`220` order, `380` invoice, ...); the message
+    /// type is in `message_type`
     pub doc_type: String,
+    /// Message type of the UNH, e.g. `INVOIC`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message_type: String,
     pub document_number: String,
     pub document_date: Option<String>,
     pub requested_delivery_date: Option<String>,
     pub currency: String,
     pub buyer: Option<String>,
     pub seller: Option<String>,
+    /// Every NAD of the message, by party qualifier (`BY`, `SU`, `DP`, `IV`, ...), in
+    /// message order when a qualifier repeats (several delivery points, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parties: BTreeMap<String, Vec<Party>>,
+    /// Message level RFF values by reference qualifier (`ON`, `CT`, `VN`, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub references: BTreeMap<String, String>,
+    /// Message level DTM values (and those of reference groups) by date qualifier,
+    /// in ISO-8601 with the raw value
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dates: BTreeMap<String, DateTimeValue>,
     pub line_count_check: Option<u64>,
     pub lines: Vec<StreamingLine>,
+    /// Translated fields without a place of their own, as text
     #[serde(default)]
     pub extra: HashMap<String, String>,
+    /// The same fields typed as the translation config declares (numbers as numbers)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub typed_extra: HashMap<String, Value>,
+    /// Segment groups of the message outside the line items (parties, references, ...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<SegmentGroup>,
+    /// Invoice totals, despatch packaging, ... depending on the message type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<MessageDetails>,
+    /// Position of UNH in the input, when requested
+    #[serde(rename = "_source", default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourcePosition>,
+    /// When a message with the same key was first written, for duplicates kept by
+    /// [`DuplicatePolicy::Flag`](crate::dedupe::DuplicatePolicy::Flag)
+    #[serde(
+        rename = "_duplicate",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub duplicate: Option<SeenMessage>,
 }
 
 impl Default for StreamingDocument {
@@ -25,27 +69,164 @@ impl Default for StreamingDocument {
             interchange_id: Default::default(),
             sender: Default::default(),
             receiver: Default::default(),
+            group: Default::default(),
             doc_type: Default::default(),
+            message_type: Default::default(),
             document_number: Default::default(),
             document_date: Default::default(),
             requested_delivery_date: Default::default(),
             currency: "UNKNOWN".to_string(),
             buyer: Default::default(),
             seller: Default::default(),
+            parties: Default::default(),
+            references: Default::default(),
+            dates: Default::default(),
             line_count_check: Default::default(),
             lines: Default::default(),
             extra: Default::default(),
+            typed_extra: Default::default(),
+            groups: Default::default(),
+            details: Default::default(),
+            source: Default::default(),
+            duplicate: Default::default(),
         }
     }
 }
 
+/// Functional group (UNG..UNE) a message was sent in
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionalGroup {
+    pub reference: String,
+    pub message_type: String,
+    pub application_sender: String,
+    pub application_receiver: String,
+    /// Date of preparation as sent in the UNG, e.g. `260121`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub date: String,
+    /// Time of preparation as sent in the UNG, e.g. `1200`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub time: String,
+}
+
+/// NAD: party identification with its name and address
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Party {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Code list responsible agency of the id, e.g. `9` (GS1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agency: Option<String>,
+    /// Party name (C080), or the unstructured name and address (C058) without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub street: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// RFF segments in the party's group (`VA` VAT number, `API`, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub references: BTreeMap<String, String>,
+}
+
+/// One repetition of a segment group, e.g. the SG2 started by `NAD+BY`, with the
+/// fields of its segments and its nested groups
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentGroup {
+    pub group: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: HashMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<SegmentGroup>,
+}
+
 #[derive(Default, Serialize, Deserialize)]
 pub struct StreamingLine {
     pub line_no: u64,
     pub sku: String,
+    /// Primary quantity of `quantities` and its unit
     pub qty: Option<f64>,
     pub uom: Option<String>,
+    /// Primary amount of `amounts`, or primary price of `prices` for lines without MOA
     pub amount: Option<f64>,
+    /// QTY values by quantity qualifier (`21` ordered, `12` despatched, `47` invoiced, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quantities: BTreeMap<String, Quantity>,
+    /// MOA values by amount qualifier (`203` line amount, `66`, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub amounts: BTreeMap<String, f64>,
+    /// PRI values by price qualifier (`AAA` net, `AAB` gross, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prices: BTreeMap<String, Price>,
+    /// Translated fields without a place of their own, as text
     #[serde(default)]
     pub extra: HashMap<String, String>,
+    /// The same fields typed as the translation config declares (numbers as numbers)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub typed_extra: HashMap<String, Value>,
+    /// Segment groups nested in the line item group (prices, references, ...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<SegmentGroup>,
+    /// Position of LIN in the input, when requested
+    #[serde(rename = "_source", default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourcePosition>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Quantity {
+    pub value: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Price {
+    pub value: f64,
+    /// Price type code, e.g. `CT` (contract), `CA` (catalogue)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_type: Option<String>,
+    /// Quantity the price applies to (unit price basis)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub basis: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+impl StreamingLine {
+    /// Records a QTY. `qty`/`uom` take the first of the `primary` qualifiers the line
+    /// has, or the last quantity seen when it has none of them.
+    pub fn add_quantity(&mut self, qualifier: &str, quantity: Quantity, primary: &[String]) {
+        self.quantities.insert(qualifier.to_string(), quantity);
+        let picked = pick(&self.quantities, primary, qualifier);
+        self.qty = picked.map(|q| q.value);
+        self.uom = picked.and_then(|q| q.unit.clone());
+    }
+
+    /// Records a MOA. `amount` takes the first of the `primary` qualifiers the line has,
+    /// or the last amount seen when it has none of them.
+    pub fn add_amount(&mut self, qualifier: &str, amount: f64, primary: &[String]) {
+        self.amounts.insert(qualifier.to_string(), amount);
+        self.amount = pick(&self.amounts, primary, qualifier).copied();
+    }
+
+    /// Records a PRI. Lines without a MOA take `amount` from their prices, picked as
+    /// in [`StreamingLine::add_amount`].
+    pub fn add_price(&mut self, qualifier: &str, price: Price, primary: &[String]) {
+        self.prices.insert(qualifier.to_string(), price);
+        if self.amounts.is_empty() {
+            self.amount = pick(&self.prices, primary, qualifier).map(|p| p.value);
+        }
+    }
+}
+
+fn pick<'a, T>(values: &'a BTreeMap<String, T>, primary: &[String], last: &str) -> Option<&'a T> {
+    primary
+        .iter()
+        .find_map(|qualifier| values.get(qualifier))
+        .or_else(|| values.get(last))
 }
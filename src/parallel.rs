@@ -0,0 +1,397 @@
+use crate::core::{EdifactProcessor, Event};
+use crate::error::{FileReduceError, Result};
+use crate::parser::edifact::parse_segment_view;
+use crate::parser::segment::Segment;
+use crate::parser::segment_reader::{ReadSegment, ReplayedSegments, SegmentReader};
+use crate::parser::tokenizer::{Delimiters, SegmentTokenizer};
+use crate::validation::EnvelopeValidator;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::BufRead;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Messages handed to the workers, or finished and waiting to be returned, per thread
+const WINDOW_PER_THREAD: usize = 8;
+
+/// A message cut out of the input: its envelope (UNB, UNG) and its segments, with
+/// their positions, in input order
+struct Job {
+    sequence: u64,
+    segments: Vec<ReadSegment>,
+    delimiters: Delimiters,
+}
+
+type Done = (u64, Result<Vec<Event>>);
+
+/// Events of an EDIFACT input parsed on a pool of worker threads.
+///
+/// The calling thread reads segments, checks the envelope (UNB/UNG/UNE/UNZ counts and
+/// references) and cuts the input at message boundaries; each message runs through
+/// the same state machine as [`EdifactProcessor::documents`] on a worker, with its
+/// interchange and group segments replayed in front of it. Events come back in input
+/// order, or as messages finish when unordered.
+pub struct ParallelEvents<R> {
+    segments: SegmentReader<R>,
+    tokenizer: SegmentTokenizer,
+    raw: String,
+    validator: EnvelopeValidator,
+    /// UNB and UNG of the interchange and group being read
+    envelope: Vec<ReadSegment>,
+    /// Segments of the message being read
+    message: Vec<ReadSegment>,
+    jobs: Option<Sender<Job>>,
+    done: Receiver<Done>,
+    workers: Vec<JoinHandle<()>>,
+    ordered: bool,
+    window: usize,
+    /// Sequence number of the next job (or envelope report)
+    next: u64,
+    /// Sequence number of the next result to return, when ordered
+    returned: u64,
+    in_flight: usize,
+    finished: BTreeMap<u64, Vec<Event>>,
+    output: VecDeque<Event>,
+    eof: bool,
+    failed: bool,
+}
+
+impl<R: BufRead> ParallelEvents<R> {
+    /// Parses `reader` on `threads` workers (at least one), each with a copy of the
+    /// settings and dictionary of `processor`
+    pub fn new(processor: &EdifactProcessor, reader: R, threads: usize, ordered: bool) -> Self {
+        let threads = threads.max(1);
+        let segments = match processor.charset() {
+            Some(charset) => SegmentReader::with_charset(reader, charset),
+            None => SegmentReader::new(reader),
+        };
+        let (jobs, queue) = channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        let (results, done) = channel();
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let results = results.clone();
+                let mut processor = processor.worker();
+                thread::spawn(move || loop {
+                    let job = queue.lock().unwrap().recv();
+                    let Ok(job) = job else {
+                        break;
+                    };
+                    let sequence = job.sequence;
+                    let events = catch_unwind(AssertUnwindSafe(|| run(&mut processor, job)))
+                        .unwrap_or_else(|_| {
+                            Err(FileReduceError::Parse(format!(
+                                "worker failed on message {}",
+                                sequence
+                            )))
+                        });
+                    if results.send((sequence, events)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            segments,
+            tokenizer: SegmentTokenizer::new(),
+            raw: String::new(),
+            validator: EnvelopeValidator::new(),
+            envelope: Vec::new(),
+            message: Vec::new(),
+            jobs: Some(jobs),
+            done,
+            workers,
+            ordered,
+            window: threads * WINDOW_PER_THREAD,
+            next: 0,
+            returned: 0,
+            in_flight: 0,
+            finished: BTreeMap::new(),
+            output: VecDeque::new(),
+            eof: false,
+            failed: false,
+        }
+    }
+
+    /// Reads one segment, dispatching the message it completes
+    fn read(&mut self) -> Result<()> {
+        let error = match self.segments.read_segment(&mut self.raw) {
+            Ok(true) => None,
+            Ok(false) => {
+                self.eof = true;
+                self.dispatch();
+                self.validator.finish(None);
+                self.envelope_reports();
+                self.jobs = None;
+                return Ok(());
+            }
+            Err(FileReduceError::Io(e)) => return Err(FileReduceError::Io(e)),
+            // The worker fails with it, or sets its message aside when tolerant
+            Err(e) => Some(e.reason()),
+        };
+        if error.is_none() && self.raw.trim().is_empty() {
+            return Ok(());
+        }
+        let position = self.segments.position();
+        self.validator.segment(position);
+        let segment = ReadSegment {
+            text: self.raw.clone(),
+            position,
+            error,
+        };
+        let delimiters = *self.segments.delimiters();
+        let view = self.tokenizer.tokenize(self.raw.as_bytes(), &delimiters);
+        let tag = view.tag();
+        if segment.error.is_some() || !tag.starts_with("UN") {
+            self.message.push(segment);
+            return Ok(());
+        }
+
+        match parse_segment_view(&view, None) {
+            Segment::UNB(_, _, reference) => {
+                self.validator.begin_interchange(&reference);
+                self.dispatch();
+                self.envelope = vec![segment];
+            }
+            Segment::UNG(_, _, _, reference) => {
+                self.validator.begin_group(&reference);
+                self.dispatch();
+                self.envelope.truncate(1);
+                self.envelope.push(segment);
+            }
+            Segment::UNH(reference, _) => {
+                self.validator.begin_message(&reference, None);
+                self.dispatch();
+                self.message.push(segment);
+            }
+            Segment::UNT(count, reference) => {
+                self.validator.end_message(Some((&count, &reference)), None);
+                self.message.push(segment);
+                self.dispatch();
+            }
+            Segment::UNE(count, reference) => {
+                self.validator.end_group(&count, &reference);
+                self.dispatch();
+                self.envelope.truncate(1);
+            }
+            Segment::UNZ(count, reference) => {
+                self.validator.end_interchange(&count, &reference);
+                self.dispatch();
+                self.envelope.clear();
+            }
+            _ => self.message.push(segment),
+        }
+        self.envelope_reports();
+        Ok(())
+    }
+
+    /// Hands the message read so far to the workers
+    fn dispatch(&mut self) {
+        if self.message.is_empty() {
+            return;
+        }
+        let mut segments = self.envelope.clone();
+        segments.append(&mut self.message);
+        let job = Job {
+            sequence: self.next,
+            segments,
+            delimiters: *self.segments.delimiters(),
+        };
+        self.next += 1;
+        if let Some(jobs) = &self.jobs {
+            // The workers only stop once `jobs` is dropped
+            let _ = jobs.send(job);
+            self.in_flight += 1;
+        }
+    }
+
+    /// Queues the interchange reports completed so far. Message reports come from the
+    /// workers, which see the message content.
+    fn envelope_reports(&mut self) {
+        let reports: Vec<Event> = self
+            .validator
+            .take_reports()
+            .into_iter()
+            .filter(|report| report.message_reference.is_none())
+            .map(Event::Report)
+            .collect();
+        if !reports.is_empty() {
+            self.finished.insert(self.next, reports);
+            self.next += 1;
+        }
+    }
+
+    /// Messages and reports read but not yet returned
+    fn pending(&self) -> usize {
+        if self.ordered {
+            (self.next - self.returned) as usize
+        } else {
+            self.in_flight + self.finished.len()
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ParallelEvents<R> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.output.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.failed {
+                return None;
+            }
+            let ready = if self.ordered {
+                self.finished.remove(&self.returned)
+            } else {
+                self.finished.pop_first().map(|(_, events)| events)
+            };
+            if let Some(events) = ready {
+                self.returned += 1;
+                self.output.extend(events);
+                continue;
+            }
+            if !self.eof && self.pending() < self.window {
+                if let Err(e) = self.read() {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+                continue;
+            }
+            if self.in_flight == 0 {
+                return None;
+            }
+            match self.done.recv() {
+                Ok((sequence, Ok(events))) => {
+                    self.in_flight -= 1;
+                    self.finished.insert(sequence, events);
+                }
+                Ok((_, Err(e))) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+                Err(_) => {
+                    self.failed = true;
+                    return Some(Err(FileReduceError::Parse(
+                        "worker threads stopped".to_string(),
+                    )));
+                }
+            }
+        }
+    }
+}
+
+impl<R> Drop for ParallelEvents<R> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Events of one message. The interchange report of the replayed envelope is left
+/// out: the reading thread reports the envelope.
+fn run(processor: &mut EdifactProcessor, job: Job) -> Result<Vec<Event>> {
+    let mut documents =
+        processor.documents_from(ReplayedSegments::new(job.segments, job.delimiters));
+    std::iter::from_fn(|| documents.next_event())
+        .filter(|event| {
+            !matches!(event, Ok(Event::Report(report)) if report.message_reference.is_none())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "UNA:+.? '\
+UNB+UNOC:3+SENDER+RECEIVER+250120:1030+REF1'\
+UNH+1+ORDERS:D:96A:UN'BGM+220+PO1+9'LIN+1++SKU1:EN'QTY+21:5'UNT+5+1'\
+UNH+2+ORDERS:D:96A:UN'BGM+220+PO2+9'LIN+1++SKU2:EN'QTY+21:7'UNT+9+2'\
+UNH+3+ORDERS:D:96A:UN'BGM+220+PO3+9'LIN+1++SKU3:EN'UNT+4+3'\
+UNZ+2+REF1'";
+
+    fn describe(event: Event) -> String {
+        match event {
+            Event::Message { document, .. } => document.document_number,
+            Event::Report(report) => format!(
+                "{}:{:?}",
+                report.message_reference.as_deref().unwrap_or("interchange"),
+                report.status
+            ),
+            Event::Unmapped(segment) => segment.segment,
+            Event::Rejected(letter) => format!("rejected {}", letter.reason),
+        }
+    }
+
+    #[test]
+    fn test_parallel_events_match_sequential() {
+        let mut processor = EdifactProcessor::new();
+        let mut documents = processor.documents(INPUT.as_bytes());
+        let sequential: Vec<String> = std::iter::from_fn(|| documents.next_event())
+            .map(|event| describe(event.unwrap()))
+            .collect();
+        assert_eq!(
+            sequential,
+            [
+                "1:Ok",
+                "PO1",
+                "2:Error",
+                "PO2",
+                "3:Ok",
+                "PO3",
+                "interchange:Error"
+            ]
+        );
+
+        for threads in [1, 2, 4] {
+            let parallel: Vec<String> =
+                ParallelEvents::new(&processor, INPUT.as_bytes(), threads, true)
+                    .map(|event| describe(event.unwrap()))
+                    .collect();
+            assert_eq!(parallel, sequential, "{} threads", threads);
+        }
+
+        let mut unordered: Vec<String> =
+            ParallelEvents::new(&processor, INPUT.as_bytes(), 3, false)
+                .map(|event| describe(event.unwrap()))
+                .collect();
+        let mut expected = sequential.clone();
+        unordered.sort();
+        expected.sort();
+        assert_eq!(unordered, expected);
+    }
+
+    #[test]
+    fn test_parallel_tolerant_matches_sequential() {
+        // The second message holds a byte that is not UTF-8
+        let mut input = b"UNB+UNOY:3+SENDER+RECEIVER+250120:1030+REF1'UNH+1+ORDERS'BGM+220+PO1'UNT+3+1'UNH+2+ORDERS'BGM+220+PO2".to_vec();
+        input.push(0xFF);
+        input.extend_from_slice(b"'UNT+3+2'UNH+3+ORDERS'BGM+220+PO3'UNT+3+3'UNZ+3+REF1'");
+
+        let mut processor = EdifactProcessor::new().with_tolerant(true);
+        let mut documents = processor.documents(input.as_slice());
+        let sequential: Vec<String> = std::iter::from_fn(|| documents.next_event())
+            .map(|event| describe(event.unwrap()))
+            .collect();
+        assert_eq!(sequential[2], "2:Error");
+        assert!(sequential[3].starts_with("rejected invalid UTF-8"));
+
+        let parallel: Vec<String> = ParallelEvents::new(&processor, input.as_slice(), 2, true)
+            .map(|event| describe(event.unwrap()))
+            .collect();
+        assert_eq!(parallel, sequential);
+
+        // Without tolerance the run stops at the segment
+        let processor = EdifactProcessor::new();
+        let result: Result<Vec<Event>> =
+            ParallelEvents::new(&processor, input.as_slice(), 2, true).collect();
+        assert!(result.is_err());
+    }
+}
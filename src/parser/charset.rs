@@ -0,0 +1,192 @@
+use crate::error::{FileReduceError, Result};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+/// Character set of an interchange, as declared by the UNB syntax identifier.
+///
+/// Segments are decoded with it before tokenizing, so everything downstream
+/// (and the JSON output) is UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Charset {
+    /// UNOA/UNOB: 7-bit subsets of ISO 646
+    Ascii,
+    /// UNOC: ISO 8859-1 (Latin-1)
+    Latin1,
+    /// UNOD: ISO 8859-2 (Latin-2)
+    Latin2,
+    /// UNOY/UNOW: ISO 10646 in UTF-8. Also used when no UNB has been seen.
+    #[default]
+    Utf8,
+}
+
+impl Charset {
+    /// Maps a syntax identifier (`UNOC` in `UNB+UNOC:3+...`) to its charset
+    pub fn from_syntax_identifier(identifier: &str) -> Option<Self> {
+        match identifier.trim().to_ascii_uppercase().as_str() {
+            "UNOA" | "UNOB" => Some(Charset::Ascii),
+            "UNOC" => Some(Charset::Latin1),
+            "UNOD" => Some(Charset::Latin2),
+            "UNOY" | "UNOW" => Some(Charset::Utf8),
+            _ => None,
+        }
+    }
+
+    /// Decodes raw segment bytes into UTF-8. Single-byte charsets never fail; ASCII and
+    /// UTF-8 input must be valid UTF-8.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>> {
+        match self {
+            Charset::Ascii | Charset::Utf8 => std::str::from_utf8(bytes)
+                .map(Cow::Borrowed)
+                .map_err(|e| invalid_data(format!("invalid {} data: {}", self, e))),
+            Charset::Latin1 => Ok(decode_single_byte(bytes, |b| b as char)),
+            Charset::Latin2 => Ok(decode_single_byte(bytes, latin2_char)),
+        }
+    }
+
+    /// Like [`Charset::decode`], into `out`. UTF-8 input is not copied: `bytes` becomes
+    /// the text and the previous buffer of `out` is handed back for reuse. When the data
+    /// is invalid, `out` holds it with the offending bytes replaced.
+    pub fn decode_into(&self, bytes: Vec<u8>, out: &mut String) -> Result<Vec<u8>> {
+        out.clear();
+        match self {
+            Charset::Ascii | Charset::Utf8 => match String::from_utf8(bytes) {
+                Ok(text) => Ok(std::mem::replace(out, text).into_bytes()),
+                Err(e) => {
+                    out.push_str(&String::from_utf8_lossy(e.as_bytes()));
+                    Err(invalid_data(format!(
+                        "invalid {} data: {}",
+                        self,
+                        e.utf8_error()
+                    )))
+                }
+            },
+            Charset::Latin1 => {
+                out.extend(bytes.iter().map(|&b| b as char));
+                Ok(bytes)
+            }
+            Charset::Latin2 => {
+                out.extend(bytes.iter().map(|&b| latin2_char(b)));
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Charset::Ascii => "ASCII",
+            Charset::Latin1 => "ISO-8859-1",
+            Charset::Latin2 => "ISO-8859-2",
+            Charset::Utf8 => "UTF-8",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    /// Accepts syntax identifiers (`UNOC`) as well as charset names (`latin1`, `iso-8859-2`)
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(charset) = Charset::from_syntax_identifier(s) {
+            return Ok(charset);
+        }
+        match s.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "ascii" | "us-ascii" => Ok(Charset::Ascii),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Ok(Charset::Latin1),
+            "latin2" | "latin-2" | "iso-8859-2" | "iso8859-2" => Ok(Charset::Latin2),
+            "utf8" | "utf-8" => Ok(Charset::Utf8),
+            _ => Err(format!("unknown charset: {}", s)),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> FileReduceError {
+    FileReduceError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        message,
+    ))
+}
+
+fn decode_single_byte(bytes: &[u8], map: impl Fn(u8) -> char) -> Cow<'_, str> {
+    if bytes.is_ascii() {
+        // ASCII is valid UTF-8 as is
+        return Cow::Borrowed(std::str::from_utf8(bytes).unwrap_or_default());
+    }
+    Cow::Owned(bytes.iter().map(|&b| map(b)).collect())
+}
+
+/// ISO 8859-2 code points for 0xA0..=0xFF; the lower half matches Latin-1
+const LATIN2_HIGH: [char; 96] = [
+    '\u{00A0}', 'Ą', '˘', 'Ł', '¤', 'Ľ', 'Ś', '§', '¨', 'Š', 'Ş', 'Ť', 'Ź', '\u{00AD}', 'Ž', 'Ż',
+    '°', 'ą', '˛', 'ł', '´', 'ľ', 'ś', 'ˇ', '¸', 'š', 'ş', 'ť', 'ź', '˝', 'ž', 'ż', 'Ŕ', 'Á', 'Â',
+    'Ă', 'Ä', 'Ĺ', 'Ć', 'Ç', 'Č', 'É', 'Ę', 'Ë', 'Ě', 'Í', 'Î', 'Ď', 'Đ', 'Ń', 'Ň', 'Ó', 'Ô', 'Ő',
+    'Ö', '×', 'Ř', 'Ů', 'Ú', 'Ű', 'Ü', 'Ý', 'Ţ', 'ß', 'ŕ', 'á', 'â', 'ă', 'ä', 'ĺ', 'ć', 'ç', 'č',
+    'é', 'ę', 'ë', 'ě', 'í', 'î', 'ď', 'đ', 'ń', 'ň', 'ó', 'ô', 'ő', 'ö', '÷', 'ř', 'ů', 'ú', 'ű',
+    'ü', 'ý', 'ţ', '˙',
+];
+
+fn latin2_char(byte: u8) -> char {
+    if byte < 0xA0 {
+        byte as char
+    } else {
+        LATIN2_HIGH[(byte - 0xA0) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syntax_identifiers() {
+        assert_eq!(
+            Charset::from_syntax_identifier("UNOA"),
+            Some(Charset::Ascii)
+        );
+        assert_eq!(
+            Charset::from_syntax_identifier("UNOC"),
+            Some(Charset::Latin1)
+        );
+        assert_eq!(
+            Charset::from_syntax_identifier("UNOD"),
+            Some(Charset::Latin2)
+        );
+        assert_eq!(Charset::from_syntax_identifier("UNOW"), Some(Charset::Utf8));
+        assert_eq!(Charset::from_syntax_identifier("UNOX"), None);
+
+        assert_eq!("unoc".parse(), Ok(Charset::Latin1));
+        assert_eq!("ISO-8859-2".parse(), Ok(Charset::Latin2));
+        assert_eq!("utf8".parse(), Ok(Charset::Utf8));
+        assert!("ebcdic".parse::<Charset>().is_err());
+    }
+
+    #[test]
+    fn test_decode_single_byte_charsets() {
+        assert_eq!(Charset::Latin1.decode(b"M\xdcLLER").unwrap(), "MÜLLER");
+        assert_eq!(Charset::Latin2.decode(b"\xa3\xf3d\xbc").unwrap(), "Łódź");
+        assert!(matches!(
+            Charset::Latin1.decode(b"PLAIN").unwrap(),
+            Cow::Borrowed("PLAIN")
+        ));
+
+        assert_eq!(Charset::Utf8.decode("Łódź".as_bytes()).unwrap(), "Łódź");
+        assert!(Charset::Utf8.decode(b"M\xdcLLER").is_err());
+
+        let mut out = String::from("previous");
+        let spare = Charset::Utf8.decode_into("Łódź".into(), &mut out).unwrap();
+        assert_eq!(out, "Łódź");
+        // The old buffer comes back emptied, keeping its capacity
+        assert!(spare.is_empty() && spare.capacity() >= "previous".len());
+        Charset::Latin1
+            .decode_into(b"M\xdcLLER".to_vec(), &mut out)
+            .unwrap();
+        assert_eq!(out, "MÜLLER");
+        assert!(Charset::Ascii
+            .decode_into(b"M\xdcLLER".to_vec(), &mut out)
+            .is_err());
+        assert_eq!(out, "M\u{FFFD}LLER");
+    }
+}
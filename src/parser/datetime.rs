@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+
+/// A DTM value in ISO-8601 with the text it was read from
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DateTimeValue {
+    /// `2025-01-20`, `2025-01-20T10:30:00`, `2025-01-01/2025-01-31`, `2025-W03`, ...
+    pub value: String,
+    pub raw: String,
+    /// Format code of the DTM (`102`, `203`, ...), when it declares one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+/// Normalises a DTM value (`DTM+137:20250120:102`) to ISO-8601.
+///
+/// Without a format code the layout is guessed from the length (8 digits as 102, 12 as
+/// 203, 14 as 204, ranges of those as 718/719). Unsupported codes and values that are
+/// not valid dates in their format are errors; callers keep the raw value.
+pub fn parse_dtm(raw: &str, format: &str) -> Result<DateTimeValue, String> {
+    let value = normalize(raw, format).ok_or_else(|| match format {
+        "" => format!("DTM value '{}' is not a date", raw),
+        _ => format!("DTM value '{}' is not a valid {} date", raw, format),
+    })?;
+    Ok(DateTimeValue {
+        value,
+        raw: raw.to_string(),
+        format: Some(format.to_string()).filter(|f| !f.is_empty()),
+    })
+}
+
+/// ISO form of a DTM value, or the raw text when it cannot be normalised
+pub fn normalize_or_raw(raw: &str, format: &str) -> String {
+    normalize(raw, format).unwrap_or_else(|| raw.to_string())
+}
+
+/// EDIFACT value and format code (`20250120:102`) for an ISO-8601 date, date-time or
+/// date range; other values are returned as they are.
+///
+/// A range of dates is a `718`. A range with a time on either side is a `719`, both
+/// sides to the minute and a bare date at 00:00; ranges with seconds, which `719`
+/// cannot carry, are returned as they are.
+pub fn format_dtm(value: &str) -> String {
+    let compact = |part: &str| -> String { part.chars().filter(char::is_ascii_digit).collect() };
+    let code = |digits: &str| match digits.len() {
+        8 => Some("102"),
+        14 if digits.ends_with("00") => Some("203"),
+        14 => Some("204"),
+        _ => None,
+    };
+    // CCYYMMDDHHMM of a 102 or 203 value
+    let to_minute = |digits: &str| format!("{:0<12}", digits.get(..12).unwrap_or(digits));
+    if let Some((start, end)) = value.split_once('/') {
+        let (start, end) = (compact(start), compact(end));
+        return match (code(&start), code(&end)) {
+            (Some("102"), Some("102")) => format!("{}-{}:718", start, end),
+            (Some("102" | "203"), Some("102" | "203")) => {
+                format!("{}-{}:719", to_minute(&start), to_minute(&end))
+            }
+            _ => value.to_string(),
+        };
+    }
+    if normalize(&compact(value), "").as_deref() != Some(value) {
+        return value.to_string();
+    }
+    let digits = compact(value);
+    match code(&digits) {
+        Some("203") => format!("{}:203", &digits[..12]),
+        Some(code) => format!("{}:{}", digits, code),
+        None => value.to_string(),
+    }
+}
+
+fn normalize(raw: &str, format: &str) -> Option<String> {
+    let raw = raw.trim();
+    let format = match format {
+        "" => guess_format(raw)?,
+        code => code,
+    };
+    match format {
+        // DDMMYY
+        "2" if raw.len() == 6 && digits(raw) => date(&century(&raw[4..6])?, &raw[2..4], &raw[0..2]),
+        // YYMMDD
+        "101" if raw.len() == 6 && digits(raw) => {
+            date(&century(&raw[0..2])?, &raw[2..4], &raw[4..6])
+        }
+        "102" => date_time(raw, 8),
+        // YYMMDDHHMM
+        "201" if raw.len() == 10 && digits(raw) => {
+            let full = format!("{}{}", century(&raw[0..2])?, &raw[2..]);
+            date_time(&full, 12)
+        }
+        "203" => date_time(raw, 12),
+        "204" => date_time(raw, 14),
+        // CCYY, CCYYMM
+        "602" if raw.len() == 4 && digits(raw) => Some(raw.to_string()),
+        "610" if raw.len() == 6 && digits(raw) => {
+            let month: u32 = raw[4..6].parse().ok()?;
+            (1..=12)
+                .contains(&month)
+                .then(|| format!("{}-{}", &raw[0..4], &raw[4..6]))
+        }
+        // CCYYWW
+        "616" if raw.len() == 6 && digits(raw) => {
+            let week: u32 = raw[4..6].parse().ok()?;
+            (1..=53)
+                .contains(&week)
+                .then(|| format!("{}-W{}", &raw[0..4], &raw[4..6]))
+        }
+        "718" => interval(raw, 8),
+        "719" => interval(raw, 12),
+        _ => None,
+    }
+}
+
+fn guess_format(raw: &str) -> Option<&'static str> {
+    match raw.len() {
+        8 => Some("102"),
+        12 => Some("203"),
+        14 => Some("204"),
+        17 => Some("718"),
+        25 => Some("719"),
+        _ => None,
+    }
+}
+
+/// `CCYYMMDD`, `CCYYMMDDHHMM` or `CCYYMMDDHHMMSS` (by `len`)
+fn date_time(raw: &str, len: usize) -> Option<String> {
+    if raw.len() != len || !digits(raw) {
+        return None;
+    }
+    let day = date(&raw[0..4], &raw[4..6], &raw[6..8])?;
+    if len == 8 {
+        return Some(day);
+    }
+    let hour: u32 = raw[8..10].parse().ok()?;
+    let minute: u32 = raw[10..12].parse().ok()?;
+    let second: u32 = if len == 14 {
+        raw[12..14].parse().ok()?
+    } else {
+        0
+    };
+    (hour < 24 && minute < 60 && second < 60)
+        .then(|| format!("{}T{:02}:{:02}:{:02}", day, hour, minute, second))
+}
+
+fn interval(raw: &str, len: usize) -> Option<String> {
+    let (start, end) = raw.split_once('-')?;
+    Some(format!(
+        "{}/{}",
+        date_time(start, len)?,
+        date_time(end, len)?
+    ))
+}
+
+fn date(year: &str, month: &str, day: &str) -> Option<String> {
+    if !(digits(year) && digits(month) && digits(day)) {
+        return None;
+    }
+    let y: u32 = year.parse().ok()?;
+    let m: u32 = month.parse().ok()?;
+    let d: u32 = day.parse().ok()?;
+    let days = match m {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if y.is_multiple_of(4) && (!y.is_multiple_of(100) || y.is_multiple_of(400)) => 29,
+        2 => 28,
+        _ => return None,
+    };
+    (1..=days)
+        .contains(&d)
+        .then(|| format!("{}-{:02}-{:02}", year, m, d))
+}
+
+/// Two digit years: 00-49 are 20xx, 50-99 19xx
+fn century(year: &str) -> Option<String> {
+    let y: u32 = year.parse().ok().filter(|_| year.len() == 2)?;
+    Some(format!("{}{}", if y < 50 { "20" } else { "19" }, year))
+}
+
+fn digits(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dtm_formats() {
+        let iso = |raw: &str, format: &str| parse_dtm(raw, format).map(|d| d.value);
+        assert_eq!(iso("20250120", "102").unwrap(), "2025-01-20");
+        assert_eq!(iso("202501201030", "203").unwrap(), "2025-01-20T10:30:00");
+        assert_eq!(iso("20250120103045", "204").unwrap(), "2025-01-20T10:30:45");
+        assert_eq!(
+            iso("20250101-20250131", "718").unwrap(),
+            "2025-01-01/2025-01-31"
+        );
+        assert_eq!(iso("202503", "616").unwrap(), "2025-W03");
+        assert_eq!(iso("250120", "101").unwrap(), "2025-01-20");
+        assert_eq!(iso("200125", "2").unwrap(), "2025-01-20");
+        // Guessed from the length
+        assert_eq!(iso("20240229", "").unwrap(), "2024-02-29");
+
+        assert!(iso("20250229", "102").is_err());
+        assert!(iso("20251301", "102").is_err());
+        assert!(iso("202501202460", "203").is_err());
+        assert!(iso("202554", "616").is_err());
+        assert!(iso("2025012", "102").is_err());
+        assert!(iso("20250120", "999").is_err());
+        assert!(iso("2501", "101").is_err());
+        assert!(iso("ÉÉÉ", "2").is_err());
+
+        let dtm = parse_dtm("202501201030", "203").unwrap();
+        assert_eq!(dtm.raw, "202501201030");
+        assert_eq!(dtm.format.as_deref(), Some("203"));
+        assert_eq!(normalize_or_raw("TOMORROW", "102"), "TOMORROW");
+
+        assert_eq!(format_dtm("2025-01-20"), "20250120:102");
+        assert_eq!(format_dtm("2025-01-20T10:30:00"), "202501201030:203");
+        assert_eq!(format_dtm("2025-01-20T10:30:45"), "20250120103045:204");
+        assert_eq!(format_dtm("2025-01-01/2025-01-31"), "20250101-20250131:718");
+        assert_eq!(
+            format_dtm("2025-01-01T08:00:00/2025-01-31T18:30:00"),
+            "202501010800-202501311830:719"
+        );
+        // Mixed precision: the date side starts at midnight
+        assert_eq!(
+            format_dtm("2025-01-01/2025-01-31T10:00:00"),
+            "202501010000-202501311000:719"
+        );
+        // Seconds do not fit a 719
+        assert_eq!(
+            format_dtm("2025-01-01T08:00:15/2025-01-31"),
+            "2025-01-01T08:00:15/2025-01-31"
+        );
+        assert_eq!(format_dtm("2025/01"), "2025/01");
+        assert_eq!(format_dtm("20250120"), "20250120");
+    }
+}
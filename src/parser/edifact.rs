@@ -1,119 +1,120 @@
-use super::segment::Segment;
-use super::tokenizer::tokenize_segment;
+use super::segment::{DynamicElements, Segment};
+use super::tokenizer::{Delimiters, SegmentTokenizer, SegmentView};
 use crate::translations::TranslationRegistry;
 
-pub fn parse_segment<'a>(raw: &'a str) -> Segment<'a> {
-    parse_segment_with_registry(raw, None)
+/// Parses one segment with `tokenizer`; the segment borrows its buffers until dropped
+pub fn parse_segment<'a>(tokenizer: &'a mut SegmentTokenizer, raw: &'a str) -> Segment<'a> {
+    parse_segment_with_registry(tokenizer, raw, None)
 }
 
 pub fn parse_segment_with_registry<'a>(
+    tokenizer: &'a mut SegmentTokenizer,
     raw: &'a str,
     registry: Option<&TranslationRegistry>,
 ) -> Segment<'a> {
-    let tokens = tokenize_segment(raw);
-    let segment_code = tokens[0][0];
+    parse_segment_with_delimiters(tokenizer, raw, registry, &Delimiters::default())
+}
+
+/// Parses a segment using the separators declared by the interchange's UNA segment
+pub fn parse_segment_with_delimiters<'a>(
+    tokenizer: &'a mut SegmentTokenizer,
+    raw: &'a str,
+    registry: Option<&TranslationRegistry>,
+    delimiters: &Delimiters,
+) -> Segment<'a> {
+    let view = tokenizer.tokenize(raw.as_bytes(), delimiters);
+    parse_segment_view(&view, registry)
+}
 
-    // Try dynamic parsing if registry provided and segment is defined
-    if let Some(reg) = registry {
-        if let Some(segment_config) = reg.get_segment(segment_code) {
-            let (qualifier, elements) = extract_dynamic_elements(&tokens, &segment_config);
+/// Builds a [`Segment`] from an already tokenized segment. Processors keep one
+/// [`SegmentTokenizer`] alive and call this for every segment to avoid allocating.
+pub fn parse_segment_view<'a>(
+    view: &SegmentView<'a, 'a>,
+    registry: Option<&TranslationRegistry>,
+) -> Segment<'a> {
+    let segment_code = view.tag();
+
+    // Try dynamic parsing if registry provided and segment is defined. Service segments
+    // keep their own variants; the dictionary only describes their elements.
+    if let Some(reg) = registry.filter(|_| !is_service_segment(&segment_code)) {
+        if let Some(use_qualifier) = reg.segment_uses_qualifier(&segment_code) {
+            // The qualifier is the first component of the first element
+            let qualifier = if use_qualifier {
+                view.component(1, 0)
+            } else {
+                None
+            };
             return Segment::Dynamic {
                 code: segment_code,
                 qualifier,
-                elements,
+                elements: DynamicElements::new(*view, use_qualifier),
             };
         }
     }
 
     // Fallback to static parsing
-    match segment_code {
-        "UNB" => Segment::UNB(
-            tokens.get(2).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(3).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(5).and_then(|v| v.get(0)).copied().unwrap_or(""),
-        ),
-        "UNH" => Segment::UNH,
-        "BGM" => Segment::BGM(
-            tokens.get(1).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(2).and_then(|v| v.get(0)).copied().unwrap_or(""),
+    match segment_code.as_ref() {
+        "UNB" => Segment::UNB(view.value(2, 0), view.value(3, 0), view.value(5, 0)),
+        "UNG" => Segment::UNG(
+            view.value(1, 0),
+            view.value(2, 0),
+            view.value(3, 0),
+            view.value(5, 0),
         ),
+        "UNH" => Segment::UNH(view.value(1, 0), view.value(2, 0)),
+        "BGM" => Segment::BGM(view.value(1, 0), view.value(2, 0)),
         "DTM" => {
-            let qual = tokens.get(1).and_then(|v| v.get(0)).copied().unwrap_or("");
-            let val = if tokens.len() >= 3 {
-                tokens.get(2).and_then(|v| v.get(0)).copied().unwrap_or("")
+            let qual = view.value(1, 0);
+            let val = if view.element_count() >= 3 {
+                view.value(2, 0)
             } else {
-                tokens.get(1).and_then(|v| v.get(1)).copied().unwrap_or("")
+                view.value(1, 1)
             };
             Segment::DTM(qual, val)
         }
-        "NAD" => Segment::NAD(
-            tokens.get(1).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(2).and_then(|v| v.get(0)).copied().unwrap_or(""),
-        ),
-        "LIN" => Segment::LIN(
-            tokens.get(1).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(3).and_then(|v| v.get(0)).copied().unwrap_or(""),
-        ),
-        "QTY" => Segment::QTY(
-            tokens.get(1).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(2).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(3).and_then(|v| v.get(0)).copied().unwrap_or(""),
-        ),
-        "MOA" => Segment::MOA(
-            tokens.get(1).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(2).and_then(|v| v.get(0)).copied().unwrap_or(""),
-        ),
-        "CNT" => Segment::CNT(
-            tokens.get(1).and_then(|v| v.get(0)).copied().unwrap_or(""),
-            tokens.get(2).and_then(|v| v.get(0)).copied().unwrap_or(""),
-        ),
+        "NAD" => Segment::NAD(view.value(1, 0), view.value(2, 0)),
+        "LIN" => Segment::LIN(view.value(1, 0), view.value(3, 0)),
+        "QTY" => {
+            // QTY+21:10:PCE (composite) or QTY+21+10+PCE
+            if view.element_count() >= 3 {
+                Segment::QTY(view.value(1, 0), view.value(2, 0), view.value(3, 0))
+            } else {
+                Segment::QTY(view.value(1, 0), view.value(1, 1), view.value(1, 2))
+            }
+        }
+        "MOA" => {
+            // MOA+203:250.00 (composite) or MOA+203+250.00
+            let val = if view.element_count() >= 3 {
+                view.value(2, 0)
+            } else {
+                view.value(1, 1)
+            };
+            Segment::MOA(view.value(1, 0), val)
+        }
+        "CNT" => {
+            // CNT+2:3 (composite) or CNT+2+3
+            let val = if view.element_count() >= 3 {
+                view.value(2, 0)
+            } else {
+                view.value(1, 1)
+            };
+            Segment::CNT(view.value(1, 0), val)
+        }
         "CUX" => Segment::CUX(
-            tokens
-                .get(2)
-                .and_then(|v| v.get(0))
-                .copied()
-                .unwrap_or_else(|| {
-                    // Fallback to second subcomponent of first element (format CUX+2:USD)
-                    tokens.get(1).and_then(|v| v.get(1)).copied().unwrap_or("")
-                }),
+            view.component(2, 0)
+                // Fallback to second subcomponent of first element (format CUX+2:USD)
+                .unwrap_or_else(|| view.value(1, 1)),
         ),
-        "UNT" => Segment::UNT,
-        "UNZ" => Segment::UNZ,
-        other => Segment::Unknown(other),
+        "UNT" => Segment::UNT(view.value(1, 0), view.value(2, 0)),
+        "UNE" => Segment::UNE(view.value(1, 0), view.value(2, 0)),
+        "UNZ" => Segment::UNZ(view.value(1, 0), view.value(2, 0)),
+        _ => Segment::Unknown(segment_code),
     }
 }
 
-fn extract_dynamic_elements<'a>(
-    tokens: &[Vec<&'a str>],
-    segment_config: &crate::translations::SegmentConfig,
-) -> (Option<&'a str>, Vec<Vec<&'a str>>) {
-    let mut qualifier = None;
-    let mut elements = Vec::new();
-
-    if tokens.len() < 2 {
-        return (qualifier, elements);
-    }
-
-    let data_tokens = &tokens[1..];
-
-    for (group_idx, group) in data_tokens.iter().enumerate() {
-        if segment_config.use_qualifier && group_idx == 0 {
-            // First element may have qualifier as first component
-            if !group.is_empty() {
-                qualifier = Some(group[0]);
-                // The rest of components belong to this element
-                let remaining: Vec<&'a str> = group.iter().skip(1).copied().collect();
-                if !remaining.is_empty() {
-                    elements.push(remaining);
-                }
-            }
-        } else {
-            // Whole group is an element
-            elements.push(group.clone());
-        }
-    }
-
-    (qualifier, elements)
+/// Envelope segments, which the processor reads by their fixed layout
+fn is_service_segment(code: &str) -> bool {
+    matches!(code, "UNB" | "UNG" | "UNH" | "UNT" | "UNE" | "UNZ")
 }
 
 #[cfg(test)]
@@ -124,9 +125,10 @@ mod tests {
     #[test]
     fn test_parse_segment_with_registry() {
         let registry = TranslationRegistry::new().unwrap();
+        let mut tokenizer = SegmentTokenizer::new();
 
         // BGM segment defined in translations.json
-        let seg = parse_segment_with_registry("BGM+220+12345", Some(&registry));
+        let seg = parse_segment_with_registry(&mut tokenizer, "BGM+220+12345", Some(&registry));
         match seg {
             Segment::Dynamic {
                 code,
@@ -137,14 +139,15 @@ mod tests {
                 assert_eq!(qualifier, None);
                 // elements should contain the two values
                 assert_eq!(elements.len(), 2);
-                assert_eq!(elements[0][0], "220");
-                assert_eq!(elements[1][0], "12345");
+                assert_eq!(elements.component(1, 0).unwrap(), "220");
+                assert_eq!(elements.component(2, 0).unwrap(), "12345");
+                assert_eq!(elements.component(3, 0), None);
             }
             _ => panic!("Expected Dynamic segment, got {:?}", seg),
         }
 
         // DTM segment with qualifier
-        let seg = parse_segment_with_registry("DTM+137:20240414", Some(&registry));
+        let seg = parse_segment_with_registry(&mut tokenizer, "DTM+137:20240414", Some(&registry));
         match seg {
             Segment::Dynamic {
                 code,
@@ -152,15 +155,40 @@ mod tests {
                 elements,
             } => {
                 assert_eq!(code, "DTM");
-                assert_eq!(qualifier, Some("137"));
+                assert_eq!(qualifier.as_deref(), Some("137"));
                 assert_eq!(elements.len(), 1);
-                assert_eq!(elements[0][0], "20240414");
+                assert_eq!(elements.component(1, 0).unwrap(), "20240414");
+            }
+            _ => panic!("Expected Dynamic segment, got {:?}", seg),
+        }
+
+        // A qualifier standing alone does not count as an element
+        let seg = parse_segment_with_registry(
+            &mut tokenizer,
+            "NAD+BY+5412345000013::9++O?'NEIL",
+            Some(&registry),
+        );
+        match seg {
+            Segment::Dynamic {
+                qualifier,
+                elements,
+                ..
+            } => {
+                assert_eq!(qualifier.as_deref(), Some("BY"));
+                assert_eq!(elements.len(), 3);
+                assert_eq!(elements.component_count(1), 3);
+                assert_eq!(
+                    elements.components(1).collect::<Vec<_>>(),
+                    vec!["5412345000013", "", "9"]
+                );
+                assert!(!elements.is_filled(2));
+                assert_eq!(elements.component(3, 0).unwrap(), "O'NEIL");
             }
             _ => panic!("Expected Dynamic segment, got {:?}", seg),
         }
 
         // Unknown segment (not in translations) should fallback to static parsing
-        let seg = parse_segment_with_registry("UNB+...", Some(&registry));
+        let seg = parse_segment_with_registry(&mut tokenizer, "UNB+...", Some(&registry));
         // UNB is not in translations.json, so should be parsed statically
         match seg {
             Segment::UNB(_, _, _) => {}
@@ -1,6 +1,10 @@
+pub mod charset;
+pub mod datetime;
 pub mod edifact;
 pub mod parser;
 pub mod segment;
+pub mod segment_reader;
+pub mod structure;
 pub mod tokenizer;
 
 #[cfg(feature = "full")]
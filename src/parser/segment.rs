@@ -1,21 +1,99 @@
+use super::tokenizer::SegmentView;
+use std::borrow::Cow;
+
+/// Values borrow from the raw segment unless they contained release characters
 #[derive(Debug)]
 pub enum Segment<'a> {
-    UNB(&'a str, &'a str, &'a str),
-    UNH,
-    BGM(&'a str, &'a str),
-    DTM(&'a str, &'a str),
-    NAD(&'a str, &'a str),
-    LIN(&'a str, &'a str),
-    QTY(&'a str, &'a str, &'a str),
-    MOA(&'a str, &'a str),
-    CNT(&'a str, &'a str),
-    CUX(&'a str),
-    UNT,
-    UNZ,
+    UNB(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>),
+    /// Message group identification, application sender, application receiver, group reference
+    UNG(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>, Cow<'a, str>),
+    /// Message reference number, message type (`ORDERS`)
+    UNH(Cow<'a, str>, Cow<'a, str>),
+    BGM(Cow<'a, str>, Cow<'a, str>),
+    DTM(Cow<'a, str>, Cow<'a, str>),
+    NAD(Cow<'a, str>, Cow<'a, str>),
+    LIN(Cow<'a, str>, Cow<'a, str>),
+    QTY(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>),
+    MOA(Cow<'a, str>, Cow<'a, str>),
+    CNT(Cow<'a, str>, Cow<'a, str>),
+    CUX(Cow<'a, str>),
+    /// Segment count, message reference number
+    UNT(Cow<'a, str>, Cow<'a, str>),
+    /// Message count, group reference number
+    UNE(Cow<'a, str>, Cow<'a, str>),
+    /// Message (or group) count, interchange control reference
+    UNZ(Cow<'a, str>, Cow<'a, str>),
     Dynamic {
-        code: &'a str,
-        qualifier: Option<&'a str>,
-        elements: Vec<Vec<&'a str>>,
+        code: Cow<'a, str>,
+        qualifier: Option<Cow<'a, str>>,
+        elements: DynamicElements<'a>,
     },
-    Unknown(&'a str),
+    Unknown(Cow<'a, str>),
+}
+
+/// Elements of a dictionary segment, numbered as in the dictionary and read straight
+/// from the tokenized segment.
+///
+/// For segments keyed by a qualifier the qualifier is not an element: element 1 is
+/// what follows it in the first composite, or the next element when it stands alone.
+#[derive(Debug, Clone, Copy)]
+pub struct DynamicElements<'a> {
+    view: SegmentView<'a, 'a>,
+    qualified: bool,
+}
+
+impl<'a> DynamicElements<'a> {
+    pub fn new(view: SegmentView<'a, 'a>, qualified: bool) -> Self {
+        Self { view, qualified }
+    }
+
+    /// Number of elements
+    pub fn len(&self) -> usize {
+        self.view
+            .element_count()
+            .saturating_sub(1 + usize::from(self.qualifier_alone()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Component `component` (0-based) of element `element` (1-based)
+    pub fn component(&self, element: usize, component: usize) -> Option<Cow<'a, str>> {
+        let (element, skip) = self.locate(element)?;
+        self.view.component(element, component + skip)
+    }
+
+    pub fn component_count(&self, element: usize) -> usize {
+        self.locate(element).map_or(0, |(element, skip)| {
+            self.view.component_count(element).saturating_sub(skip)
+        })
+    }
+
+    pub fn components(&self, element: usize) -> impl Iterator<Item = Cow<'a, str>> + '_ {
+        (0..self.component_count(element)).filter_map(move |idx| self.component(element, idx))
+    }
+
+    /// Whether element `element` carries a value
+    pub fn is_filled(&self, element: usize) -> bool {
+        self.components(element).any(|value| !value.is_empty())
+    }
+
+    /// The qualifier is the only component of the first element
+    fn qualifier_alone(&self) -> bool {
+        self.qualified && self.view.component_count(1) <= 1
+    }
+
+    /// Element of the view and components to skip for a dictionary element
+    fn locate(&self, element: usize) -> Option<(usize, usize)> {
+        if element == 0 || element > self.len() {
+            return None;
+        }
+        Some(match (self.qualified, self.qualifier_alone()) {
+            (false, _) => (element, 0),
+            (true, true) => (element + 1, 0),
+            (true, false) if element == 1 => (1, 1),
+            (true, false) => (element, 0),
+        })
+    }
 }
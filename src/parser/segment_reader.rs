@@ -0,0 +1,546 @@
+use super::charset::Charset;
+use super::tokenizer::Delimiters;
+use crate::error::{FileReduceError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::BufRead;
+
+/// Where a segment sits in the input file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourcePosition {
+    /// Offset of the segment's first byte, counted from the start of the file
+    pub byte_offset: u64,
+    /// 1-based segment number; UNA is not counted
+    pub segment_index: u64,
+    /// 1-based number of the last UNH seen, 0 before the first message
+    pub message_index: u64,
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "segment {} (byte {}, message {})",
+            self.segment_index, self.byte_offset, self.message_index
+        )
+    }
+}
+
+/// Streams EDIFACT segments out of any `BufRead`, cutting on the segment terminator.
+///
+/// Interchanges may come as one long line of `'`-terminated segments, wrapped at a fixed
+/// width, or one segment per line, so CR/LF are never treated as data. Until the first
+/// segment terminator is seen, a line break also ends a segment when the next line
+/// starts with a segment tag, which keeps newline-separated files without terminators
+/// working. UNA is consumed here and its
+/// separators are exposed through [`SegmentReader::delimiters`]; they hold until the
+/// interchange's UNZ, after which the defaults apply unless another UNA comes first.
+///
+/// Segments are decoded to UTF-8 with the charset named by the syntax identifier of
+/// the current UNB (`UNOC` is ISO 8859-1, ...), unless one was forced with
+/// [`SegmentReader::with_charset`].
+pub struct SegmentReader<R> {
+    reader: R,
+    splitter: Splitter,
+    charset: Charset,
+    forced_charset: bool,
+    position: SourcePosition,
+}
+
+impl<R: BufRead> SegmentReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            splitter: Splitter::default(),
+            charset: Charset::default(),
+            forced_charset: false,
+            position: SourcePosition::default(),
+        }
+    }
+
+    /// Decodes every segment with `charset`, ignoring what UNB declares. For partners
+    /// that send a syntax identifier that does not match their data.
+    pub fn with_charset(reader: R, charset: Charset) -> Self {
+        Self {
+            charset,
+            forced_charset: true,
+            ..Self::new(reader)
+        }
+    }
+
+    /// Separators in effect for the segments read so far
+    pub fn delimiters(&self) -> &Delimiters {
+        &self.splitter.delimiters
+    }
+
+    /// Position of the segment returned by the last [`SegmentReader::read_segment`]
+    pub fn position(&self) -> SourcePosition {
+        self.position
+    }
+
+    /// Charset used to decode the current interchange
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+
+    /// Reads the next segment, without its terminator, into `buf`.
+    /// Returns `Ok(false)` once the input is exhausted. When the segment cannot be
+    /// decoded, `buf` holds it with the offending bytes replaced and reading can go on
+    /// with the next segment.
+    pub fn read_segment(&mut self, buf: &mut String) -> Result<bool> {
+        buf.clear();
+        let next = self.next_raw()?;
+        if let Some((start, una, reason)) = self.splitter.rejected_una.take() {
+            // The interchange goes on with the separators in effect before the UNA
+            buf.push_str(&una);
+            self.position.byte_offset = start;
+            return Err(FileReduceError::Parse(reason).at(self.position));
+        }
+        let Some((start, segment)) = next else {
+            return Ok(false);
+        };
+        self.position.byte_offset = start;
+        self.position.segment_index += 1;
+        if segment.starts_with(b"UNH") {
+            self.position.message_index += 1;
+        }
+        if !self.forced_charset && segment.starts_with(b"UNB") {
+            let identifier = syntax_identifier(&segment, &self.splitter.delimiters);
+            self.charset = Charset::from_syntax_identifier(&identifier).unwrap_or_default();
+        }
+        match self.charset.decode_into(segment, buf) {
+            Ok(spare) => {
+                self.splitter.recycle(spare);
+                Ok(true)
+            }
+            Err(e) => Err(e.at(self.position)),
+        }
+    }
+
+    fn next_raw(&mut self) -> Result<Option<(u64, Vec<u8>)>> {
+        while self.splitter.ready.is_empty() && self.splitter.rejected_una.is_none() {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                self.splitter.finish_segment();
+                break;
+            }
+
+            let mut used = 0;
+            for &byte in available {
+                used += 1;
+                if self.splitter.push(byte) {
+                    break;
+                }
+            }
+            self.reader.consume(used);
+        }
+        Ok(self.splitter.ready.pop_front())
+    }
+}
+
+/// Where the processor takes segments from: a [`SegmentReader`], or segments read
+/// earlier and handed over with [`ReplayedSegments`]
+pub trait SegmentSource {
+    /// Reads the next segment, without its terminator, into `buf`.
+    /// Returns `Ok(false)` once there are no more.
+    fn read_segment(&mut self, buf: &mut String) -> Result<bool>;
+
+    /// Separators in effect for the segments read so far
+    fn delimiters(&self) -> &Delimiters;
+
+    /// Position of the segment returned by the last `read_segment`
+    fn position(&self) -> SourcePosition;
+}
+
+impl<R: BufRead> SegmentSource for SegmentReader<R> {
+    fn read_segment(&mut self, buf: &mut String) -> Result<bool> {
+        SegmentReader::read_segment(self, buf)
+    }
+
+    fn delimiters(&self) -> &Delimiters {
+        SegmentReader::delimiters(self)
+    }
+
+    fn position(&self) -> SourcePosition {
+        SegmentReader::position(self)
+    }
+}
+
+/// A segment as a [`SegmentReader`] returned it: decoded, or the reason it could not be
+/// and its lossy text
+#[derive(Debug, Clone)]
+pub struct ReadSegment {
+    pub text: String,
+    pub position: SourcePosition,
+    pub error: Option<String>,
+}
+
+/// Segments with the positions they were read at, replayed in order. Lets a message be
+/// processed away from the reader that cut it out of the input.
+pub struct ReplayedSegments {
+    segments: std::vec::IntoIter<ReadSegment>,
+    delimiters: Delimiters,
+    position: SourcePosition,
+}
+
+impl ReplayedSegments {
+    pub fn new(segments: Vec<ReadSegment>, delimiters: Delimiters) -> Self {
+        Self {
+            segments: segments.into_iter(),
+            delimiters,
+            position: SourcePosition::default(),
+        }
+    }
+}
+
+impl SegmentSource for ReplayedSegments {
+    fn read_segment(&mut self, buf: &mut String) -> Result<bool> {
+        let Some(segment) = self.segments.next() else {
+            return Ok(false);
+        };
+        *buf = segment.text;
+        self.position = segment.position;
+        match segment.error {
+            Some(reason) => Err(FileReduceError::Parse(reason).at(segment.position)),
+            None => Ok(true),
+        }
+    }
+
+    fn delimiters(&self) -> &Delimiters {
+        &self.delimiters
+    }
+
+    fn position(&self) -> SourcePosition {
+        self.position
+    }
+}
+
+/// Byte-level state of the segment currently being read
+#[derive(Default)]
+struct Splitter {
+    delimiters: Delimiters,
+    pending: Vec<u8>,
+    /// Complete segments with the offset of their first byte
+    ready: VecDeque<(u64, Vec<u8>)>,
+    /// Bytes fed so far
+    offset: u64,
+    /// Offset of the first byte in `pending`
+    start: u64,
+    soft_break: Option<usize>,
+    /// Offset of the first byte after `soft_break`
+    break_start: u64,
+    /// A segment terminator was seen, so line breaks are only wrapping
+    terminated: bool,
+    /// A UNZ was queued; the next segment starts a new interchange
+    interchange_ended: bool,
+    escaped: bool,
+    una: Option<Vec<u8>>,
+    /// Offset of the UNA being read
+    una_start: u64,
+    /// Offset, text and reason of a UNA whose separators could not be used
+    rejected_una: Option<(u64, String, String)>,
+    spare: Vec<u8>,
+}
+
+impl Splitter {
+    /// Feeds one byte; returns `true` once a complete segment is queued in `ready`
+    fn push(&mut self, byte: u8) -> bool {
+        let at = self.offset;
+        self.offset += 1;
+        if byte == b'\r' || byte == b'\n' {
+            if !self.pending.is_empty()
+                && self.soft_break.is_none()
+                && !self.escaped
+                && !self.terminated
+            {
+                self.soft_break = Some(self.pending.len());
+            }
+            return false;
+        }
+        if let Some(una) = self.una.as_mut() {
+            una.push(byte);
+            if una.len() == 6 {
+                let una = format!("UNA{}", String::from_utf8_lossy(una));
+                self.una = None;
+                match Delimiters::from_una(&una) {
+                    Ok(delimiters) => self.delimiters = delimiters,
+                    Err(e) => {
+                        self.rejected_una = Some((self.una_start, una, e.reason()));
+                        return true;
+                    }
+                }
+            }
+            return false;
+        }
+        if self.interchange_ended && self.pending.is_empty() {
+            // Each interchange declares its own separators, or uses the defaults
+            self.delimiters = Delimiters::default();
+            self.terminated = false;
+            self.interchange_ended = false;
+        }
+
+        if self.escaped {
+            self.push_data(byte, at);
+            self.escaped = false;
+        } else if byte == self.delimiters.release as u8 {
+            self.push_data(byte, at);
+            self.escaped = true;
+        } else if byte == self.delimiters.segment as u8 {
+            self.terminated = true;
+            self.finish_segment();
+            return !self.ready.is_empty();
+        } else {
+            self.push_data(byte, at);
+        }
+
+        if self.soft_break.is_none() && self.pending == b"UNA" {
+            // Service string advice: the next six characters are the separators
+            self.una_start = self.start;
+            self.pending.clear();
+            self.una = Some(Vec::with_capacity(6));
+            return false;
+        }
+        self.split_at_soft_break(false)
+    }
+
+    fn push_data(&mut self, byte: u8, at: u64) {
+        if self.pending.is_empty() {
+            self.start = at;
+        }
+        if self.soft_break == Some(self.pending.len()) {
+            self.break_start = at;
+        }
+        self.pending.push(byte);
+    }
+
+    fn finish_segment(&mut self) {
+        self.split_at_soft_break(true);
+        self.escaped = false;
+        if !self.pending.is_empty() {
+            let next = std::mem::take(&mut self.spare);
+            let segment = std::mem::replace(&mut self.pending, next);
+            self.queue(self.start, segment);
+        }
+    }
+
+    fn queue(&mut self, start: u64, segment: Vec<u8>) {
+        if segment.starts_with(b"UNZ") {
+            self.interchange_ended = true;
+        }
+        self.ready.push_back((start, segment));
+    }
+
+    /// Hands a consumed segment buffer back so the next segment reuses its capacity
+    fn recycle(&mut self, mut segment: Vec<u8>) {
+        segment.clear();
+        self.spare = segment;
+    }
+
+    /// Decides whether the line break recorded in `soft_break` ended a segment, which is
+    /// the case when the following line starts with a segment tag. On a split the bytes
+    /// before the break are queued as a complete segment and `true` is returned.
+    fn split_at_soft_break(&mut self, at_end: bool) -> bool {
+        let Some(at) = self.soft_break else {
+            return false;
+        };
+        let rest = &self.pending[at..];
+        if rest.len() < 4 && !at_end {
+            return false;
+        }
+        self.soft_break = None;
+
+        let is_tag = rest.len() >= 3 && rest[..3].iter().all(u8::is_ascii_uppercase);
+        let tag_ends = rest
+            .get(3)
+            .is_none_or(|&b| b == self.delimiters.element as u8);
+        if !(is_tag && tag_ends) {
+            return false;
+        }
+        let next = self.pending.split_off(at);
+        let current = std::mem::replace(&mut self.pending, next);
+        self.queue(self.start, current);
+        self.start = self.break_start;
+        true
+    }
+}
+
+/// First component of the first UNB element, e.g. `UNOC` in `UNB+UNOC:3+...`
+fn syntax_identifier(unb: &[u8], delimiters: &Delimiters) -> String {
+    let element = delimiters.element as u8;
+    let component = delimiters.component as u8;
+    unb.split(|&b| b == element)
+        .nth(1)
+        .and_then(|e| e.split(|&b| b == component).next())
+        .map(|id| String::from_utf8_lossy(id).into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(input: &str) -> Vec<String> {
+        let mut reader = SegmentReader::new(std::io::BufReader::with_capacity(7, input.as_bytes()));
+        let mut segments = Vec::new();
+        let mut buf = String::new();
+        while reader.read_segment(&mut buf).unwrap() {
+            segments.push(buf.clone());
+        }
+        segments
+    }
+
+    #[test]
+    fn test_single_line_and_wrapped_interchanges() {
+        let expected = vec![
+            "UNB+UNOC:3+S+R+1",
+            "UNH+1+ORDERS:D:96A:UN",
+            "FTX+AAI+++O?'NEIL",
+            "UNT+3+1",
+        ];
+
+        let single = "UNB+UNOC:3+S+R+1'UNH+1+ORDERS:D:96A:UN'FTX+AAI+++O?'NEIL'UNT+3+1'";
+        assert_eq!(read_all(single), expected);
+
+        let wrapped =
+            "UNB+UNOC:3+S+R+1'UNH+1+ORD\r\nERS:D:96A:UN'FTX+AAI+++O?'NE\r\nIL'UNT+3+1'\r\n";
+        assert_eq!(read_all(wrapped), expected);
+
+        let per_line = "UNB+UNOC:3+S+R+1'\nUNH+1+ORDERS:D:96A:UN'\nFTX+AAI+++O?'NEIL'\nUNT+3+1'\n";
+        assert_eq!(read_all(per_line), expected);
+
+        let no_terminators = "UNB+UNOC:3+S+R+1\nUNH+1+ORDERS:D:96A:UN\nFTX+AAI+++O?'NEIL\nUNT+3+1";
+        assert_eq!(read_all(no_terminators), expected);
+    }
+
+    #[test]
+    fn test_wrapped_tag_inside_terminated_segment() {
+        // Once `'` ends segments, a wrapped line starting like a tag is still data
+        let input = "UNB+UNOC:3+S+R+1'TAX+7+\nVAT+++:::21+S'FTX+\r\nAAI+++NOTE'UNT+3+1'";
+        assert_eq!(
+            read_all(input),
+            vec![
+                "UNB+UNOC:3+S+R+1",
+                "TAX+7+VAT+++:::21+S",
+                "FTX+AAI+++NOTE",
+                "UNT+3+1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_una_sets_delimiters() {
+        let input = "UNA|*.? ~UNB*UNOC|3*S*R*1~UNH*1*ORDERS~";
+        let mut reader = SegmentReader::new(input.as_bytes());
+        let mut buf = String::new();
+        assert!(reader.read_segment(&mut buf).unwrap());
+        assert_eq!(buf, "UNB*UNOC|3*S*R*1");
+        assert_eq!(reader.delimiters().segment, '~');
+        assert!(reader.read_segment(&mut buf).unwrap());
+        assert_eq!(buf, "UNH*1*ORDERS");
+        assert!(!reader.read_segment(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_una_ends_with_its_interchange() {
+        let input =
+            "UNA|*.? ~UNB*UNOC|3*S*R*1~UNZ*0*1~\nUNB+UNOC:3+S+R+2'\nUNH+1+ORDERS'\nUNZ+0+2'";
+        let mut reader = SegmentReader::new(input.as_bytes());
+        let mut buf = String::new();
+        let mut segments = Vec::new();
+        while reader.read_segment(&mut buf).unwrap() {
+            segments.push((buf.clone(), reader.delimiters().element));
+        }
+        assert_eq!(
+            segments,
+            vec![
+                ("UNB*UNOC|3*S*R*1".to_string(), '*'),
+                ("UNZ*0*1".to_string(), '*'),
+                ("UNB+UNOC:3+S+R+2".to_string(), '+'),
+                ("UNH+1+ORDERS".to_string(), '+'),
+                ("UNZ+0+2".to_string(), '+'),
+            ]
+        );
+
+        // Line breaks end segments again until the second interchange uses a terminator
+        let input = "UNB+UNOC:3+S+R+1'UNZ+0+1'\nUNB+UNOC:3+S+R+2\nUNH+1+ORDERS\nUNZ+0+2";
+        assert_eq!(
+            read_all(input),
+            vec![
+                "UNB+UNOC:3+S+R+1",
+                "UNZ+0+1",
+                "UNB+UNOC:3+S+R+2",
+                "UNH+1+ORDERS",
+                "UNZ+0+2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_ascii_una_is_rejected() {
+        // A Latin-1 acute accent as segment terminator
+        let input = b"UNA:+.? \xb4UNB+UNOC:3+S+R+1'UNH+1+ORDERS'";
+        let mut reader = SegmentReader::new(&input[..]);
+        let mut buf = String::new();
+        let err = reader.read_segment(&mut buf).unwrap_err();
+        assert!(err.reason().contains("not ASCII"), "{err}");
+        assert!(buf.starts_with("UNA:+.? "));
+        assert_eq!(reader.position().byte_offset, 0);
+        assert!(reader.read_segment(&mut buf).unwrap());
+        assert_eq!(buf, "UNB+UNOC:3+S+R+1");
+        assert_eq!(*reader.delimiters(), Delimiters::default());
+    }
+
+    #[test]
+    fn test_charset_follows_syntax_identifier() {
+        let input =
+            b"UNB+UNOC:3+S+R+1'NAD+BY+++M\xdcLLER'UNZ+1+1'UNB+UNOW:4+S+R+2'NAD+BY+++M\xc3\x9cLLER'";
+        let mut reader = SegmentReader::new(&input[..]);
+        let mut buf = String::new();
+        reader.read_segment(&mut buf).unwrap();
+        assert_eq!(reader.charset(), Charset::Latin1);
+        reader.read_segment(&mut buf).unwrap();
+        assert_eq!(buf, "NAD+BY+++MÜLLER");
+        reader.read_segment(&mut buf).unwrap();
+        reader.read_segment(&mut buf).unwrap();
+        assert_eq!(reader.charset(), Charset::Utf8);
+        reader.read_segment(&mut buf).unwrap();
+        assert_eq!(buf, "NAD+BY+++MÜLLER");
+
+        // Partner declares UNOA but sends Latin-1
+        let input = b"UNB+UNOA:3+S+R+1'NAD+BY+++M\xdcLLER'";
+        let mut reader = SegmentReader::new(&input[..]);
+        reader.read_segment(&mut buf).unwrap();
+        assert!(reader.read_segment(&mut buf).is_err());
+
+        let mut reader = SegmentReader::with_charset(&input[..], Charset::Latin1);
+        reader.read_segment(&mut buf).unwrap();
+        reader.read_segment(&mut buf).unwrap();
+        assert_eq!(reader.charset(), Charset::Latin1);
+        assert_eq!(buf, "NAD+BY+++MÜLLER");
+    }
+
+    #[test]
+    fn test_segment_positions() {
+        // Offsets count UNA, line breaks and wrapped lines
+        let input = "UNA:+.? 'UNB+UNOC:3+S+R+1'\r\nUNH+1+ORD\r\nERS'BGM+220+PO1'\nUNT+3+1'";
+        let mut reader = SegmentReader::with_charset(input.as_bytes(), Charset::Utf8);
+        let mut buf = String::new();
+        let mut positions = Vec::new();
+        while reader.read_segment(&mut buf).unwrap() {
+            let position = reader.position();
+            assert!(input[position.byte_offset as usize..].starts_with(&buf[..3]));
+            positions.push((position.segment_index, position.message_index));
+        }
+        assert_eq!(positions, vec![(1, 0), (2, 1), (3, 1), (4, 1)]);
+
+        let input = b"UNB+UNOA:3+S+R+1'UNH+1+ORDERS'NAD+BY+++M\xdcLLER'";
+        let mut reader = SegmentReader::new(&input[..]);
+        reader.read_segment(&mut buf).unwrap();
+        reader.read_segment(&mut buf).unwrap();
+        let err = reader.read_segment(&mut buf).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("at segment 3 (byte 30, message 1)"));
+    }
+}
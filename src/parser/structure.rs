@@ -0,0 +1,226 @@
+use crate::translations::{LinePrimary, MessageStructure, SegmentGroupConfig};
+
+/// Where a segment belongs within its message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement {
+    /// No structure is known for the message type
+    Unstructured,
+    /// Header or summary section
+    Message,
+    /// Directly in the line item group
+    Line,
+    /// Inside another segment group. `depth` counts the groups below the owner (the
+    /// line for groups nested in the line item group, the document otherwise),
+    /// starting at 1. `opens` names the group when the segment starts a new repetition.
+    Group {
+        depth: usize,
+        in_line: bool,
+        opens: Option<String>,
+    },
+}
+
+impl Placement {
+    /// Whether the segment belongs to the current line item. Without a structure every
+    /// segment may, as before structures existed.
+    pub fn in_line(&self) -> bool {
+        matches!(
+            self,
+            Placement::Unstructured | Placement::Line | Placement::Group { in_line: true, .. }
+        )
+    }
+
+    /// Whether the segment sits in the line item group itself rather than in a group
+    /// nested in it (a TAX or ALC group, whose MOA is not the line amount)
+    pub fn in_line_group(&self) -> bool {
+        matches!(self, Placement::Unstructured | Placement::Line)
+    }
+
+    /// Whether the segment belongs to the document rather than to a line item
+    pub fn in_document(&self) -> bool {
+        !matches!(
+            self,
+            Placement::Line | Placement::Group { in_line: true, .. }
+        )
+    }
+}
+
+/// Follows the segment groups of a message as its segments stream by.
+///
+/// A segment is looked up in the innermost open group first and then in its
+/// ancestors: a group trigger opens (or repeats) that group, a listed segment stays in
+/// the group where it is listed. Segments the structure does not mention stay in the
+/// innermost open group, so an incomplete structure never closes a group early.
+#[derive(Debug, Default)]
+pub struct StructureTracker {
+    structure: Option<MessageStructure>,
+    path: Vec<usize>,
+    opened: bool,
+}
+
+impl StructureTracker {
+    pub fn new(structure: Option<MessageStructure>) -> Self {
+        Self {
+            structure,
+            path: Vec::new(),
+            opened: false,
+        }
+    }
+
+    /// Primary line qualifiers of the message type, none without a structure
+    pub fn line_primary(&self) -> &LinePrimary {
+        static NONE: LinePrimary = LinePrimary {
+            quantity: Vec::new(),
+            amount: Vec::new(),
+            price: Vec::new(),
+        };
+        self.structure.as_ref().map_or(&NONE, |s| &s.primary)
+    }
+
+    /// Names of the open groups, outermost first
+    pub fn open_groups(&self) -> Vec<&str> {
+        let Some(structure) = &self.structure else {
+            return Vec::new();
+        };
+        (1..=self.path.len())
+            .filter_map(|level| group_at(structure, &self.path[..level]))
+            .map(|group| group.name.as_str())
+            .collect()
+    }
+
+    /// Whether the last placed segment started a new repetition of the innermost group
+    pub fn opened(&self) -> bool {
+        self.opened
+    }
+
+    pub fn place(&mut self, tag: &str) -> Placement {
+        let Some(structure) = &self.structure else {
+            return Placement::Unstructured;
+        };
+
+        for level in (0..=self.path.len()).rev() {
+            let open = &self.path[..level];
+            if let Some(idx) = children(structure, open)
+                .iter()
+                .position(|g| g.trigger == tag)
+            {
+                self.path.truncate(level);
+                self.path.push(idx);
+                return self.placement(true);
+            }
+            match group_at(structure, open) {
+                None => {
+                    if structure.segments.iter().any(|s| s == tag) {
+                        self.path.clear();
+                        return Placement::Message;
+                    }
+                }
+                Some(group) => {
+                    let repeats = group.trigger == tag;
+                    if repeats || group.segments.iter().any(|s| s == tag) {
+                        self.path.truncate(level);
+                        return self.placement(repeats);
+                    }
+                }
+            }
+        }
+        self.placement(false)
+    }
+
+    fn placement(&mut self, opened: bool) -> Placement {
+        self.opened = opened;
+        let Some(structure) = &self.structure else {
+            return Placement::Unstructured;
+        };
+        if self.path.is_empty() {
+            return Placement::Message;
+        }
+        let groups: Vec<&SegmentGroupConfig> = (1..=self.path.len())
+            .filter_map(|level| group_at(structure, &self.path[..level]))
+            .collect();
+        let innermost = groups.len() - 1;
+        let opens = opened.then(|| groups[innermost].name.clone());
+        match groups.iter().position(|g| g.line) {
+            Some(line) if line == innermost => Placement::Line,
+            Some(line) => Placement::Group {
+                depth: innermost - line,
+                in_line: true,
+                opens,
+            },
+            None => Placement::Group {
+                depth: groups.len(),
+                in_line: false,
+                opens,
+            },
+        }
+    }
+}
+
+/// Groups that can open below `path` (the top-level groups for an empty path)
+fn children<'s>(structure: &'s MessageStructure, path: &[usize]) -> &'s [SegmentGroupConfig] {
+    match group_at(structure, path) {
+        Some(group) => &group.groups,
+        None => &structure.groups,
+    }
+}
+
+fn group_at<'s>(structure: &'s MessageStructure, path: &[usize]) -> Option<&'s SegmentGroupConfig> {
+    let (&first, rest) = path.split_first()?;
+    let mut group = structure.groups.get(first)?;
+    for &idx in rest {
+        group = group.groups.get(idx)?;
+    }
+    Some(group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translations::TranslationRegistry;
+
+    #[test]
+    fn test_orders_segment_groups() {
+        let registry = TranslationRegistry::new().unwrap();
+        let mut tracker = StructureTracker::new(registry.get_message_structure("ORDERS"));
+
+        let placements: Vec<_> = [
+            "BGM", "DTM", "NAD", "RFF", "DTM", "CTA", "COM", "NAD", "LIN", "QTY", "PRI", "LIN",
+            "ZZZ", "UNS", "MOA", "CNT",
+        ]
+        .iter()
+        .map(|tag| tracker.place(tag))
+        .collect();
+
+        let group = |depth, in_line, opens: Option<&str>| Placement::Group {
+            depth,
+            in_line,
+            opens: opens.map(str::to_string),
+        };
+        assert_eq!(
+            placements,
+            vec![
+                Placement::Message,
+                Placement::Message,
+                group(1, false, Some("SG2")),
+                group(2, false, Some("SG3")),
+                group(2, false, None),
+                group(2, false, Some("SG5")),
+                group(2, false, None),
+                group(1, false, Some("SG2")),
+                Placement::Line,
+                Placement::Line,
+                group(1, true, Some("SG28")),
+                Placement::Line,
+                // Not described: stays in the line item group
+                Placement::Line,
+                Placement::Message,
+                Placement::Message,
+                Placement::Message,
+            ]
+        );
+        assert!(!placements[14].in_line());
+        assert!(placements[10].in_line() && !placements[10].in_document());
+
+        let mut unstructured = StructureTracker::new(None);
+        assert_eq!(unstructured.place("MOA"), Placement::Unstructured);
+    }
+}
@@ -1,7 +1,356 @@
-pub fn tokenize_segment(segment: &str) -> Vec<Vec<&str>> {
-    segment
-        .trim_end_matches('\'')
-        .split('+')
-        .map(|part| part.split(':').collect())
-        .collect()
+use crate::error::{FileReduceError, Result};
+use std::borrow::Cow;
+
+/// Service characters of an interchange, as declared by the UNA service string advice.
+///
+/// UNA layout: `UNA` followed by the component separator, element separator,
+/// decimal mark, release character, reserved character and segment terminator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delimiters {
+    pub component: char,
+    pub element: char,
+    pub decimal: char,
+    pub release: char,
+    pub reserved: char,
+    pub segment: char,
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Self {
+            component: ':',
+            element: '+',
+            decimal: '.',
+            release: '?',
+            reserved: ' ',
+            segment: '\'',
+        }
+    }
+}
+
+impl Delimiters {
+    /// Parses a UNA segment (e.g. `UNA:+.? '`). Fails if the segment is not a complete
+    /// service string advice, or names a separator outside ASCII: segments are split
+    /// byte by byte, so a separator has to be a single byte in every charset.
+    pub fn from_una(segment: &str) -> Result<Self> {
+        let incomplete =
+            || FileReduceError::Parse(format!("incomplete service string advice '{segment}'"));
+        let rest = segment.strip_prefix("UNA").ok_or_else(incomplete)?;
+        if let Some(c) = rest.chars().find(|c| !c.is_ascii()) {
+            return Err(FileReduceError::Parse(format!(
+                "UNA separator '{c}' is not ASCII"
+            )));
+        }
+        let mut chars = rest.chars();
+        let mut next = || chars.next().ok_or_else(incomplete);
+        Ok(Self {
+            component: next()?,
+            element: next()?,
+            decimal: next()?,
+            release: next()?,
+            reserved: next()?,
+            segment: next()?,
+        })
+    }
+
+    /// Rewrites a numeric value so that it uses `.` as decimal mark, which is what
+    /// `str::parse::<f64>` expects.
+    pub fn normalize_decimal<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        // ISO 9735 accepts a comma as decimal mark whatever UNA declares
+        let is_mark = |c: char| c != '.' && (c == ',' || c == self.decimal);
+        if value.contains(is_mark) {
+            Cow::Owned(value.replace(is_mark, "."))
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+}
+
+pub fn tokenize_segment(segment: &str) -> Vec<Vec<Cow<'_, str>>> {
+    tokenize_segment_with(segment, &Delimiters::default())
+}
+
+/// Splits a segment into elements and components.
+///
+/// Separators preceded by the release character are kept as data and the release
+/// character is removed from the value. Components without escapes borrow from
+/// `segment`; only escaped ones are copied.
+pub fn tokenize_segment_with<'a>(
+    segment: &'a str,
+    delimiters: &Delimiters,
+) -> Vec<Vec<Cow<'a, str>>> {
+    let mut tokenizer = SegmentTokenizer::new();
+    tokenizer.tokenize(segment.as_bytes(), delimiters).to_vec()
+}
+
+/// Reusable byte-level tokenizer.
+///
+/// Rather than building a `Vec<Vec<&str>>` for every segment it records the byte range of
+/// each component in buffers that are reused from one segment to the next, so steady-state
+/// tokenizing does not allocate. Values are read back through [`SegmentView`].
+#[derive(Debug, Default)]
+pub struct SegmentTokenizer {
+    spans: Vec<Span>,
+    elements: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+    escaped: bool,
+}
+
+impl SegmentTokenizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tokenizes one segment (with or without its terminator)
+    pub fn tokenize<'t, 'a>(
+        &'t mut self,
+        segment: &'a [u8],
+        delimiters: &Delimiters,
+    ) -> SegmentView<'t, 'a> {
+        self.spans.clear();
+        self.elements.clear();
+        self.elements.push(0);
+
+        let segment = strip_terminator(segment, delimiters);
+        let release = delimiters.release as u8;
+        let component = delimiters.component as u8;
+        let element = delimiters.element as u8;
+        let mut start = 0;
+        let mut escaped = false;
+        let mut idx = 0;
+
+        while idx < segment.len() {
+            let byte = segment[idx];
+            if byte == release {
+                escaped = true;
+                idx += 2;
+                continue;
+            }
+            if byte == component || byte == element {
+                self.spans.push(Span {
+                    start,
+                    end: idx,
+                    escaped,
+                });
+                start = idx + 1;
+                escaped = false;
+                if byte == element {
+                    self.elements.push(self.spans.len());
+                }
+            }
+            idx += 1;
+        }
+        self.spans.push(Span {
+            start,
+            end: segment.len(),
+            escaped,
+        });
+
+        SegmentView {
+            data: segment,
+            spans: &self.spans,
+            elements: &self.elements,
+            release,
+        }
+    }
+}
+
+/// Element/component accessors over a tokenized segment.
+///
+/// Element 0 is the segment tag. Values borrow from the segment bytes unless they
+/// contained release characters.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentView<'t, 'a> {
+    data: &'a [u8],
+    spans: &'t [Span],
+    elements: &'t [usize],
+    release: u8,
+}
+
+impl<'t, 'a> SegmentView<'t, 'a> {
+    /// Segment tag, e.g. `NAD`
+    pub fn tag(&self) -> Cow<'a, str> {
+        self.value(0, 0)
+    }
+
+    /// Number of elements, including the tag
+    pub fn element_count(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn component_count(&self, element: usize) -> usize {
+        self.element_spans(element).map_or(0, <[Span]>::len)
+    }
+
+    /// Raw bytes of a component, release characters included
+    pub fn raw(&self, element: usize, component: usize) -> Option<&'a [u8]> {
+        let span = self.element_spans(element)?.get(component)?;
+        Some(&self.data[span.start..span.end])
+    }
+
+    pub fn component(&self, element: usize, component: usize) -> Option<Cow<'a, str>> {
+        let span = *self.element_spans(element)?.get(component)?;
+        Some(self.decode(span))
+    }
+
+    /// Like [`SegmentView::component`], with `""` for missing positions
+    pub fn value(&self, element: usize, component: usize) -> Cow<'a, str> {
+        self.component(element, component)
+            .unwrap_or(Cow::Borrowed(""))
+    }
+
+    pub fn components(&self, element: usize) -> impl Iterator<Item = Cow<'a, str>> + '_ {
+        self.element_spans(element)
+            .unwrap_or_default()
+            .iter()
+            .map(|&span| self.decode(span))
+    }
+
+    /// Copies the spans out into the nested form returned by [`tokenize_segment_with`]
+    pub fn to_vec(&self) -> Vec<Vec<Cow<'a, str>>> {
+        (0..self.element_count())
+            .map(|element| self.components(element).collect())
+            .collect()
+    }
+
+    fn element_spans(&self, element: usize) -> Option<&'t [Span]> {
+        let start = *self.elements.get(element)?;
+        let end = self
+            .elements
+            .get(element + 1)
+            .copied()
+            .unwrap_or(self.spans.len());
+        Some(&self.spans[start..end])
+    }
+
+    fn decode(&self, span: Span) -> Cow<'a, str> {
+        let raw = &self.data[span.start..span.end];
+        if !span.escaped {
+            return String::from_utf8_lossy(raw);
+        }
+        let mut out = Vec::with_capacity(raw.len());
+        let mut bytes = raw.iter();
+        while let Some(&byte) = bytes.next() {
+            if byte == self.release {
+                if let Some(&next) = bytes.next() {
+                    out.push(next);
+                }
+            } else {
+                out.push(byte);
+            }
+        }
+        Cow::Owned(String::from_utf8_lossy(&out).into_owned())
+    }
+}
+
+/// Removes the release characters from a value, e.g. `10?+TAX?'S` becomes `10+TAX'S`
+pub fn unescape<'a>(value: &'a str, delimiters: &Delimiters) -> Cow<'a, str> {
+    if !value.contains(delimiters.release) {
+        return Cow::Borrowed(value);
+    }
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == delimiters.release {
+            if let Some(next) = chars.next() {
+                out.push(next);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Drops the trailing segment terminator unless it is released (`?'` is an apostrophe)
+fn strip_terminator<'a>(segment: &'a [u8], delimiters: &Delimiters) -> &'a [u8] {
+    let Some(body) = segment.strip_suffix(&[delimiters.segment as u8]) else {
+        return segment;
+    };
+    let releases = body
+        .iter()
+        .rev()
+        .take_while(|&&b| b == delimiters.release as u8)
+        .count();
+    if releases % 2 == 0 {
+        body
+    } else {
+        segment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_with_una_delimiters() {
+        let delimiters = Delimiters::from_una("UNA|*,? ~").unwrap();
+        assert_eq!(delimiters.component, '|');
+        assert_eq!(delimiters.element, '*');
+        assert_eq!(delimiters.decimal, ',');
+        assert_eq!(delimiters.segment, '~');
+
+        let tokens = tokenize_segment_with("QTY*21|10,5|PCE~", &delimiters);
+        assert_eq!(tokens, vec![vec!["QTY"], vec!["21", "10,5", "PCE"]]);
+        assert_eq!(delimiters.normalize_decimal(&tokens[1][1]), "10.5");
+
+        assert!(Delimiters::from_una("UNA:+").is_err());
+        assert_eq!(
+            Delimiters::from_una("UNA:+.? '").unwrap(),
+            Delimiters::default()
+        );
+        // Separators are compared byte by byte
+        let err = Delimiters::from_una("UNA:+.? \u{2019}").unwrap_err();
+        assert_eq!(err.reason(), "UNA separator '\u{2019}' is not ASCII");
+    }
+
+    #[test]
+    fn test_tokenize_release_character() {
+        let delimiters = Delimiters::default();
+
+        let tokens = tokenize_segment("FTX+AAI+++PRICE 10?+TAX?'S'");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[4], vec!["PRICE 10+TAX'S"]);
+        assert!(matches!(tokens[1][0], Cow::Borrowed("AAI")));
+        assert!(matches!(tokens[4][0], Cow::Owned(_)));
+
+        let tokens = tokenize_segment("NAD+BY+++O?'NEIL?:SONS??'");
+        assert_eq!(tokens[4], vec!["O'NEIL:SONS?"]);
+
+        // A released terminator at the end is data, not the end of the segment
+        let tokens = tokenize_segment("FTX+AAI+++ROCK?'");
+        assert_eq!(tokens[4], vec!["ROCK'"]);
+
+        assert_eq!(unescape("A??B?:C", &delimiters), "A?B:C");
+    }
+
+    #[test]
+    fn test_segment_tokenizer_reuses_buffers() {
+        let delimiters = Delimiters::default();
+        let mut tokenizer = SegmentTokenizer::new();
+
+        let view = tokenizer.tokenize(b"NAD+BY+5412345000013::9++O?'NEIL'", &delimiters);
+        assert_eq!(view.tag(), "NAD");
+        assert_eq!(view.element_count(), 5);
+        assert_eq!(view.component_count(2), 3);
+        assert_eq!(view.value(2, 0), "5412345000013");
+        assert_eq!(view.value(2, 2), "9");
+        assert_eq!(view.value(4, 0), "O'NEIL");
+        assert_eq!(view.raw(4, 0), Some(&b"O?'NEIL"[..]));
+        assert_eq!(view.component(9, 0), None);
+        assert_eq!(view.value(9, 0), "");
+
+        let view = tokenizer.tokenize(b"QTY+21:10:PCE", &delimiters);
+        assert_eq!(view.element_count(), 2);
+        assert_eq!(
+            view.components(1).collect::<Vec<_>>(),
+            vec!["21", "10", "PCE"]
+        );
+    }
 }
@@ -2,95 +2,449 @@ use crate::error::Result;
 use engine_filereduce::executor::executor::eval;
 use engine_filereduce::query::ast::Expr;
 use engine_filereduce::row::{Row, RowKind, Value as RowValue};
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader as XmlReader;
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::fmt;
 use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+/// Elements read as records when no [`RecordPath`] is given
+const DEFAULT_RECORDS: [&str; 3] = ["record", "item", "row"];
+
+/// Field holding the text of an element that also has attributes or children
+pub const TEXT_KEY: &str = "#text";
 
 #[derive(Debug)]
 pub struct XmlRecord {
-    pub fields: HashMap<String, String>,
+    /// Attributes (under [`XmlOptions::attribute_prefix`]) and child elements by name:
+    /// text for leaves, objects for elements with attributes or children, arrays for
+    /// names that repeat
+    pub fields: Map<String, Value>,
 }
 
-pub fn process_xml<R: BufRead, W: Write>(
-    reader: R,
-    writer: &mut W,
-    query: Option<&Expr>,
-) -> Result<()> {
-    let mut xml_reader = XmlReader::from_reader(reader);
-    xml_reader.config_mut().trim_text(true);
-
-    let mut current_record: Option<XmlRecord> = None;
-    let mut current_tag: Option<String> = None;
-    let mut current_text = String::new();
-    let mut buf = Vec::new();
-
-    loop {
-        let event = xml_reader.read_event_into(&mut buf)?;
-
-        match event {
-            Event::Start(ref e) => {
-                let tag_name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-
-                if tag_name == "record" || tag_name == "item" || tag_name == "row" {
-                    current_record = Some(XmlRecord {
-                        fields: HashMap::new(),
-                    });
-                } else {
-                    current_tag = Some(tag_name);
-                    current_text.clear();
-                }
-            }
+/// Selects the elements read as records: `/Orders/Order` from the root, `Order` or
+/// `//Order` at any depth. A step with a prefix (`ns:Order`) matches that qualified
+/// name only, one without matches the local name in any namespace, `*` matches any
+/// element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordPath {
+    steps: Vec<String>,
+    anywhere: bool,
+}
 
-            Event::Text(e) => {
-                if current_tag.is_some() {
-                    current_text.push_str(&e.unescape()?);
-                }
+impl RecordPath {
+    /// True when the innermost elements of `open` (qualified names, root first) are
+    /// selected
+    fn matches(&self, open: &[String]) -> bool {
+        if open.len() < self.steps.len() || (!self.anywhere && open.len() != self.steps.len()) {
+            return false;
+        }
+        let tail = &open[open.len() - self.steps.len()..];
+        self.steps
+            .iter()
+            .zip(tail)
+            .all(|(step, name)| match step.as_str() {
+                "*" => true,
+                step if step.contains(':') => step == name,
+                step => step == local_name(name),
+            })
+    }
+}
+
+impl FromStr for RecordPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (anywhere, rest) = match s.strip_prefix("//") {
+            Some(rest) => (true, rest),
+            None => match s.strip_prefix('/') {
+                Some(rest) => (false, rest),
+                None => (true, s),
+            },
+        };
+        let steps: Vec<String> = rest.split('/').map(str::to_string).collect();
+        if steps.iter().any(|step| step.is_empty()) {
+            return Err(format!("invalid record path '{}'", s));
+        }
+        Ok(RecordPath { steps, anywhere })
+    }
+}
+
+impl fmt::Display for RecordPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.anywhere { "//" } else { "/" };
+        write!(f, "{}{}", prefix, self.steps.join("/"))
+    }
+}
+
+/// How [`XmlRecords`] finds, names and types record fields
+#[derive(Debug, Clone)]
+pub struct XmlOptions {
+    /// Elements read as records; `record`, `item` and `row` at any depth when unset
+    pub record_path: Option<RecordPath>,
+    /// Drops namespace prefixes from field names (`cac:Item` → `Item`)
+    pub strip_namespaces: bool,
+    /// Put before attribute names to tell them from child elements (`@id`)
+    pub attribute_prefix: String,
+    /// Writes `true`/`false` as booleans and numbers as numbers instead of text.
+    /// Values with leading zeros (`007`) stay text.
+    pub infer_types: bool,
+}
+
+impl Default for XmlOptions {
+    fn default() -> Self {
+        Self {
+            record_path: None,
+            strip_namespaces: false,
+            attribute_prefix: "@".to_string(),
+            infer_types: false,
+        }
+    }
+}
+
+impl XmlOptions {
+    fn is_record(&self, open: &[String]) -> bool {
+        match &self.record_path {
+            Some(path) => path.matches(open),
+            None => open
+                .last()
+                .is_some_and(|name| DEFAULT_RECORDS.contains(&name.as_str())),
+        }
+    }
+
+    fn field_name(&self, name: String) -> String {
+        if self.strip_namespaces {
+            local_name(&name).to_string()
+        } else {
+            name
+        }
+    }
+
+    /// Attributes of `e` as fields, leaving out namespace declarations
+    fn attributes(&self, e: &BytesStart) -> Result<Map<String, Value>> {
+        let mut fields = Map::new();
+        for attribute in e.attributes() {
+            let attribute = attribute.map_err(quick_xml::Error::from)?;
+            let name = String::from_utf8_lossy(attribute.key.as_ref()).to_string();
+            if name == "xmlns" || name.starts_with("xmlns:") {
+                continue;
             }
+            let value = attribute.unescape_value()?.into_owned();
+            fields.insert(
+                format!("{}{}", self.attribute_prefix, self.field_name(name)),
+                self.scalar(value),
+            );
+        }
+        Ok(fields)
+    }
 
-            Event::End(ref e) => {
-                let tag_name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-
-                if tag_name == "record" || tag_name == "item" || tag_name == "row" {
-                    if let Some(record) = current_record.take() {
-                        let should_write = if let Some(expr) = query {
-                            let mut row = Row::new(RowKind::UNH);
-                            for (k, v) in &record.fields {
-                                // Try to parse as number if possible, otherwise text
-                                let val = if let Ok(n) = v.parse::<f64>() {
-                                    RowValue::Number(n)
-                                } else {
-                                    RowValue::Text(v.clone())
-                                };
-                                row.insert(k, val);
-                            }
-                            eval(expr, &row)
-                        } else {
-                            true
-                        };
-
-                        if should_write {
-                            let json = serde_json::to_string(&record.fields)?;
-                            writeln!(writer, "{}", json)?;
+    /// The value of an element from its attributes and children (`fields`) and text
+    fn value(&self, mut fields: Map<String, Value>, text: String) -> Value {
+        if fields.is_empty() {
+            return self.scalar(text);
+        }
+        if !text.is_empty() {
+            fields.insert(TEXT_KEY.to_string(), self.scalar(text));
+        }
+        Value::Object(fields)
+    }
+
+    fn scalar(&self, text: String) -> Value {
+        if !self.infer_types {
+            return Value::String(text);
+        }
+        infer_scalar(text)
+    }
+}
+
+/// `true`/`false` as booleans and numbers as numbers, keeping values with leading
+/// zeros as text
+fn infer_scalar(text: String) -> Value {
+    match text.as_str() {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    // Codes such as `007` are not numbers
+    let digits = text.strip_prefix('-').unwrap_or(&text);
+    if digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.") {
+        return Value::String(text);
+    }
+    if let Ok(n) = text.parse::<i64>() {
+        return Value::from(n);
+    }
+    match text
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        Some(n) => Value::Number(n),
+        None => Value::String(text),
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// An element of the current record still open
+struct Frame {
+    name: String,
+    fields: Map<String, Value>,
+    text: String,
+}
+
+/// Records of an XML input, in document order, see [`records`]
+pub struct XmlRecords<'o, R: BufRead> {
+    reader: XmlReader<R>,
+    options: &'o XmlOptions,
+    /// Qualified names of the open elements, root first
+    open: Vec<String>,
+    /// Open elements from the record down; empty outside a record
+    frames: Vec<Frame>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+/// Reads `reader` record by record
+pub fn records<R: BufRead>(reader: R, options: &XmlOptions) -> XmlRecords<'_, R> {
+    let mut reader = XmlReader::from_reader(reader);
+    reader.config_mut().trim_text(true);
+    XmlRecords {
+        reader,
+        options,
+        open: Vec::new(),
+        frames: Vec::new(),
+        buf: Vec::new(),
+        done: false,
+    }
+}
+
+impl<R: BufRead> XmlRecords<'_, R> {
+    fn next_record(&mut self) -> Result<Option<XmlRecord>> {
+        let options = self.options;
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(ref e) => {
+                    let name = element_name(e);
+                    self.open.push(name.clone());
+                    if !self.frames.is_empty() || options.is_record(&self.open) {
+                        self.frames.push(Frame {
+                            name,
+                            fields: options.attributes(e)?,
+                            text: String::new(),
+                        });
+                    }
+                }
+                Event::Empty(ref e) => {
+                    let name = element_name(e);
+                    if !self.frames.is_empty() {
+                        let fields = options.attributes(e)?;
+                        self.close(name, fields, String::new());
+                    } else {
+                        self.open.push(name);
+                        let is_record = options.is_record(&self.open);
+                        self.open.pop();
+                        if is_record {
+                            return Ok(Some(XmlRecord {
+                                fields: options.attributes(e)?,
+                            }));
                         }
                     }
-                } else if current_tag.as_ref() == Some(&tag_name) {
-                    if let Some(ref mut record) = current_record {
-                        if let Some(tag) = current_tag.take() {
-                            record.fields.insert(tag, current_text.clone());
+                }
+                Event::Text(e) => {
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.text.push_str(&e.unescape()?);
+                    }
+                }
+                Event::CData(e) => {
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.text.push_str(&String::from_utf8_lossy(&e));
+                    }
+                }
+                Event::End(_) => {
+                    self.open.pop();
+                    if let Some(mut frame) = self.frames.pop() {
+                        if self.frames.is_empty() {
+                            if !frame.text.is_empty() {
+                                let text = options.scalar(frame.text);
+                                frame.fields.insert(TEXT_KEY.to_string(), text);
+                            }
+                            return Ok(Some(XmlRecord {
+                                fields: frame.fields,
+                            }));
                         }
+                        self.close(frame.name, frame.fields, frame.text);
                     }
                 }
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+
+    /// Adds a finished element to the element that contains it, grouping repeated
+    /// names into an array
+    fn close(&mut self, name: String, fields: Map<String, Value>, text: String) {
+        let value = self.options.value(fields, text);
+        let key = self.options.field_name(name);
+        let Some(parent) = self.frames.last_mut() else {
+            return;
+        };
+        match parent.fields.get_mut(&key) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                parent.fields.insert(key, value);
             }
+        }
+    }
+}
 
-            Event::Eof => break,
+impl<R: BufRead> Iterator for XmlRecords<'_, R> {
+    type Item = Result<XmlRecord>;
 
-            _ => {}
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
         }
+    }
+}
+
+fn element_name(e: &BytesStart) -> String {
+    String::from_utf8_lossy(e.name().as_ref()).to_string()
+}
+
+/// True when `record` matches `expr`. Nested values are addressed by dotted path
+/// (`Header.Number`, `Line.@id`); with repeated elements the query matches when it
+/// holds for one of them, so `Line.Sku = 'A' AND Line.Qty > 5` needs both on the same
+/// `Line`. Text compared with a number compares as one when [`XmlOptions::infer_types`]
+/// would make it one, so `Qty > 10` works on text, `Qty = '3'` still matches the text
+/// `3` and `Buyer.@code = '0042'` keeps its leading zeros.
+pub fn matches_query(expr: &Expr, record: &XmlRecord) -> bool {
+    query_rows(expr, record).iter().any(|row| eval(expr, row))
+}
+
+/// The rows `expr` is evaluated on: one per combination of the elements of the arrays
+/// the query refers to
+fn query_rows(expr: &Expr, record: &XmlRecord) -> Vec<Row> {
+    let mut fields = Vec::new();
+    query_fields(expr, &mut fields);
+    record
+        .fields
+        .iter()
+        .fold(vec![Row::new(RowKind::UNH)], |rows, (key, value)| {
+            add_values(key, value, &fields, rows)
+        })
+}
 
-        buf.clear();
+/// Field paths compared by `expr`, and whether they are compared with a number
+fn query_fields<'e>(expr: &'e Expr, fields: &mut Vec<(&'e str, bool)>) {
+    let numeric = |value: &RowValue| matches!(value, RowValue::Number(_));
+    match expr {
+        Expr::Eq(field, value)
+        | Expr::Gt(field, value)
+        | Expr::Lt(field, value)
+        | Expr::Gte(field, value)
+        | Expr::Lte(field, value) => fields.push((field, numeric(value))),
+        Expr::Like(field, _) => fields.push((field, false)),
+        Expr::In(field, values) => fields.push((field, values.iter().any(numeric))),
+        Expr::Between(field, start, end) => fields.push((field, numeric(start) || numeric(end))),
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            query_fields(left, fields);
+            query_fields(right, fields);
+        }
+        Expr::Not(inner) => query_fields(inner, fields),
+        Expr::KindEq(_) => {}
     }
+}
 
+/// Adds the values under `path` that `fields` refer to to `rows`, one copy of the rows
+/// per array element
+fn add_values(path: &str, value: &Value, fields: &[(&str, bool)], mut rows: Vec<Row>) -> Vec<Row> {
+    let referenced = fields.iter().any(|(field, _)| {
+        field
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    });
+    if !referenced {
+        return rows;
+    }
+    match value {
+        Value::Object(children) => children.iter().fold(rows, |rows, (key, value)| {
+            add_values(&format!("{}.{}", path, key), value, fields, rows)
+        }),
+        Value::Array(values) => values
+            .iter()
+            .flat_map(|value| add_values(path, value, fields, rows.clone()))
+            .collect(),
+        Value::Null => rows,
+        scalar => {
+            let value = match scalar {
+                Value::Number(n) => RowValue::Number(n.as_f64().unwrap_or_default()),
+                // Compare with a number as a number what would be written as one
+                Value::String(s) if compared_with_number(path, fields) => {
+                    match infer_scalar(s.clone()) {
+                        Value::Number(n) => RowValue::Number(n.as_f64().unwrap_or_default()),
+                        _ => RowValue::Text(s.clone()),
+                    }
+                }
+                Value::String(s) => RowValue::Text(s.clone()),
+                other => RowValue::Text(other.to_string()),
+            };
+            for row in &mut rows {
+                row.insert(path, value.clone());
+            }
+            rows
+        }
+    }
+}
+
+fn compared_with_number(path: &str, fields: &[(&str, bool)]) -> bool {
+    fields
+        .iter()
+        .any(|&(field, numeric)| numeric && field == path)
+}
+
+pub fn process_xml<R: BufRead, W: Write>(
+    reader: R,
+    writer: &mut W,
+    query: Option<&Expr>,
+) -> Result<()> {
+    process_xml_with_options(reader, writer, query, &XmlOptions::default())
+}
+
+/// Like [`process_xml`], choosing records and field names with `options`
+pub fn process_xml_with_options<R: BufRead, W: Write>(
+    reader: R,
+    writer: &mut W,
+    query: Option<&Expr>,
+    options: &XmlOptions,
+) -> Result<()> {
+    for record in records(reader, options) {
+        let record = record?;
+        if query.is_none_or(|expr| matches_query(expr, &record)) {
+            let json = serde_json::to_string(&record.fields)?;
+            writeln!(writer, "{}", json)?;
+        }
+    }
     Ok(())
 }
 
@@ -119,4 +473,122 @@ mod tests {
         let lines: Vec<&str> = output_str.lines().collect();
         assert_eq!(lines.len(), 2);
     }
+
+    #[test]
+    fn test_record_path_and_repeated_children() {
+        let xml_input = r#"<ns:Orders xmlns:ns="urn:orders">
+            <ns:Header><ns:Order><ns:Number>IGNORED</ns:Number></ns:Order></ns:Header>
+            <ns:Order>
+                <ns:Number>PO1</ns:Number>
+                <ns:Line><ns:Sku>A</ns:Sku></ns:Line>
+                <ns:Line><ns:Sku>B</ns:Sku></ns:Line>
+                <ns:Note/>
+            </ns:Order>
+        </ns:Orders>"#;
+        let options = XmlOptions {
+            record_path: Some("/Orders/Order".parse().unwrap()),
+            strip_namespaces: true,
+            ..Default::default()
+        };
+        let found: Vec<XmlRecord> = records(xml_input.as_bytes(), &options)
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            Value::Object(found[0].fields.clone()),
+            serde_json::json!({
+                "Number": "PO1",
+                "Line": [{"Sku": "A"}, {"Sku": "B"}],
+                "Note": "",
+            })
+        );
+
+        let prefixed: RecordPath = "//ns:Order".parse().unwrap();
+        let options = XmlOptions {
+            record_path: Some(prefixed),
+            ..Default::default()
+        };
+        assert_eq!(records_count(xml_input, &options), 2);
+        assert!("/Orders//Order".parse::<RecordPath>().is_err());
+    }
+
+    #[test]
+    fn test_attributes_nesting_and_types() {
+        let xml_input = r#"<Orders>
+            <Order id="7" xmlns:x="urn:x">
+                <Number>PO1</Number>
+                <Buyer code="0042">ACME</Buyer>
+                <Line no="1"><Sku>A</Sku><Qty>3</Qty><Rush>true</Rush></Line>
+                <Line no="2"><Sku>B</Sku><Qty>12.5</Qty></Line>
+                <Ship><Address><City>Lima</City></Address></Ship>
+            </Order>
+        </Orders>"#;
+        let options = XmlOptions {
+            record_path: Some("Order".parse().unwrap()),
+            infer_types: true,
+            ..Default::default()
+        };
+        let found: Vec<XmlRecord> = records(xml_input.as_bytes(), &options)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            Value::Object(found[0].fields.clone()),
+            serde_json::json!({
+                "@id": 7,
+                "Number": "PO1",
+                "Buyer": {"@code": "0042", "#text": "ACME"},
+                "Line": [
+                    {"@no": 1, "Sku": "A", "Qty": 3, "Rush": true},
+                    {"@no": 2, "Sku": "B", "Qty": 12.5},
+                ],
+                "Ship": {"Address": {"City": "Lima"}},
+            })
+        );
+
+        let matches = |query: &str| {
+            let expr = engine_filereduce::query::parser::Parser::new(query).parse();
+            matches_query(&expr, &found[0])
+        };
+        assert!(matches("Ship.Address.City = 'Lima'"));
+        assert!(matches("Buyer.@code = '0042' AND @id = 7"));
+        assert!(!matches("Buyer.@code = 42"));
+        assert!(matches("Line.Sku = 'B' AND Line.Qty > 10"));
+        assert!(!matches("Line.Sku = 'A' AND Line.Qty > 10"));
+
+        // Only the arrays the query refers to multiply the rows
+        let rows = |query: &str| {
+            let expr = engine_filereduce::query::parser::Parser::new(query).parse();
+            query_rows(&expr, &found[0]).len()
+        };
+        assert_eq!(rows("Number = 'PO1'"), 1);
+        assert_eq!(rows("Line.Sku = 'A'"), 2);
+
+        // Signed codes keep their leading zeros; fractions below one are numbers
+        assert_eq!(infer_scalar("-007".to_string()), "-007");
+        assert_eq!(infer_scalar("0.5".to_string()), 0.5);
+        assert_eq!(infer_scalar("-0.5".to_string()), -0.5);
+
+        // Without inferred types every value is text, and a text literal matches it
+        let found: Vec<XmlRecord> = records(
+            xml_input.as_bytes(),
+            &XmlOptions {
+                infer_types: false,
+                ..options
+            },
+        )
+        .collect::<Result<_>>()
+        .unwrap();
+        assert_eq!(found[0].fields["Line"][0]["Qty"], "3");
+        let expr = engine_filereduce::query::parser::Parser::new("Line.Qty = '3'").parse();
+        assert!(matches_query(&expr, &found[0]));
+        let expr =
+            engine_filereduce::query::parser::Parser::new("Line.Sku = 'B' AND Line.Qty > 10")
+                .parse();
+        assert!(matches_query(&expr, &found[0]));
+    }
+
+    fn records_count(xml: &str, options: &XmlOptions) -> usize {
+        records(xml.as_bytes(), options).count()
+    }
 }
@@ -1,10 +1,14 @@
+use crate::core::{EdifactProcessor, Event, RunSummary};
+use crate::dedupe::Deduplicator;
 use crate::error::Result;
 use crate::model::streaming::{StreamingDocument, StreamingLine};
-use crate::parser::edifact::parse_segment_with_registry;
-use crate::parser::segment::Segment;
+use crate::parallel::ParallelEvents;
+use crate::parser::charset::Charset;
+use crate::parser::xml::{self, XmlOptions};
 use crate::sink::{DataSink, SinkItem};
-
-use crate::core::apply_dynamic_segment;
+use crate::telemetry::TelemetryCollector;
+use crate::tree::OutputMode;
+use crate::validation::StrictnessPolicy;
 use engine_filereduce::executor::executor::eval;
 use engine_filereduce::query::ast::Expr;
 use engine_filereduce::row::{Row, RowKind, Value};
@@ -18,18 +22,80 @@ pub enum FileFormat {
     Json,
 }
 
+/// Per-run settings for [`process`]
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// Overrides the charset declared by the UNB syntax identifier
+    pub charset: Option<Charset>,
+    /// Drops messages whose envelope validation ends in an error
+    pub reject_invalid: bool,
+    /// Adds `_source` (input position) to documents and lines
+    pub source_positions: bool,
+    /// Sends a [`MessageTree`](crate::tree::MessageTree) per message instead of the
+    /// document. The query still filters on the document fields.
+    pub output_mode: OutputMode,
+    /// Parses messages on this many worker threads when above 1, see
+    /// [`ParallelEvents`]
+    pub threads: usize,
+    /// With `threads`, writes messages as they finish instead of in input order
+    pub unordered: bool,
+    /// Sets failing messages aside (see [`ProcessOutputs::dead_letters`]) and keeps
+    /// going, instead of stopping at the first unreadable segment
+    pub tolerant: bool,
+    /// Treatment of unknown segments and empty mandatory elements, per UNB sender
+    pub strictness: StrictnessPolicy,
+    /// Record selection and field naming of XML inputs
+    pub xml: XmlOptions,
+}
+
+/// Where [`process_with_options`] sends what is not a document
+#[derive(Default)]
+pub struct ProcessOutputs<'a> {
+    /// EDIFACT validation reports, one per message and per interchange
+    pub reports: Option<&'a mut dyn DataSink>,
+    /// [`DeadLetter`](crate::core::DeadLetter)s of a tolerant run
+    pub dead_letters: Option<&'a mut dyn DataSink>,
+    /// Counts unknown segments and unmapped elements, see [`TelemetryCollector::report`]
+    pub telemetry: Option<&'a mut TelemetryCollector>,
+    /// Drops, flags or replaces messages already written by this or an earlier run,
+    /// once they pass the query. Their keys are committed batch by batch for sinks that
+    /// report what they stored, and otherwise after the sink is flushed at the end of a
+    /// successful run.
+    pub dedupe: Option<Deduplicator>,
+}
+
 pub async fn process<R: BufRead + Send>(
     reader: R,
     sink: &mut dyn DataSink,
     format: FileFormat,
     query: Option<&Expr>,
 ) -> Result<()> {
+    process_with_options(
+        reader,
+        sink,
+        format,
+        query,
+        &ProcessOptions::default(),
+        ProcessOutputs::default(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Like [`process`], sending EDIFACT validation reports and dead letters to
+/// `outputs`. Returns how many messages were written, rejected, filtered out by the
+/// query and duplicated.
+pub async fn process_with_options<R: BufRead + Send>(
+    reader: R,
+    sink: &mut dyn DataSink,
+    format: FileFormat,
+    query: Option<&Expr>,
+    options: &ProcessOptions,
+    outputs: ProcessOutputs<'_>,
+) -> Result<RunSummary> {
     match format {
-        FileFormat::Edifact => process_edifact(reader, sink, query).await,
-        FileFormat::Xml => Err(crate::error::FileReduceError::Io(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "XML not yet supported with async Sink",
-        ))),
+        FileFormat::Edifact => process_edifact(reader, sink, query, options, outputs).await,
+        FileFormat::Xml => process_xml(reader, sink, query, &options.xml).await,
         FileFormat::Json => Err(crate::error::FileReduceError::Io(std::io::Error::new(
             std::io::ErrorKind::Other,
             "JSON not yet supported with async Sink",
@@ -41,181 +107,230 @@ async fn process_edifact<R: BufRead>(
     reader: R,
     sink: &mut dyn DataSink,
     query: Option<&Expr>,
-) -> Result<()> {
-    let mut current_doc: Option<StreamingDocument> = None;
-    let mut current_line: Option<StreamingLine> = None;
-    let mut interchange_id = String::new();
-    let mut sender_id = String::new();
-    let mut receiver_id = String::new();
-    let registry = crate::translations::TranslationRegistry::new().ok();
-
-    for line in reader.lines() {
-        let raw = line?;
-        if raw.trim().is_empty() {
-            continue;
-        }
-        let segment = parse_segment_with_registry(&raw, registry.as_ref());
+    options: &ProcessOptions,
+    mut outputs: ProcessOutputs<'_>,
+) -> Result<RunSummary> {
+    let mut processor = EdifactProcessor::new()
+        .with_reject_invalid(options.reject_invalid)
+        .with_tolerant(options.tolerant)
+        .with_strictness(options.strictness.clone())
+        .with_telemetry(outputs.telemetry.is_some())
+        .with_source_positions(options.source_positions)
+        .with_output_mode(options.output_mode);
+    if let Some(charset) = options.charset {
+        processor = processor.with_charset(charset);
+    }
 
-        match segment {
-            Segment::UNB(s, r, id) => {
-                sender_id = s.to_string();
-                receiver_id = r.to_string();
-                interchange_id = id.to_string();
-            }
-            Segment::UNH => {
-                current_doc = Some(StreamingDocument {
-                    interchange_id: interchange_id.clone(),
-                    sender: sender_id.clone(),
-                    receiver: receiver_id.clone(),
-                    ..Default::default()
-                });
-            }
-            Segment::BGM(code, num) => {
-                if let Some(doc) = current_doc.as_mut() {
-                    doc.document_number = num.to_string();
-                    doc.doc_type = match code {
-                        "220" => "ORDERS".to_string(),
-                        _ => code.to_string(),
-                    };
+    let mut documents;
+    let mut sequential;
+    let mut parallel;
+    let events: &mut dyn Iterator<Item = Result<Event>> = if options.threads > 1 {
+        parallel = ParallelEvents::new(&processor, reader, options.threads, !options.unordered);
+        &mut parallel
+    } else {
+        documents = processor.documents(reader);
+        sequential = std::iter::from_fn(|| documents.next_event());
+        &mut sequential
+    };
+    let mut summary = RunSummary::default();
+    for event in events {
+        match event? {
+            Event::Message {
+                mut document,
+                mut tree,
+            } => {
+                if !query.is_none_or(|expr| matches_query(expr, &document)) {
+                    summary.filtered += 1;
+                    continue;
                 }
-            }
-            Segment::DTM(qualifier, date) => {
-                if let Some(doc) = current_doc.as_mut() {
-                    match qualifier {
-                        "137" => doc.document_date = Some(date.to_string()),
-                        "2" => doc.requested_delivery_date = Some(date.to_string()),
-                        _ => {}
+                if let Some(dedupe) = outputs.dedupe.as_mut() {
+                    if !dedupe.admit(&mut document, tree.as_mut())? {
+                        continue;
                     }
                 }
-            }
-            Segment::NAD("BY", id) => {
-                if let Some(doc) = current_doc.as_mut() {
-                    doc.buyer = Some(id.to_string());
-                }
-            }
-            Segment::NAD("SU", id) => {
-                if let Some(doc) = current_doc.as_mut() {
-                    doc.seller = Some(id.to_string());
-                }
-            }
-            Segment::LIN(line_num, sku) => {
-                if let Some(line) = current_line.take() {
-                    if let Some(doc) = current_doc.as_mut() {
-                        doc.lines.push(line);
+                match tree {
+                    Some(tree) => {
+                        sink.send(SinkItem::Raw(serde_json::to_value(tree)?))
+                            .await?
                     }
+                    None => sink.send(SinkItem::Document(*document)).await?,
                 }
-                current_line = Some(StreamingLine {
-                    line_no: line_num.parse().unwrap_or(0),
-                    sku: sku.to_string(),
-                    ..Default::default()
-                });
-            }
-            Segment::QTY(_, qty, unit) => {
-                if let Some(line) = current_line.as_mut() {
-                    line.qty = qty.parse().ok();
-                    line.uom = if !unit.is_empty() {
-                        Some(unit.to_string())
-                    } else {
-                        None
-                    };
+                summary.accepted += 1;
+                if let (Some(dedupe), Some(stored)) = (outputs.dedupe.as_mut(), sink.stored()) {
+                    dedupe.commit_stored(stored)?;
                 }
             }
-            Segment::MOA(_, amt) => {
-                if let Some(line) = current_line.as_mut() {
-                    line.amount = amt.parse().ok();
+            Event::Report(entry) => {
+                if let Some(sink) = outputs.reports.as_mut() {
+                    sink.send(SinkItem::Raw(serde_json::to_value(&entry)?))
+                        .await?;
                 }
             }
-            Segment::CNT(code, val) => {
-                if code == "2" {
-                    if let Some(doc) = current_doc.as_mut() {
-                        doc.line_count_check = val.parse().ok();
-                    }
+            Event::Unmapped(segment) => {
+                if segment.unknown {
+                    tracing::warn!(
+                        "Unknown segment encountered: {} at {}",
+                        segment.segment,
+                        segment.position
+                    );
                 }
-            }
-            Segment::CUX(curr) => {
-                if let Some(doc) = current_doc.as_mut() {
-                    doc.currency = curr.to_string();
+                if let Some(telemetry) = outputs.telemetry.as_deref_mut() {
+                    telemetry.record(&segment);
                 }
             }
-            Segment::UNT => {
-                if let Some(line) = current_line.take() {
-                    if let Some(doc) = current_doc.as_mut() {
-                        doc.lines.push(line);
-                    }
+            Event::Rejected(letter) => {
+                summary.rejected += 1;
+                tracing::warn!(
+                    "Message {} rejected: {}",
+                    letter.message_reference.as_deref().unwrap_or("-"),
+                    letter.reason
+                );
+                if let Some(sink) = outputs.dead_letters.as_mut() {
+                    sink.send(SinkItem::Raw(serde_json::to_value(&letter)?))
+                        .await?;
                 }
+            }
+        }
+    }
+    if let Some(dedupe) = outputs.dedupe.as_mut() {
+        // The remaining keys are only remembered once the messages are in the sink
+        sink.flush().await?;
+        dedupe.commit()?;
+        summary.duplicates = dedupe.duplicates();
+    }
 
-                if let Some(doc) = current_doc.take() {
-                    let should_write = if let Some(expr) = query {
-                        let mut matched = false;
-                        let mut base_row = Row::new(RowKind::UNH);
-                        base_row.insert("number", Value::Text(doc.document_number.clone()));
-                        base_row.insert("doc_type", Value::Text(doc.doc_type.clone()));
-                        base_row.insert("interchange_id", Value::Text(doc.interchange_id.clone()));
-                        base_row.insert("sender", Value::Text(doc.sender.clone()));
-                        if let Some(val) = &doc.document_date {
-                            base_row.insert("date", Value::Text(val.clone()));
-                        }
-                        if let Some(val) = &doc.buyer {
-                            base_row.insert("buyer", Value::Text(val.clone()));
-                        }
-                        if let Some(val) = &doc.seller {
-                            base_row.insert("seller", Value::Text(val.clone()));
-                        }
+    Ok(summary)
+}
 
-                        if doc.lines.is_empty() {
-                            if eval(expr, &base_row) {
-                                matched = true;
-                            }
-                        } else {
-                            for line in &doc.lines {
-                                let mut row = base_row.clone();
-                                row.kind = RowKind::LIN;
-                                row.insert("sku", Value::Text(line.sku.clone()));
-                                if let Some(q) = line.qty {
-                                    row.insert("qty", Value::Number(q));
-                                }
-                                if let Some(a) = line.amount {
-                                    row.insert("amount", Value::Number(a));
-                                }
-                                if eval(expr, &row) {
-                                    matched = true;
-                                    break;
-                                }
-                            }
-                        }
-                        matched
-                    } else {
-                        true
-                    };
+/// Sends each XML record matching `query` to `sink` as a JSON object
+async fn process_xml<R: BufRead>(
+    reader: R,
+    sink: &mut dyn DataSink,
+    query: Option<&Expr>,
+    options: &XmlOptions,
+) -> Result<RunSummary> {
+    let mut summary = RunSummary::default();
+    for record in xml::records(reader, options) {
+        let record = record?;
+        if !query.is_none_or(|expr| xml::matches_query(expr, &record)) {
+            summary.filtered += 1;
+            continue;
+        }
+        sink.send(SinkItem::Raw(serde_json::Value::Object(record.fields)))
+            .await?;
+        summary.accepted += 1;
+    }
+    Ok(summary)
+}
 
-                    if should_write {
-                        sink.send(SinkItem::Document(doc)).await?;
-                    }
-                }
-            }
-            Segment::UNZ => {}
-            Segment::Dynamic {
-                code,
-                qualifier,
-                elements: element_groups,
-            } => {
-                if let Some(reg) = &registry {
-                    apply_dynamic_segment(
-                        code,
-                        qualifier,
-                        &element_groups,
-                        reg,
-                        &mut current_doc,
-                        &mut current_line,
-                    );
-                }
-            }
-            Segment::Unknown(code) => {
-                tracing::warn!("Unknown segment encountered: {}", code);
+/// True when the document header, or any of its lines, matches `expr`
+fn matches_query(expr: &Expr, doc: &StreamingDocument) -> bool {
+    let mut base_row = Row::new(RowKind::UNH);
+    base_row.insert("number", Value::Text(doc.document_number.clone()));
+    base_row.insert("doc_type", Value::Text(doc.doc_type.clone()));
+    base_row.insert("message_type", Value::Text(doc.message_type.clone()));
+    base_row.insert("interchange_id", Value::Text(doc.interchange_id.clone()));
+    base_row.insert("sender", Value::Text(doc.sender.clone()));
+    if let Some(group) = &doc.group {
+        base_row.insert("group_reference", Value::Text(group.reference.clone()));
+        if doc.message_type.is_empty() {
+            base_row.insert("message_type", Value::Text(group.message_type.clone()));
+        }
+    }
+    if let Some(val) = &doc.document_date {
+        base_row.insert("date", Value::Text(val.clone()));
+    }
+    if let Some(val) = &doc.buyer {
+        base_row.insert("buyer", Value::Text(val.clone()));
+    }
+    if let Some(val) = &doc.seller {
+        base_row.insert("seller", Value::Text(val.clone()));
+    }
+
+    // A repeated party qualifier is matched one repeat at a time
+    let repeats = doc.parties.values().map(Vec::len).max().unwrap_or(1);
+    (0..repeats.max(1)).any(|repeat| {
+        let mut row = base_row.clone();
+        insert_qualified(&mut row, doc, repeat);
+        matches_lines(expr, &row, doc)
+    })
+}
+
+fn matches_lines(expr: &Expr, base_row: &Row, doc: &StreamingDocument) -> bool {
+    if doc.lines.is_empty() {
+        return eval(expr, base_row);
+    }
+    doc.lines.iter().any(|line| {
+        let mut row = base_row.clone();
+        row.kind = RowKind::LIN;
+        row.insert("sku", Value::Text(line.sku.clone()));
+        if let Some(q) = line.qty {
+            row.insert("qty", Value::Number(q));
+        }
+        if let Some(a) = line.amount {
+            row.insert("amount", Value::Number(a));
+        }
+        insert_line_qualified(&mut row, line);
+        eval(expr, &row)
+    })
+}
+
+/// Adds the parties, references and dates of `doc` by qualifier: `party.DP` (id),
+/// `party.DP.name` (and `street`, `city`, `region`, `postal_code`, `country`),
+/// `party.BY.ref.VA`, `ref.ON` and `date.137`. Of a repeated qualifier the row gets
+/// the `repeat`-th party, or the last one when there are fewer.
+fn insert_qualified(row: &mut Row, doc: &StreamingDocument, repeat: usize) {
+    for (qualifier, parties) in &doc.parties {
+        let Some(party) = parties.get(repeat).or(parties.last()) else {
+            continue;
+        };
+        let key = format!("party.{}", qualifier);
+        let fields = [
+            ("", &party.id),
+            (".agency", &party.agency),
+            (".name", &party.name),
+            (".street", &party.street),
+            (".city", &party.city),
+            (".region", &party.region),
+            (".postal_code", &party.postal_code),
+            (".country", &party.country),
+        ];
+        for (suffix, value) in fields {
+            if let Some(value) = value {
+                row.insert(&format!("{}{}", key, suffix), Value::Text(value.clone()));
             }
-            _ => {}
         }
+        for (reference, value) in &party.references {
+            row.insert(
+                &format!("{}.ref.{}", key, reference),
+                Value::Text(value.clone()),
+            );
+        }
+    }
+    for (qualifier, value) in &doc.references {
+        row.insert(&format!("ref.{}", qualifier), Value::Text(value.clone()));
+    }
+    for (qualifier, date) in &doc.dates {
+        row.insert(
+            &format!("date.{}", qualifier),
+            Value::Text(date.value.clone()),
+        );
     }
+}
 
-    Ok(())
+/// Adds the quantities, amounts and prices of `line` by qualifier: `qty.21`, `uom.21`,
+/// `amount.203` and `price.AAA`
+fn insert_line_qualified(row: &mut Row, line: &StreamingLine) {
+    for (qualifier, quantity) in &line.quantities {
+        row.insert(&format!("qty.{}", qualifier), Value::Number(quantity.value));
+        if let Some(unit) = &quantity.unit {
+            row.insert(&format!("uom.{}", qualifier), Value::Text(unit.clone()));
+        }
+    }
+    for (qualifier, amount) in &line.amounts {
+        row.insert(&format!("amount.{}", qualifier), Value::Number(*amount));
+    }
+    for (qualifier, price) in &line.prices {
+        row.insert(&format!("price.{}", qualifier), Value::Number(price.value));
+    }
 }
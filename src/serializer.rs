@@ -1,7 +1,9 @@
 use crate::error::Result;
 use crate::model::streaming::StreamingDocument;
+use crate::parser::datetime::format_dtm;
 use crate::translations::TranslationRegistry;
 use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct EdifactSerializer {
     registry: TranslationRegistry,
@@ -46,7 +48,28 @@ impl EdifactSerializer {
             doc.sender, doc.receiver, doc.interchange_id
         ));
 
+        // UNG segment (functional group header)
+        if let Some(group) = &doc.group {
+            // Date and time of preparation are mandatory; groups that were read without
+            // them get the generation time
+            let (date, time) = if group.date.is_empty() || group.time.is_empty() {
+                generation_time()
+            } else {
+                (group.date.clone(), group.time.clone())
+            };
+            segments.push(format!(
+                "UNG+{}+{}+{}+{}:{}+{}'",
+                group.message_type,
+                group.application_sender,
+                group.application_receiver,
+                date,
+                time,
+                group.reference
+            ));
+        }
+
         // UNH segment
+        let message_start = segments.len();
         segments.push("UNH+1'".to_string());
 
         // BGM segment
@@ -58,10 +81,10 @@ impl EdifactSerializer {
 
         // DTM segments
         if let Some(date) = &doc.document_date {
-            segments.push(format!("DTM+137:{}'", date));
+            segments.push(format!("DTM+137:{}'", format_dtm(date)));
         }
         if let Some(delivery_date) = &doc.requested_delivery_date {
-            segments.push(format!("DTM+2:{}'", delivery_date));
+            segments.push(format!("DTM+2:{}'", format_dtm(delivery_date)));
         }
 
         // NAD segments
@@ -80,17 +103,31 @@ impl EdifactSerializer {
         // Lines
         for line in &doc.lines {
             segments.push(format!("LIN+{}+++{}'", line.line_no, line.sku));
-            if let Some(qty) = line.qty {
-                let uom = line.uom.as_deref().unwrap_or("");
-                let suffix = if uom.is_empty() {
-                    String::new()
-                } else {
-                    format!(":{}", uom)
-                };
-                segments.push(format!("QTY+1:{}{}'", qty, suffix));
+            let unit = |unit: Option<&str>| unit.map(|u| format!(":{}", u)).unwrap_or_default();
+            // Qualified values when the line has them, the primary ones otherwise
+            if line.quantities.is_empty() {
+                if let Some(qty) = line.qty {
+                    segments.push(format!("QTY+1:{}{}'", qty, unit(line.uom.as_deref())));
+                }
+            }
+            for (qualifier, quantity) in &line.quantities {
+                segments.push(format!(
+                    "QTY+{}:{}{}'",
+                    qualifier,
+                    quantity.value,
+                    unit(quantity.unit.as_deref())
+                ));
+            }
+            if line.amounts.is_empty() && line.prices.is_empty() {
+                if let Some(amount) = line.amount {
+                    segments.push(format!("MOA+1:{}'", amount));
+                }
+            }
+            for (qualifier, amount) in &line.amounts {
+                segments.push(format!("MOA+{}:{}'", qualifier, amount));
             }
-            if let Some(amount) = line.amount {
-                segments.push(format!("MOA+1:{}'", amount));
+            for (qualifier, price) in &line.prices {
+                segments.push(format!("PRI+{}:{}'", qualifier, price.value));
             }
         }
 
@@ -100,9 +137,14 @@ impl EdifactSerializer {
         }
 
         // UNT segment (count of segments from UNH to UNT inclusive)
-        let segment_count = segments.len() + 1; // including UNT itself
+        let segment_count = segments.len() - message_start + 1; // including UNT itself
         segments.push(format!("UNT+{}+1'", segment_count));
 
+        // UNE segment (functional group trailer)
+        if let Some(group) = &doc.group {
+            segments.push(format!("UNE+1+{}'", group.reference));
+        }
+
         // UNZ segment (interchange trailer)
         segments.push("UNZ+1+0'".to_string());
 
@@ -110,6 +152,28 @@ impl EdifactSerializer {
     }
 }
 
+/// Current UTC date (`YYMMDD`) and time (`HHMM`)
+fn generation_time() -> (String, String) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, secs) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date of a day count since 1970-01-01, in 400-year eras starting in March
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        format!("{:02}{:02}{:02}", year % 100, month, day),
+        format!("{:02}{:02}", secs / 3_600, secs % 3_600 / 60),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,13 +187,18 @@ mod tests {
             interchange_id: "12345".to_string(),
             sender: "SENDER".to_string(),
             receiver: "RECEIVER".to_string(),
+            group: None,
             doc_type: "ORDERS".to_string(),
+            message_type: "ORDERS".to_string(),
             document_number: "ORDER001".to_string(),
-            document_date: Some("20240415".to_string()),
+            document_date: Some("2024-04-15".to_string()),
             requested_delivery_date: Some("20240420".to_string()),
             currency: "USD".to_string(),
             buyer: Some("BUYER001".to_string()),
             seller: Some("SELLER001".to_string()),
+            parties: Default::default(),
+            references: Default::default(),
+            dates: Default::default(),
             line_count_check: Some(2),
             lines: vec![
                 StreamingLine {
@@ -138,7 +207,13 @@ mod tests {
                     qty: Some(10.0),
                     uom: Some("KGM".to_string()),
                     amount: Some(100.0),
+                    quantities: Default::default(),
+                    amounts: Default::default(),
+                    prices: Default::default(),
                     extra: Default::default(),
+                    typed_extra: Default::default(),
+                    groups: Vec::new(),
+                    source: None,
                 },
                 StreamingLine {
                     line_no: 2,
@@ -146,15 +221,26 @@ mod tests {
                     qty: Some(5.0),
                     uom: Some("PCE".to_string()),
                     amount: Some(50.0),
+                    quantities: Default::default(),
+                    amounts: Default::default(),
+                    prices: Default::default(),
                     extra: Default::default(),
+                    typed_extra: Default::default(),
+                    groups: Vec::new(),
+                    source: None,
                 },
             ],
             extra: Default::default(),
+            typed_extra: Default::default(),
+            groups: Vec::new(),
+            details: None,
+            source: None,
+            duplicate: None,
         };
         let edifact = serializer.serialize_document(&doc).unwrap();
         assert!(edifact.contains("UNB+UNOC:3+SENDER:14+RECEIVER:14+12345:0'"));
         assert!(edifact.contains("BGM+220+ORDER001'"));
-        assert!(edifact.contains("DTM+137:20240415'"));
+        assert!(edifact.contains("DTM+137:20240415:102'"));
         assert!(edifact.contains("DTM+2:20240420'"));
         assert!(edifact.contains("NAD+BY+BUYER001'"));
         assert!(edifact.contains("NAD+SU+SELLER001'"));
@@ -166,7 +252,39 @@ mod tests {
         assert!(edifact.contains("QTY+1:5:PCE'"));
         assert!(edifact.contains("MOA+1:50'"));
         assert!(edifact.contains("CNT+2:2'"));
-        assert!(edifact.contains("UNT+"));
+        assert!(edifact.contains("UNT+15+1'"));
         assert!(edifact.contains("UNZ+1+0'"));
     }
+
+    #[test]
+    fn test_serialize_functional_group() {
+        let serializer = EdifactSerializer::new(TranslationRegistry::new().unwrap());
+        let doc = StreamingDocument {
+            group: Some(crate::model::streaming::FunctionalGroup {
+                reference: "GRP1".to_string(),
+                message_type: "ORDERS".to_string(),
+                application_sender: "APPS".to_string(),
+                application_receiver: "APPR".to_string(),
+                date: "260121".to_string(),
+                time: "1200".to_string(),
+            }),
+            doc_type: "ORDERS".to_string(),
+            document_number: "ORDER001".to_string(),
+            ..Default::default()
+        };
+        let edifact = serializer.serialize_document(&doc).unwrap();
+        assert!(edifact.contains("UNG+ORDERS+APPS+APPR+260121:1200+GRP1'"));
+        assert!(edifact.contains("UNT+3+1'\nUNE+1+GRP1'"));
+
+        // A group read without its date gets the generation time
+        let mut doc = doc;
+        if let Some(group) = doc.group.as_mut() {
+            group.date.clear();
+        }
+        let edifact = serializer.serialize_document(&doc).unwrap();
+        let ung = edifact.lines().find(|s| s.starts_with("UNG")).unwrap();
+        let (date, time) = ung.split('+').nth(4).unwrap().split_once(':').unwrap();
+        assert_eq!((date.len(), time.len()), (6, 4));
+        assert!(date.chars().chain(time.chars()).all(|c| c.is_ascii_digit()));
+    }
 }
@@ -83,4 +83,8 @@ impl DataSink for DbDataSink {
         );
         Ok(())
     }
+
+    fn stored(&self) -> Option<u64> {
+        Some(self.success_count)
+    }
 }
@@ -19,4 +19,10 @@ pub enum SinkItem {
 pub trait DataSink: Send + Sync {
     async fn send(&mut self, item: SinkItem) -> Result<()>;
     async fn flush(&mut self) -> Result<()>;
+
+    /// How many of the items sent so far are stored for good, for sinks that write
+    /// batches as they go. `None` when that is only known once `flush` returns.
+    fn stored(&self) -> Option<u64> {
+        None
+    }
 }
@@ -0,0 +1,202 @@
+use crate::parser::segment_reader::SourcePosition;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Distinct raw segments kept per entry of the report
+const SAMPLES: usize = 3;
+
+/// A segment of a message that the dictionary does not describe in full
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnmappedSegment {
+    /// Directory of the message as declared in UNH (`D96A`)
+    pub version: Option<String>,
+    pub message_type: String,
+    pub sender: String,
+    pub segment: String,
+    /// Neither the dictionary nor the parser knows the segment
+    pub unknown: bool,
+    /// Positions, numbered as in the dictionary, of elements that carry a value but
+    /// have no dictionary entry
+    pub elements: Vec<usize>,
+    pub raw: String,
+    pub position: SourcePosition,
+}
+
+/// Unknown segments and unmapped elements of one segment code, for one version,
+/// message type and sender
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub message_type: String,
+    pub sender: String,
+    pub segment: String,
+    /// Occurrences of the segment while unknown
+    pub unknown: u64,
+    /// Occurrences with a value of each element position the dictionary lacks
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unmapped_elements: BTreeMap<usize, u64>,
+    /// First distinct raw segments seen
+    pub samples: Vec<String>,
+}
+
+impl TelemetryEntry {
+    fn occurrences(&self) -> u64 {
+        self.unknown + self.unmapped_elements.values().sum::<u64>()
+    }
+
+    fn sample(&mut self, raw: &str) {
+        if self.samples.len() < SAMPLES && !self.samples.iter().any(|s| s == raw) {
+            self.samples.push(raw.to_string());
+        }
+    }
+
+    fn absorb(&mut self, other: TelemetryEntry) {
+        self.unknown += other.unknown;
+        for (position, count) in other.unmapped_elements {
+            *self.unmapped_elements.entry(position).or_default() += count;
+        }
+        for raw in &other.samples {
+            self.sample(raw);
+        }
+    }
+}
+
+/// What the dictionary is missing, most frequent first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryReport {
+    pub unknown_segments: u64,
+    pub unmapped_elements: u64,
+    pub segments: Vec<TelemetryEntry>,
+}
+
+type TelemetryKey = (Option<String>, String, String, String);
+
+/// Counts the [`UnmappedSegment`]s of one or more runs
+#[derive(Debug, Default)]
+pub struct TelemetryCollector {
+    entries: BTreeMap<TelemetryKey, TelemetryEntry>,
+}
+
+impl TelemetryCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, segment: &UnmappedSegment) {
+        let entry = self.entry(
+            &segment.version,
+            &segment.message_type,
+            &segment.sender,
+            &segment.segment,
+        );
+        if segment.unknown {
+            entry.unknown += 1;
+        }
+        for position in &segment.elements {
+            *entry.unmapped_elements.entry(*position).or_default() += 1;
+        }
+        entry.sample(&segment.raw);
+    }
+
+    /// Adds the counts of another collector, e.g. one per request into a shared one
+    pub fn merge(&mut self, other: TelemetryCollector) {
+        for (key, other) in other.entries {
+            match self.entries.get_mut(&key) {
+                Some(entry) => entry.absorb(other),
+                None => {
+                    self.entries.insert(key, other);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn report(&self) -> TelemetryReport {
+        let mut segments: Vec<TelemetryEntry> = self.entries.values().cloned().collect();
+        segments.sort_by_key(|entry| std::cmp::Reverse(entry.occurrences()));
+        TelemetryReport {
+            unknown_segments: segments.iter().map(|entry| entry.unknown).sum(),
+            unmapped_elements: segments
+                .iter()
+                .flat_map(|entry| entry.unmapped_elements.values())
+                .sum(),
+            segments,
+        }
+    }
+
+    fn entry(
+        &mut self,
+        version: &Option<String>,
+        message_type: &str,
+        sender: &str,
+        segment: &str,
+    ) -> &mut TelemetryEntry {
+        let key = (
+            version.clone(),
+            message_type.to_string(),
+            sender.to_string(),
+            segment.to_string(),
+        );
+        self.entries.entry(key).or_insert_with(|| TelemetryEntry {
+            version: version.clone(),
+            message_type: message_type.to_string(),
+            sender: sender.to_string(),
+            segment: segment.to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unmapped(segment: &str, unknown: bool, elements: Vec<usize>, raw: &str) -> UnmappedSegment {
+        UnmappedSegment {
+            version: Some("D96A".to_string()),
+            message_type: "ORDERS".to_string(),
+            sender: "SENDER".to_string(),
+            segment: segment.to_string(),
+            unknown,
+            elements,
+            raw: raw.to_string(),
+            position: SourcePosition::default(),
+        }
+    }
+
+    #[test]
+    fn test_report_groups_and_samples() {
+        let mut collector = TelemetryCollector::new();
+        collector.record(&unmapped("BGM", false, vec![4], "BGM+220+PO1+9+AB"));
+        for raw in [
+            "FTX+AAI+++A",
+            "FTX+AAI+++B",
+            "FTX+AAI+++A",
+            "FTX+AAI+++C",
+            "FTX+AAI+++D",
+        ] {
+            collector.record(&unmapped("FTX", true, Vec::new(), raw));
+        }
+        let mut other = TelemetryCollector::new();
+        other.record(&unmapped("BGM", false, vec![4, 5], "BGM+220+PO2+9+AB+X"));
+        collector.merge(other);
+
+        let report = collector.report();
+        assert_eq!(report.unknown_segments, 5);
+        assert_eq!(report.unmapped_elements, 3);
+        assert_eq!(report.segments[0].segment, "FTX");
+        assert_eq!(
+            report.segments[0].samples,
+            ["FTX+AAI+++A", "FTX+AAI+++B", "FTX+AAI+++C"]
+        );
+        assert_eq!(
+            report.segments[1].unmapped_elements,
+            BTreeMap::from([(4, 2), (5, 1)])
+        );
+        assert_eq!(report.segments[1].samples.len(), 2);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::translations::types::{DataFormat, DataType};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
@@ -6,6 +7,9 @@ pub struct TranslationConfig {
     pub version: String,
     pub metadata: Metadata,
     pub segments: BTreeMap<String, SegmentConfig>,
+    /// Message structures keyed by UNH message type (`ORDERS`, `INVOIC`, ...)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub messages: BTreeMap<String, MessageStructure>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -23,6 +27,9 @@ pub struct SegmentConfig {
     pub qualifiers: BTreeMap<String, SubSegmentConfig>,
     #[serde(default)]
     pub elements: BTreeMap<String, ElementConfig>,
+    /// Document and line fields filled from the segment, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bindings: Vec<FieldBinding>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -32,12 +39,193 @@ pub struct SubSegmentConfig {
     pub elements: BTreeMap<String, ElementConfig>,
 }
 
+/// Copies a value of the segment into a field of the document or of the current line,
+/// e.g. `{"target": "document.buyer", "from": "PartyId", "qualifier": "BY"}`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct FieldBinding {
+    pub target: BindingTarget,
+    /// Label of the element or component that holds the value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Position of the value when it has no label: element as numbered in the
+    /// dictionary, and component (1 when omitted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub element: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub component: Option<usize>,
+    /// Applies only to segments with this qualifier
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qualifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<BindingTransform>,
+    /// Leaves a field that already has a value, e.g. the message type from UNH
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub if_empty: bool,
+}
+
+/// Fields a [`FieldBinding`] can fill. Document fields are only set by segments at
+/// document level; line fields go to the line being read.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingTarget {
+    #[serde(rename = "document.document_number")]
+    DocumentNumber,
+    #[serde(rename = "document.doc_type")]
+    DocType,
+    #[serde(rename = "document.document_date")]
+    DocumentDate,
+    #[serde(rename = "document.requested_delivery_date")]
+    RequestedDeliveryDate,
+    #[serde(rename = "document.buyer")]
+    Buyer,
+    #[serde(rename = "document.seller")]
+    Seller,
+    #[serde(rename = "document.currency")]
+    Currency,
+    #[serde(rename = "document.line_count_check")]
+    LineCountCheck,
+    #[serde(rename = "line.line_no")]
+    LineNo,
+    #[serde(rename = "line.sku")]
+    Sku,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BindingTransform {
+    /// Normalizes a date with the format code in the component that follows it
+    Date,
+    /// Replaces the listed codes and keeps any other value
+    Map(BTreeMap<String, String>),
+}
+
+/// An element: just its label (`"DocumentNumber"`), a composite with labelled
+/// components, or a label with a data type (`{"label": "Quantity", "type": "numeric",
+/// "format": "n..15"}`). Composites and typed fields may be marked `"mandatory": true`;
+/// a bare label cannot, so a mandatory element without a type is `{"label": "...",
+/// "mandatory": true}`
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum ElementConfig {
     Simple(String),
     Composite {
         label: String,
-        components: BTreeMap<String, String>,
+        components: BTreeMap<String, ComponentConfig>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        mandatory: bool,
     },
+    Typed(TypedField),
+}
+
+/// A component of a composite element: its label, or a label with a data type
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ComponentConfig {
+    Simple(String),
+    Typed(TypedField),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TypedField {
+    pub label: String,
+    /// Defaults to numeric for `n` formats and text otherwise
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub data_type: Option<DataType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<DataFormat>,
+    /// Checked by the warn and strict policies
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mandatory: bool,
+}
+
+impl ElementConfig {
+    pub fn label(&self) -> &str {
+        match self {
+            ElementConfig::Simple(label) | ElementConfig::Composite { label, .. } => label,
+            ElementConfig::Typed(field) => &field.label,
+        }
+    }
+
+    /// Data type of the element value, for simple elements that declare one
+    pub fn typed(&self) -> Option<&TypedField> {
+        match self {
+            ElementConfig::Typed(field) => Some(field),
+            _ => None,
+        }
+    }
+
+    pub fn mandatory(&self) -> bool {
+        match self {
+            ElementConfig::Simple(_) => false,
+            ElementConfig::Composite { mandatory, .. } => *mandatory,
+            ElementConfig::Typed(field) => field.mandatory,
+        }
+    }
+}
+
+impl ComponentConfig {
+    pub fn label(&self) -> &str {
+        match self {
+            ComponentConfig::Simple(label) => label,
+            ComponentConfig::Typed(field) => &field.label,
+        }
+    }
+
+    pub fn typed(&self) -> Option<&TypedField> {
+        match self {
+            ComponentConfig::Typed(field) => Some(field),
+            ComponentConfig::Simple(_) => None,
+        }
+    }
+
+    pub fn mandatory(&self) -> bool {
+        self.typed().is_some_and(|field| field.mandatory)
+    }
+}
+
+impl From<String> for ComponentConfig {
+    fn from(label: String) -> Self {
+        ComponentConfig::Simple(label)
+    }
+}
+
+/// Layout of a message: the segments at message level (header and summary
+/// section) and the segment groups (loops) in between
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct MessageStructure {
+    #[serde(default)]
+    pub segments: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<SegmentGroupConfig>,
+    /// Qualifiers filling the single quantity and amount of each line
+    #[serde(default)]
+    pub primary: LinePrimary,
+}
+
+/// Qualifiers, most wanted first, whose QTY, MOA and PRI values fill `qty`/`uom` and
+/// `amount` of a line. A line with none of them keeps the last value seen.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct LinePrimary {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quantity: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amount: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub price: Vec<String>,
+}
+
+/// A segment group such as `SG2` (NAD with its RFF and CTA groups).
+///
+/// Each occurrence of `trigger` starts a new repetition of the group; `segments` are
+/// the other segments that may follow it inside the group.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SegmentGroupConfig {
+    pub name: String,
+    pub trigger: String,
+    #[serde(default)]
+    pub segments: Vec<String>,
+    /// Marks the line item group: each repetition becomes a document line
+    #[serde(default)]
+    pub line: bool,
+    #[serde(default)]
+    pub groups: Vec<SegmentGroupConfig>,
 }
@@ -1,5 +1,7 @@
 pub mod config;
 pub mod registry;
+pub mod types;
 
 pub use config::*;
 pub use registry::TranslationRegistry;
+pub use types::{convert_value, CharClass, DataFormat, DataType};
@@ -22,9 +22,13 @@ impl TranslationRegistry {
         let content = fs::read_to_string(path)?;
         let config: TranslationConfig =
             serde_json::from_str(&content).map_err(|e| FileReduceError::Serialization(e))?;
-        Ok(Self {
+        Ok(Self::from_config(config))
+    }
+
+    pub fn from_config(config: TranslationConfig) -> Self {
+        Self {
             config: RwLock::new(config),
-        })
+        }
     }
 
     /// Load translation configuration for a specific EDIFACT version
@@ -91,6 +95,16 @@ impl TranslationRegistry {
         config.segments.get(segment_code).cloned()
     }
 
+    /// Whether `segment_code` is defined and, if so, whether its first element is a
+    /// qualifier. Used on the parsing hot path where cloning the config is too costly.
+    pub fn segment_uses_qualifier(&self, segment_code: &str) -> Option<bool> {
+        let config = self.config.read().unwrap();
+        config
+            .segments
+            .get(segment_code)
+            .map(|seg| seg.use_qualifier)
+    }
+
     pub fn get_qualifier(&self, segment_code: &str, qualifier: &str) -> Option<SubSegmentConfig> {
         let config = self.config.read().unwrap();
         config.segments.get(segment_code).and_then(|seg| {
@@ -110,6 +124,31 @@ impl TranslationRegistry {
             .and_then(|seg| seg.elements.get(element_pos).cloned())
     }
 
+    /// Structure (segment groups) of a message type, if the config describes it
+    pub fn get_message_structure(&self, message_type: &str) -> Option<MessageStructure> {
+        let config = self.config.read().unwrap();
+        config.messages.get(message_type).cloned()
+    }
+
+    /// Copies the message structures of `other` that this config lacks, and its segment
+    /// definitions over the ones here. Version files generated by the scraper only carry
+    /// segments, labelled by the standard rather than with the field names the
+    /// processor maps, so `other` should be the base registry rather than the one of
+    /// another version.
+    pub fn inherit(&self, other: &TranslationRegistry) {
+        let other = other.config.read().unwrap();
+        let mut config = self.config.write().unwrap();
+        for (message_type, structure) in &other.messages {
+            config
+                .messages
+                .entry(message_type.clone())
+                .or_insert_with(|| structure.clone());
+        }
+        for (code, segment) in &other.segments {
+            config.segments.insert(code.clone(), segment.clone());
+        }
+    }
+
     pub fn reload_from_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let content = fs::read_to_string(path)?;
         let new_config: TranslationConfig =
@@ -138,6 +177,7 @@ impl Default for TranslationRegistry {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::translations::DataType;
 
     #[test]
     fn test_load_default() {
@@ -160,7 +200,30 @@ mod tests {
         assert!(dtm_config.qualifiers.contains_key("137"));
 
         // Check element mapping
-        let element = registry.get_element("BGM", "1");
-        assert!(matches!(element, Some(ElementConfig::Simple(_))));
+        let element = registry.get_element("BGM", "1").unwrap();
+        assert_eq!(element.label(), "MessageName");
+        assert_eq!(
+            element.typed().and_then(|field| field.data_type),
+            Some(DataType::Code)
+        );
+
+        // Mandatory elements
+        assert!(registry.get_element("BGM", "2").unwrap().mandatory());
+        assert!(!registry.get_element("BGM", "3").unwrap().mandatory());
+        assert!(registry.get_element("UNH", "1").unwrap().mandatory());
+        assert!(registry.get_element("UNT", "2").unwrap().mandatory());
+    }
+
+    #[test]
+    fn test_version_mandatory_elements() {
+        let registry = TranslationRegistry::from_version("D96A").unwrap();
+        let Some(ElementConfig::Composite { components, .. }) = registry.get_element("QTY", "1")
+        else {
+            panic!("QTY should start with a composite");
+        };
+        assert!(registry.get_element("QTY", "1").unwrap().mandatory());
+        assert!(components["1"].mandatory() && components["2"].mandatory());
+        assert!(!components["3"].mandatory());
+        assert!(!registry.get_element("BGM", "2").unwrap().mandatory());
     }
 }
@@ -0,0 +1,202 @@
+use crate::parser::tokenizer::Delimiters;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// What a translated value holds, deciding its JSON type
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+    /// JSON number
+    Numeric,
+    /// Digits of a DTM value (`CCYYMMDD`, `CCYYMMDDHHMM`, ...), kept as a string
+    Date,
+    /// Code list value, kept as a string
+    Code,
+    Text,
+}
+
+/// Character set of an EDIFACT data element representation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    /// `a`
+    Alphabetic,
+    /// `n`
+    Numeric,
+    /// `an`
+    Alphanumeric,
+}
+
+/// EDIFACT data element representation such as `an..35`, `n..15`, `a3` or `an1..3`
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct DataFormat {
+    pub char_class: CharClass,
+    pub min: usize,
+    pub max: usize,
+}
+
+impl FromStr for DataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid data element format '{}'", s);
+        let (char_class, length) = if let Some(rest) = s.strip_prefix("an") {
+            (CharClass::Alphanumeric, rest)
+        } else if let Some(rest) = s.strip_prefix('a') {
+            (CharClass::Alphabetic, rest)
+        } else if let Some(rest) = s.strip_prefix('n') {
+            (CharClass::Numeric, rest)
+        } else {
+            return Err(invalid());
+        };
+        // `..35` up to 35, `1..3` between 1 and 3, `3` exactly 3
+        let (min, max) = match length.split_once("..") {
+            Some(("", max)) => (0, max.parse().map_err(|_| invalid())?),
+            Some((min, max)) => (
+                min.parse().map_err(|_| invalid())?,
+                max.parse().map_err(|_| invalid())?,
+            ),
+            None => {
+                let len = length.parse().map_err(|_| invalid())?;
+                (len, len)
+            }
+        };
+        Ok(Self {
+            char_class,
+            min,
+            max,
+        })
+    }
+}
+
+impl TryFrom<String> for DataFormat {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<DataFormat> for String {
+    fn from(format: DataFormat) -> Self {
+        format.to_string()
+    }
+}
+
+impl fmt::Display for DataFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let char_class = match self.char_class {
+            CharClass::Alphabetic => "a",
+            CharClass::Numeric => "n",
+            CharClass::Alphanumeric => "an",
+        };
+        match (self.min, self.max) {
+            (0, max) => write!(f, "{}..{}", char_class, max),
+            (min, max) if min == max => write!(f, "{}{}", char_class, max),
+            (min, max) => write!(f, "{}{}..{}", char_class, min, max),
+        }
+    }
+}
+
+impl DataFormat {
+    /// Checks the length (digits only for numeric values, as EDIFACT does not count
+    /// the sign and the decimal mark) and the characters of `value`
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        let length = match self.char_class {
+            CharClass::Numeric => value.chars().filter(char::is_ascii_digit).count(),
+            _ => value.chars().count(),
+        };
+        if length < self.min || length > self.max {
+            return Err(format!("has {} characters, format is {}", length, self));
+        }
+        if self.char_class == CharClass::Alphabetic && value.chars().any(|c| c.is_ascii_digit()) {
+            return Err(format!("has digits, format is {}", self));
+        }
+        Ok(())
+    }
+}
+
+/// Converts a raw element value to JSON following its declared type and format.
+///
+/// Numbers may use the decimal mark of the interchange. On failure the error explains
+/// why; callers keep the raw text and report it.
+pub fn convert_value(
+    raw: &str,
+    data_type: Option<DataType>,
+    format: Option<DataFormat>,
+    delimiters: &Delimiters,
+) -> Result<Value, String> {
+    if let Some(format) = format {
+        format.check(raw)?;
+    }
+    let data_type = data_type.unwrap_or(match format.map(|f| f.char_class) {
+        Some(CharClass::Numeric) => DataType::Numeric,
+        _ => DataType::Text,
+    });
+    match data_type {
+        DataType::Numeric => {
+            let normalized = delimiters.normalize_decimal(raw);
+            if let Ok(int) = normalized.parse::<i64>() {
+                return Ok(Value::from(int));
+            }
+            normalized
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(Value::from)
+                .ok_or_else(|| "is not a number".to_string())
+        }
+        DataType::Date => {
+            // Ranges (`20250101-20250131`) and weeks keep their separators
+            if !raw.is_empty() && raw.chars().all(|c| c.is_ascii_digit() || c == '-') {
+                Ok(Value::from(raw))
+            } else {
+                Err("is not a date".to_string())
+            }
+        }
+        DataType::Code | DataType::Text => Ok(Value::from(raw)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_and_conversion() {
+        let format: DataFormat = "an..35".parse().unwrap();
+        assert_eq!(
+            (format.char_class, format.min, format.max),
+            (CharClass::Alphanumeric, 0, 35)
+        );
+        assert_eq!(
+            "an1..3".parse::<DataFormat>().unwrap().to_string(),
+            "an1..3"
+        );
+        assert_eq!("a3".parse::<DataFormat>().unwrap().to_string(), "a3");
+        assert!("x..3".parse::<DataFormat>().is_err());
+
+        let delimiters = Delimiters::default();
+        let numeric = Some("n..15".parse().unwrap());
+        assert_eq!(
+            convert_value("12,5", None, numeric, &delimiters),
+            Ok(Value::from(12.5))
+        );
+        assert_eq!(
+            convert_value("-4", None, numeric, &delimiters),
+            Ok(Value::from(-4))
+        );
+        assert!(convert_value("12A", None, numeric, &delimiters).is_err());
+        assert!(convert_value("1234567890123456", None, numeric, &delimiters).is_err());
+
+        let code = Some("an..3".parse().unwrap());
+        assert_eq!(
+            convert_value("220", Some(DataType::Code), code, &delimiters),
+            Ok(Value::from("220"))
+        );
+        assert!(convert_value("2201", Some(DataType::Code), code, &delimiters).is_err());
+        assert!(convert_value("2025-01-XX", Some(DataType::Date), None, &delimiters).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use crate::dedupe::SeenMessage;
+use crate::model::streaming::FunctionalGroup;
+use crate::parser::segment::Segment;
+use crate::parser::segment_reader::SourcePosition;
+use crate::parser::tokenizer::SegmentView;
+use crate::translations::{ElementConfig, TranslationRegistry};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+/// What is written for each message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Flattened [`StreamingDocument`](crate::model::streaming::StreamingDocument) with
+    /// header fields and lines
+    #[default]
+    Document,
+    /// [`MessageTree`] keeping every segment, grouped as in the message structure
+    Tree,
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "document" => Ok(OutputMode::Document),
+            "tree" => Ok(OutputMode::Tree),
+            other => Err(format!(
+                "unknown output mode '{}' (expected document or tree)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputMode::Document => "document",
+            OutputMode::Tree => "tree",
+        })
+    }
+}
+
+/// One message as segment groups → segments → elements → components
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageTree {
+    pub interchange_id: String,
+    pub sender: String,
+    pub receiver: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<FunctionalGroup>,
+    pub message_reference: String,
+    pub message_type: String,
+    pub content: Vec<TreeNode>,
+    /// See [`StreamingDocument::duplicate`](crate::model::streaming::StreamingDocument::duplicate)
+    #[serde(
+        rename = "_duplicate",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub duplicate: Option<SeenMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TreeNode {
+    Segment(TreeSegment),
+    Group(TreeGroup),
+}
+
+/// One repetition of a segment group, starting with its trigger segment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeGroup {
+    pub group: String,
+    pub content: Vec<TreeNode>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TreeSegment {
+    pub tag: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qualifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qualifier_label: Option<String>,
+    pub elements: Vec<TreeElement>,
+    #[serde(rename = "_source", default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourcePosition>,
+}
+
+/// A data element: `value` when it has a single component, `components` otherwise.
+/// Positions follow the registry numbering for segments it describes (the qualifier
+/// of a `use_qualifier` segment is not counted) and the segment itself otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TreeElement {
+    pub position: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<TreeComponent>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TreeComponent {
+    pub position: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub value: String,
+}
+
+/// Builds a [`TreeSegment`], labelled from the registry when it describes the segment
+pub fn tree_segment(
+    view: &SegmentView<'_, '_>,
+    segment: &Segment<'_>,
+    registry: Option<&TranslationRegistry>,
+) -> TreeSegment {
+    let tag = view.tag().into_owned();
+    if let (
+        Segment::Dynamic {
+            qualifier,
+            elements,
+            ..
+        },
+        Some(registry),
+    ) = (segment, registry)
+    {
+        if let Some(config) = registry.get_segment(&tag) {
+            let sub = qualifier
+                .as_deref()
+                .filter(|_| config.use_qualifier)
+                .and_then(|q| registry.get_qualifier(&tag, q));
+            let labels = sub.as_ref().map_or(&config.elements, |sub| &sub.elements);
+            let elements = (1..=elements.len())
+                .filter_map(|pos| {
+                    let values: Vec<Cow<str>> = elements.components(pos).collect();
+                    tree_element(pos, &values, labels.get(&pos.to_string()))
+                })
+                .collect();
+            return TreeSegment {
+                label: Some(config.label.clone()),
+                qualifier: qualifier.as_deref().map(str::to_string),
+                qualifier_label: sub.map(|sub| sub.label),
+                elements,
+                tag,
+                source: None,
+            };
+        }
+    }
+
+    let elements = (1..view.element_count())
+        .filter_map(|idx| {
+            let values: Vec<Cow<str>> = view.components(idx).collect();
+            tree_element(idx, &values, None)
+        })
+        .collect();
+    TreeSegment {
+        tag,
+        elements,
+        ..Default::default()
+    }
+}
+
+fn tree_element(
+    position: usize,
+    values: &[Cow<str>],
+    config: Option<&ElementConfig>,
+) -> Option<TreeElement> {
+    if values.iter().all(|v| v.is_empty()) {
+        return None;
+    }
+    let label = config.map(|config| config.label().to_string());
+    let component_label = |idx: usize| -> Option<String> {
+        match config {
+            Some(ElementConfig::Composite { components, .. }) => components
+                .get(&idx.to_string())
+                .map(|component| component.label().to_string()),
+            _ => None,
+        }
+    };
+
+    if values.len() == 1 {
+        return Some(TreeElement {
+            position,
+            label,
+            value: Some(values[0].to_string()),
+            components: Vec::new(),
+        });
+    }
+    Some(TreeElement {
+        position,
+        label,
+        value: None,
+        components: values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(idx, v)| TreeComponent {
+                position: idx + 1,
+                label: component_label(idx + 1),
+                value: v.to_string(),
+            })
+            .collect(),
+    })
+}
+
+/// Assembles a [`MessageTree`] from segments and the groups open when each was placed
+#[derive(Debug)]
+pub struct TreeBuilder {
+    message: MessageTree,
+    open: Vec<TreeGroup>,
+}
+
+impl TreeBuilder {
+    pub fn new(message: MessageTree) -> Self {
+        Self {
+            message,
+            open: Vec::new(),
+        }
+    }
+
+    /// Adds a segment inside `groups` (outermost first, as returned by
+    /// [`StructureTracker::open_groups`](crate::parser::structure::StructureTracker::open_groups)).
+    /// `opened` starts a new repetition of the innermost group. UNH and UNT always
+    /// stay at message level.
+    pub fn push(&mut self, groups: &[&str], opened: bool, segment: TreeSegment) {
+        let groups = if matches!(segment.tag.as_str(), "UNH" | "UNT") {
+            &[][..]
+        } else {
+            groups
+        };
+        let kept = self
+            .open
+            .iter()
+            .zip(groups)
+            .take_while(|(open, name)| open.group == **name)
+            .count();
+        // A repeated trigger closes the innermost group even when the names match
+        let kept = if opened && kept == groups.len() {
+            kept.min(groups.len().saturating_sub(1))
+        } else {
+            kept
+        };
+        self.close_to(kept);
+        for name in &groups[kept..] {
+            self.open.push(TreeGroup {
+                group: name.to_string(),
+                content: Vec::new(),
+            });
+        }
+        self.content().push(TreeNode::Segment(segment));
+    }
+
+    pub fn finish(mut self) -> MessageTree {
+        self.close_to(0);
+        self.message
+    }
+
+    fn content(&mut self) -> &mut Vec<TreeNode> {
+        match self.open.last_mut() {
+            Some(group) => &mut group.content,
+            None => &mut self.message.content,
+        }
+    }
+
+    fn close_to(&mut self, depth: usize) {
+        while self.open.len() > depth {
+            let group = self.open.pop().expect("open group");
+            self.content().push(TreeNode::Group(group));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::edifact::parse_segment_view;
+    use crate::parser::structure::StructureTracker;
+    use crate::parser::tokenizer::{Delimiters, SegmentTokenizer};
+
+    #[test]
+    fn test_tree_groups_and_labels() {
+        let registry = TranslationRegistry::new().unwrap();
+        let mut tracker = StructureTracker::new(registry.get_message_structure("ORDERS"));
+        let mut builder = TreeBuilder::new(MessageTree::default());
+        let mut tokenizer = SegmentTokenizer::new();
+        let delimiters = Delimiters::default();
+
+        for raw in [
+            "UNH+1+ORDERS:D:96A:UN",
+            "BGM+220+PO1",
+            "NAD+BY+BUYER1",
+            "NAD+SU+SELLER1",
+            "LIN+1++SKU1:EN",
+            "QTY+21:4",
+            "LIN+2++SKU2:EN",
+            "ZZZ+X:Y",
+            "UNS+S",
+            "UNT+9+1",
+        ] {
+            let view = tokenizer.tokenize(raw.as_bytes(), &delimiters);
+            let segment = parse_segment_view(&view, Some(&registry));
+            tracker.place(&view.tag());
+            builder.push(
+                &tracker.open_groups(),
+                tracker.opened(),
+                tree_segment(&view, &segment, Some(&registry)),
+            );
+        }
+        let tree = builder.finish();
+
+        let shape: Vec<String> = tree
+            .content
+            .iter()
+            .map(|node| match node {
+                TreeNode::Segment(s) => s.tag.clone(),
+                TreeNode::Group(g) => format!("{}({})", g.group, g.content.len()),
+            })
+            .collect();
+        assert_eq!(
+            shape,
+            ["UNH", "BGM", "SG2(1)", "SG2(1)", "SG25(2)", "SG25(2)", "UNS", "UNT"]
+        );
+
+        let TreeNode::Group(line) = &tree.content[5] else {
+            panic!("expected a line group");
+        };
+        let TreeNode::Segment(unknown) = &line.content[1] else {
+            panic!("expected a segment");
+        };
+        assert_eq!(unknown.tag, "ZZZ");
+        assert_eq!(unknown.label, None);
+        assert_eq!(unknown.elements[0].components.len(), 2);
+
+        let TreeNode::Group(buyer) = &tree.content[2] else {
+            panic!("expected a party group");
+        };
+        let TreeNode::Segment(nad) = &buyer.content[0] else {
+            panic!("expected a segment");
+        };
+        assert_eq!(nad.label.as_deref(), Some("PartyInformation"));
+        assert_eq!(nad.qualifier_label.as_deref(), Some("Buyer"));
+        assert_eq!(nad.elements[0].label.as_deref(), Some("PartyId"));
+        assert_eq!(nad.elements[0].value.as_deref(), Some("BUYER1"));
+    }
+}
@@ -0,0 +1,542 @@
+use crate::model::streaming::StreamingDocument;
+use crate::parser::segment_reader::SourcePosition;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Outcome of a check, ordered by severity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[default]
+    Ok,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Finding {
+    pub status: Status,
+    pub reason: String,
+    /// Segment where the problem was detected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<SourcePosition>,
+}
+
+/// Validation result for one message, or for a whole interchange when
+/// `message_reference` is `None`. Written as one JSON line per report.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub interchange_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_number: Option<String>,
+    pub status: Status,
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    fn push(&mut self, position: Option<SourcePosition>, status: Status, reason: String) {
+        self.status = self.status.max(status);
+        self.findings.push(Finding {
+            status,
+            reason,
+            position,
+        });
+    }
+}
+
+/// How segments missing from the translation dictionary, and dictionary elements
+/// marked mandatory but left empty, are treated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strictness {
+    /// Ignored, as before the policy existed
+    #[default]
+    Lenient,
+    /// Reported as warnings on the message report
+    Warn,
+    /// Reported as errors, and the run fails listing the offending segments
+    Strict,
+}
+
+impl FromStr for Strictness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lenient" => Ok(Strictness::Lenient),
+            "warn" => Ok(Strictness::Warn),
+            "strict" => Ok(Strictness::Strict),
+            other => Err(format!(
+                "unknown strictness '{}' (expected lenient, warn or strict)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Strictness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Strictness::Lenient => "lenient",
+            Strictness::Warn => "warn",
+            Strictness::Strict => "strict",
+        })
+    }
+}
+
+/// Strictness of a run, with overrides for interchanges from given UNB senders
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StrictnessPolicy {
+    #[serde(default)]
+    pub default: Strictness,
+    #[serde(default)]
+    pub partners: HashMap<String, Strictness>,
+}
+
+impl StrictnessPolicy {
+    pub fn new(default: Strictness) -> Self {
+        StrictnessPolicy {
+            default,
+            partners: HashMap::new(),
+        }
+    }
+
+    pub fn with_partner(mut self, sender: impl Into<String>, strictness: Strictness) -> Self {
+        self.partners.insert(sender.into(), strictness);
+        self
+    }
+
+    pub fn for_sender(&self, sender: &str) -> Strictness {
+        self.partners.get(sender).copied().unwrap_or(self.default)
+    }
+}
+
+/// One partner override, written `SENDER=LEVEL`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartnerStrictness {
+    pub sender: String,
+    pub strictness: Strictness,
+}
+
+impl FromStr for PartnerStrictness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sender, level) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected SENDER=LEVEL, got '{}'", s))?;
+        if sender.is_empty() {
+            return Err(format!("missing sender in '{}'", s));
+        }
+        Ok(PartnerStrictness {
+            sender: sender.to_string(),
+            strictness: level.parse()?,
+        })
+    }
+}
+
+/// Checks envelope control counts and references while segments stream by.
+///
+/// Processors call [`EnvelopeValidator::segment`] for every segment and the
+/// `begin_*`/`end_*` methods on service segments; finished reports are collected
+/// with [`EnvelopeValidator::take_reports`].
+#[derive(Debug, Default)]
+pub struct EnvelopeValidator {
+    interchange: Option<OpenInterchange>,
+    group: Option<OpenGroup>,
+    message: Option<OpenMessage>,
+    reports: Vec<ValidationReport>,
+    position: Option<SourcePosition>,
+}
+
+#[derive(Debug)]
+struct OpenInterchange {
+    reference: String,
+    messages: u64,
+    groups: u64,
+    report: ValidationReport,
+}
+
+#[derive(Debug)]
+struct OpenGroup {
+    reference: String,
+    messages: u64,
+}
+
+#[derive(Debug)]
+struct OpenMessage {
+    reference: String,
+    segments: u64,
+    /// Raised on content (data type conversions, ...) while the message was open
+    findings: Vec<Finding>,
+}
+
+impl EnvelopeValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts one segment towards the open message; findings raised until the next
+    /// call point at `position`
+    pub fn segment(&mut self, position: SourcePosition) {
+        self.position = Some(position);
+        if let Some(message) = self.message.as_mut() {
+            message.segments += 1;
+        }
+    }
+
+    /// UNB. An interchange still open is closed as missing its UNZ.
+    pub fn begin_interchange(&mut self, reference: &str) {
+        if self.interchange.is_some() {
+            self.close_interchange(None);
+        }
+        self.interchange = Some(OpenInterchange {
+            reference: reference.to_string(),
+            messages: 0,
+            groups: 0,
+            report: ValidationReport {
+                interchange_id: reference.to_string(),
+                ..Default::default()
+            },
+        });
+    }
+
+    /// UNZ with its message (or group) count and control reference
+    pub fn end_interchange(&mut self, count: &str, reference: &str) {
+        if self.interchange.is_none() {
+            let mut report = ValidationReport {
+                interchange_id: reference.to_string(),
+                ..Default::default()
+            };
+            report.push(
+                self.position,
+                Status::Error,
+                "UNZ without a matching UNB".to_string(),
+            );
+            self.reports.push(report);
+            return;
+        }
+        self.close_interchange(Some((count, reference)));
+    }
+
+    /// UNG
+    pub fn begin_group(&mut self, reference: &str) {
+        if let Some(interchange) = self.interchange.as_mut() {
+            interchange.groups += 1;
+        }
+        self.group = Some(OpenGroup {
+            reference: reference.to_string(),
+            messages: 0,
+        });
+    }
+
+    /// UNE with its message count and group reference
+    pub fn end_group(&mut self, count: &str, reference: &str) {
+        let Some(group) = self.group.take() else {
+            self.interchange_finding(Status::Error, "UNE without a matching UNG".to_string());
+            return;
+        };
+        if reference != group.reference {
+            self.interchange_finding(
+                Status::Error,
+                format!(
+                    "UNE group reference {} does not match UNG reference {}",
+                    reference, group.reference
+                ),
+            );
+        }
+        if let Some(reason) = check_count(count, group.messages, "UNE", "messages") {
+            self.interchange_finding(
+                Status::Error,
+                format!("group {}: {}", group.reference, reason),
+            );
+        }
+    }
+
+    /// UNH. `unterminated` is the document of a previous message that never got
+    /// its UNT, if any.
+    pub fn begin_message(&mut self, reference: &str, unterminated: Option<&StreamingDocument>) {
+        if self.message.is_some() {
+            self.end_message(None, unterminated);
+        }
+        if let Some(interchange) = self.interchange.as_mut() {
+            interchange.messages += 1;
+        }
+        if let Some(group) = self.group.as_mut() {
+            group.messages += 1;
+        }
+        self.message = Some(OpenMessage {
+            reference: reference.to_string(),
+            segments: 1,
+            findings: Vec::new(),
+        });
+    }
+
+    /// Adds a finding about the content of the open message at the current segment
+    pub fn message_finding(&mut self, status: Status, reason: String) {
+        if let Some(message) = self.message.as_mut() {
+            message.findings.push(Finding {
+                status,
+                reason,
+                position: self.position,
+            });
+        }
+    }
+
+    /// UNT (`trailer` holds its segment count and message reference), or the end of a
+    /// message that was cut short when `trailer` is `None`. Returns the message status.
+    pub fn end_message(
+        &mut self,
+        trailer: Option<(&str, &str)>,
+        doc: Option<&StreamingDocument>,
+    ) -> Status {
+        let Some(message) = self.message.take() else {
+            return Status::Ok;
+        };
+        let mut report = ValidationReport {
+            interchange_id: self
+                .interchange
+                .as_ref()
+                .map(|i| i.reference.clone())
+                .unwrap_or_default(),
+            group_reference: self.group.as_ref().map(|g| g.reference.clone()),
+            message_reference: Some(message.reference.clone()),
+            document_number: doc.map(|d| d.document_number.clone()),
+            ..Default::default()
+        };
+        for finding in message.findings {
+            report.push(finding.position, finding.status, finding.reason);
+        }
+
+        match trailer {
+            Some((count, reference)) => {
+                if let Some(reason) = check_count(count, message.segments, "UNT", "segments") {
+                    report.push(self.position, Status::Error, reason);
+                }
+                if reference != message.reference {
+                    report.push(
+                        self.position,
+                        Status::Error,
+                        format!(
+                            "UNT message reference {} does not match UNH reference {}",
+                            reference, message.reference
+                        ),
+                    );
+                }
+            }
+            None => report.push(
+                self.position,
+                Status::Error,
+                "message has no UNT trailer".to_string(),
+            ),
+        }
+
+        if let Some(doc) = doc {
+            if let Some(declared) = doc.line_count_check {
+                if declared != doc.lines.len() as u64 {
+                    report.push(
+                        self.position,
+                        Status::Warn,
+                        format!(
+                            "CNT+2 declares {} lines, message has {}",
+                            declared,
+                            doc.lines.len()
+                        ),
+                    );
+                }
+            }
+        }
+
+        let status = report.status;
+        self.reports.push(report);
+        status
+    }
+
+    /// End of input: closes whatever is still open. Returns the status of the
+    /// unterminated message, if there was one.
+    pub fn finish(&mut self, unterminated: Option<&StreamingDocument>) -> Status {
+        let status = self.end_message(None, unterminated);
+        if self.interchange.is_some() {
+            self.close_interchange(None);
+        }
+        status
+    }
+
+    /// Reports completed since the last call
+    pub fn take_reports(&mut self) -> Vec<ValidationReport> {
+        std::mem::take(&mut self.reports)
+    }
+
+    fn close_interchange(&mut self, trailer: Option<(&str, &str)>) {
+        if self.message.is_some() {
+            self.end_message(None, None);
+        }
+        let Some(mut interchange) = self.interchange.take() else {
+            return;
+        };
+        let report = &mut interchange.report;
+        if let Some(group) = self.group.take() {
+            report.push(
+                self.position,
+                Status::Error,
+                format!("group {} has no UNE trailer", group.reference),
+            );
+        }
+        match trailer {
+            Some((count, reference)) => {
+                // UNZ counts functional groups when the interchange uses them
+                let (actual, unit) = if interchange.groups > 0 {
+                    (interchange.groups, "groups")
+                } else {
+                    (interchange.messages, "messages")
+                };
+                if let Some(reason) = check_count(count, actual, "UNZ", unit) {
+                    report.push(self.position, Status::Error, reason);
+                }
+                if reference != interchange.reference {
+                    report.push(
+                        self.position,
+                        Status::Error,
+                        format!(
+                            "UNZ control reference {} does not match UNB reference {}",
+                            reference, interchange.reference
+                        ),
+                    );
+                }
+            }
+            None => report.push(
+                self.position,
+                Status::Error,
+                "interchange has no UNZ trailer".to_string(),
+            ),
+        }
+        self.reports.push(interchange.report);
+    }
+
+    fn interchange_finding(&mut self, status: Status, reason: String) {
+        if let Some(interchange) = self.interchange.as_mut() {
+            interchange.report.push(self.position, status, reason);
+        }
+    }
+}
+
+/// Compares a declared control count with the actual one
+fn check_count(declared: &str, actual: u64, segment: &str, unit: &str) -> Option<String> {
+    match declared.trim().parse::<u64>() {
+        Ok(declared) if declared == actual => None,
+        Ok(declared) => Some(format!(
+            "{} declares {} {}, found {}",
+            segment, declared, unit, actual
+        )),
+        Err(_) => Some(format!(
+            "{} control count '{}' is not a number",
+            segment, declared
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(validator: &mut EnvelopeValidator, reference: &str, segments: u64) {
+        validator.segment(SourcePosition::default());
+        validator.begin_message(reference, None);
+        for _ in 1..segments {
+            validator.segment(SourcePosition::default());
+        }
+    }
+
+    #[test]
+    fn test_valid_interchange() {
+        let mut validator = EnvelopeValidator::new();
+        validator.begin_interchange("ICR1");
+        message(&mut validator, "1", 3);
+        let doc = StreamingDocument {
+            line_count_check: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            validator.end_message(Some(("3", "1")), Some(&doc)),
+            Status::Ok
+        );
+        validator.end_interchange("1", "ICR1");
+
+        let reports = validator.take_reports();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.status == Status::Ok));
+        assert_eq!(reports[0].message_reference.as_deref(), Some("1"));
+        assert_eq!(reports[1].message_reference, None);
+    }
+
+    #[test]
+    fn test_control_count_mismatches() {
+        let mut validator = EnvelopeValidator::new();
+        validator.begin_interchange("ICR1");
+        validator.begin_group("G1");
+        message(&mut validator, "1", 3);
+        let unt = SourcePosition {
+            byte_offset: 120,
+            segment_index: 7,
+            message_index: 1,
+        };
+        validator.segment(unt);
+        let doc = StreamingDocument {
+            line_count_check: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            validator.end_message(Some(("3", "9")), Some(&doc)),
+            Status::Error
+        );
+        validator.end_group("2", "G1");
+        validator.end_interchange("1", "ICR2");
+
+        let reports = validator.take_reports();
+        let message = &reports[0];
+        assert_eq!(message.group_reference.as_deref(), Some("G1"));
+        let reasons: Vec<_> = message.findings.iter().map(|f| f.reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                "UNT declares 3 segments, found 4",
+                "UNT message reference 9 does not match UNH reference 1",
+                "CNT+2 declares 2 lines, message has 0",
+            ]
+        );
+        assert_eq!(message.findings[2].status, Status::Warn);
+        assert_eq!(message.findings[0].position, Some(unt));
+
+        let interchange = &reports[1];
+        assert_eq!(interchange.status, Status::Error);
+        assert_eq!(interchange.findings.len(), 2);
+        assert_eq!(
+            interchange.findings[0].reason,
+            "group G1: UNE declares 2 messages, found 1"
+        );
+    }
+
+    #[test]
+    fn test_missing_trailers() {
+        let mut validator = EnvelopeValidator::new();
+        validator.begin_interchange("ICR1");
+        message(&mut validator, "1", 2);
+        message(&mut validator, "2", 2);
+        assert_eq!(validator.finish(None), Status::Error);
+
+        let reports = validator.take_reports();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].findings[0].reason, "message has no UNT trailer");
+        assert_eq!(reports[1].message_reference.as_deref(), Some("2"));
+        assert_eq!(
+            reports[2].findings[0].reason,
+            "interchange has no UNZ trailer"
+        );
+    }
+}
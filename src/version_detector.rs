@@ -1,10 +1,15 @@
-use crate::parser::tokenizer::tokenize_segment;
+use crate::parser::tokenizer::{tokenize_segment_with, Delimiters};
 
 /// Extracts EDIFACT version from UNH segment
 /// UNH format: UNH+<message reference>+<message type>:<version>:<release>:<controlling agency>+...
 /// Returns combined version string like "D96A"
 pub fn extract_version_from_unh(unh_segment: &str) -> Option<String> {
-    let tokens = tokenize_segment(unh_segment);
+    extract_version_from_unh_with(unh_segment, &Delimiters::default())
+}
+
+/// Same as [`extract_version_from_unh`] for interchanges that declare their own separators in UNA
+pub fn extract_version_from_unh_with(unh_segment: &str, delimiters: &Delimiters) -> Option<String> {
+    let tokens = tokenize_segment_with(unh_segment, delimiters);
     if tokens.is_empty() || tokens[0][0] != "UNH" {
         return None;
     }
@@ -14,8 +19,8 @@ pub fn extract_version_from_unh(unh_segment: &str) -> Option<String> {
     // tokens[1] = ["1"]
     // tokens[2] = ["ORDERS", "D", "96A", "UN"]
     if tokens.len() >= 3 && tokens[2].len() >= 3 {
-        let version = tokens[2][1]; // D
-        let release = tokens[2][2]; // 96A
+        let version = &tokens[2][1]; // D
+        let release = &tokens[2][2]; // 96A
         return Some(format!("{}{}", version, release));
     }
 
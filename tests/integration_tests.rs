@@ -1,8 +1,18 @@
-use filereduce::processor::{process, FileFormat};
+use async_trait::async_trait;
+use engine_filereduce::query::parser::Parser as QueryParser;
+use filereduce::core::EdifactProcessor;
+use filereduce::dedupe::{DedupeKey, DedupeStore, Deduplicator, DuplicatePolicy};
+use filereduce::error::{FileReduceError, Result};
+use filereduce::parser::xml::XmlOptions;
+use filereduce::processor::{
+    process, process_with_options, FileFormat, ProcessOptions, ProcessOutputs,
+};
 use filereduce::sink::file::FileDataSink;
-use filereduce::sink::DataSink;
+use filereduce::sink::{DataSink, SinkItem};
+use futures::StreamExt;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
+use std::sync::{Arc, Mutex};
 
 #[tokio::test]
 async fn test_edifact_processing() {
@@ -36,13 +46,172 @@ async fn test_edifact_processing() {
 async fn test_xml_processing() {
     let input = File::open("tests/fixtures/sample.xml").expect("Failed to open XML file");
     let mut output = Vec::new();
-    let mut writer = BufWriter::new(&mut output);
-    let mut sink = FileDataSink::new(&mut writer);
+    {
+        let mut writer = BufWriter::new(&mut output);
+        let mut sink = FileDataSink::new(&mut writer);
+        process(BufReader::new(input), &mut sink, FileFormat::Xml, None)
+            .await
+            .expect("Failed to process XML file");
+        sink.flush().await.expect("Failed to flush");
+    }
+
+    let output_str = String::from_utf8(output).expect("Invalid UTF-8");
+    let lines: Vec<&str> = output_str.lines().collect();
+    assert_eq!(lines.len(), 7, "Should read every <record>");
+
+    let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(first["number"], "ORDER001");
+}
+
+#[tokio::test]
+async fn test_xml_record_path() {
+    let input = File::open("tests/fixtures/sample.xml").expect("Failed to open XML file");
+    let options = ProcessOptions {
+        xml: XmlOptions {
+            record_path: Some("/orders/record".parse().unwrap()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut query = QueryParser::new("qty > 10");
+    let expr = query.parse();
+    let mut output = Vec::new();
+    {
+        let mut writer = BufWriter::new(&mut output);
+        let mut sink = FileDataSink::new(&mut writer);
+        let summary = process_with_options(
+            BufReader::new(input),
+            &mut sink,
+            FileFormat::Xml,
+            Some(&expr),
+            &options,
+            ProcessOutputs::default(),
+        )
+        .await
+        .expect("Failed to process XML file");
+        sink.flush().await.expect("Failed to flush");
+        assert_eq!((summary.accepted, summary.filtered), (2, 5));
+    }
 
-    let result = process(BufReader::new(input), &mut sink, FileFormat::Xml, None).await;
+    let output_str = String::from_utf8(output).expect("Invalid UTF-8");
+    let skus: Vec<serde_json::Value> = output_str
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["sku"].clone())
+        .collect();
+    assert_eq!(skus, ["SKU002", "SKU003"]);
+}
+
+#[tokio::test]
+async fn test_summary_counts_written_messages() {
+    let input = "UNB+UNOC:3+S+R+260121:1200+REF1'UNH+1+ORDERS'BGM+220+PO1'UNT+3+1'UNH+2+ORDERS'BGM+220+PO1'UNT+3+2'UNH+3+ORDERS'BGM+220+PO3'UNT+3+3'UNH+4+ORDERS'BGM+220+PO2'UNT+3+4'UNZ+4+REF1'";
+    let expr = QueryParser::new("number < 'PO3'").parse();
+    let store = Arc::new(Mutex::new(DedupeStore::in_memory()));
+    let mut output = Vec::new();
+    let mut sink = FileDataSink::new(&mut output);
+    let summary = process_with_options(
+        input.as_bytes(),
+        &mut sink,
+        FileFormat::Edifact,
+        Some(&expr),
+        &ProcessOptions::default(),
+        ProcessOutputs {
+            dedupe: Some(Deduplicator::new(
+                store.clone(),
+                DedupeKey::default(),
+                DuplicatePolicy::Skip,
+            )),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("Failed to process messages");
+    sink.flush().await.expect("Failed to flush");
+
+    // PO3 is filtered out before the duplicate check, the second PO1 is skipped
+    assert_eq!(String::from_utf8(output).unwrap().lines().count(), 2);
+    assert_eq!(
+        summary.to_string(),
+        "2 messages accepted, 0 rejected, 1 filtered, 1 duplicates"
+    );
+    assert_eq!(store.lock().unwrap().len(), 2);
+}
+
+/// Stores document numbers two at a time, like the database sink, and fails the batch
+/// holding `fail_on`
+struct BatchSink {
+    batch: Vec<String>,
+    stored: Vec<String>,
+    fail_on: &'static str,
+}
 
-    // In current async implementation, XML is explicitly not supported yet.
-    assert!(result.is_err(), "Xml is not yet supported in async mode");
+#[async_trait]
+impl DataSink for BatchSink {
+    async fn send(&mut self, item: SinkItem) -> Result<()> {
+        if let SinkItem::Document(doc) = item {
+            self.batch.push(doc.document_number);
+        }
+        if self.batch.len() == 2 {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.batch.iter().any(|number| number == self.fail_on) {
+            return Err(FileReduceError::Parse("batch rejected".to_string()));
+        }
+        self.stored.append(&mut self.batch);
+        Ok(())
+    }
+
+    fn stored(&self) -> Option<u64> {
+        Some(self.stored.len() as u64)
+    }
+}
+
+#[tokio::test]
+async fn test_dedupe_commits_stored_batches() {
+    let input = "UNB+UNOC:3+S+R+260121:1200+REF1'UNH+1+ORDERS'BGM+220+PO1'UNT+3+1'UNH+2+ORDERS'BGM+220+PO2'UNT+3+2'UNH+3+ORDERS'BGM+220+PO3'UNT+3+3'UNH+4+ORDERS'BGM+220+PO4'UNT+3+4'UNZ+4+REF1'";
+    let store = Arc::new(Mutex::new(DedupeStore::in_memory()));
+    let run = |fail_on| {
+        let store = store.clone();
+        async move {
+            let mut sink = BatchSink {
+                batch: Vec::new(),
+                stored: Vec::new(),
+                fail_on,
+            };
+            let summary = process_with_options(
+                input.as_bytes(),
+                &mut sink,
+                FileFormat::Edifact,
+                None,
+                &ProcessOptions::default(),
+                ProcessOutputs {
+                    dedupe: Some(Deduplicator::new(
+                        store,
+                        DedupeKey::default(),
+                        DuplicatePolicy::Skip,
+                    )),
+                    ..Default::default()
+                },
+            )
+            .await;
+            (summary, sink.stored)
+        }
+    };
+
+    // The second batch fails: the first one is in the sink and its keys are kept
+    let (summary, stored) = run("PO3").await;
+    assert!(summary.is_err());
+    assert_eq!(stored, ["PO1", "PO2"]);
+    assert_eq!(store.lock().unwrap().len(), 2);
+
+    // Running again only sends what did not make it
+    let (summary, stored) = run("").await;
+    assert_eq!(summary.unwrap().duplicates, 2);
+    assert_eq!(stored, ["PO3", "PO4"]);
+    assert_eq!(store.lock().unwrap().len(), 4);
 }
 
 #[tokio::test]
@@ -86,3 +255,234 @@ async fn test_empty_files_handling() {
 
     std::fs::remove_file(temp_json).ok();
 }
+
+async fn process_sample(path: &str) -> Vec<serde_json::Value> {
+    query_sample(path, None).await
+}
+
+async fn query_sample(path: &str, query: Option<&str>) -> Vec<serde_json::Value> {
+    let input = File::open(path).expect("Failed to open sample");
+    let expr = query.map(|q| QueryParser::new(q).parse());
+    let mut output = Vec::new();
+    {
+        let mut writer = BufWriter::new(&mut output);
+        let mut sink = FileDataSink::new(&mut writer);
+        process(
+            BufReader::new(input),
+            &mut sink,
+            FileFormat::Edifact,
+            expr.as_ref(),
+        )
+        .await
+        .expect("Failed to process sample");
+        sink.flush().await.expect("Failed to flush");
+    }
+    String::from_utf8(output)
+        .expect("Invalid UTF-8")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Invalid JSON line"))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_invoice_totals_taxes_and_payment_terms() {
+    let docs = process_sample("test/samples/invoice.edi").await;
+    assert_eq!(docs.len(), 1);
+    let doc = &docs[0];
+    assert_eq!(doc["message_type"], "INVOIC");
+    assert_eq!(doc["doc_type"], "380");
+    assert_eq!(doc["document_number"], "INV98765");
+    assert_eq!(doc["lines"][0]["amount"], 250.0);
+    assert_eq!(doc["lines"][1]["qty"], 4.0);
+    assert_eq!(doc["lines"][0]["quantities"]["47"]["unit"], "PCE");
+    // Line tax amounts stay in their group
+    assert_eq!(
+        doc["lines"][0]["amounts"],
+        serde_json::json!({"203": 250.0})
+    );
+
+    let invoice = &doc["details"]["invoice"];
+    assert_eq!(invoice["total_amount"], 346.5);
+    assert_eq!(invoice["line_total"], 290.0);
+    assert_eq!(invoice["tax_amount"], 56.5);
+    // Line level TAX segments are not invoice taxes
+    let taxes = invoice["taxes"].as_array().unwrap();
+    assert_eq!(taxes.len(), 2);
+    assert_eq!(taxes[0]["tax_type"], "VAT");
+    assert_eq!(taxes[0]["rate"], 21.0);
+    assert_eq!(taxes[0]["amount"], 52.5);
+    assert_eq!(taxes[1]["taxable_amount"], 40.0);
+
+    let terms = invoice["payment_terms"].as_array().unwrap();
+    assert_eq!(terms.len(), 2);
+    assert_eq!(terms[0]["period_count"], 30);
+    assert_eq!(terms[0]["due_date"], "2025-02-19");
+    assert_eq!(terms[1]["terms_type"], "22");
+    assert_eq!(terms[1]["discount_percent"], 2.0);
+}
+
+#[tokio::test]
+async fn test_despatch_advice_packaging() {
+    let docs = process_sample("test/samples/desadv.edi").await;
+    let doc = &docs[0];
+    assert_eq!(doc["message_type"], "DESADV");
+    assert_eq!(doc["doc_type"], "351");
+    assert_eq!(doc["lines"].as_array().unwrap().len(), 2);
+
+    let despatch = &doc["details"]["despatch_advice"];
+    assert_eq!(despatch["despatch_date"], "2025-01-21");
+    assert_eq!(despatch["delivery_date"], "2025-01-23");
+    let packages = despatch["packages"].as_array().unwrap();
+    assert_eq!(packages.len(), 3);
+    assert_eq!(packages[0]["packaging"][0]["count"], 2);
+    assert_eq!(packages[1]["parent"], "1");
+    assert_eq!(packages[1]["sscc"][0], "354123450000000014");
+    assert_eq!(packages[1]["line_numbers"], serde_json::json!([1]));
+    assert_eq!(packages[2]["line_numbers"], serde_json::json!([2]));
+}
+
+#[tokio::test]
+async fn test_order_response_and_price_catalogue() {
+    let docs = process_sample("test/samples/ordrsp.edi").await;
+    let doc = &docs[0];
+    assert_eq!(doc["message_type"], "ORDRSP");
+    assert_eq!(doc["doc_type"], "231");
+    let response = &doc["details"]["order_response"];
+    assert_eq!(response["response_code"], "4");
+    assert_eq!(response["order_number"], "PO12345");
+    assert_eq!(response["order_date"], "2025-01-15");
+    assert_eq!(doc["lines"][1]["extra"]["ActionCode"], "3");
+
+    let docs = process_sample("test/samples/pricat.edi").await;
+    let doc = &docs[0];
+    assert_eq!(doc["message_type"], "PRICAT");
+    assert_eq!(doc["doc_type"], "9");
+    assert_eq!(doc["currency"], "EUR");
+    let catalogue = &doc["details"]["price_catalogue"];
+    assert_eq!(catalogue["validity_start"], "2025-01-01");
+    assert_eq!(catalogue["validity_end"], "2025-12-31");
+    assert_eq!(doc["lines"][1]["amount"], 10.0);
+}
+
+#[tokio::test]
+async fn test_repeated_party_qualifier_query() {
+    let input = "UNH+1+ORDERS'BGM+220+PO1'NAD+BY+BUYER1::9'NAD+DP+STORE7::9'NAD+DP+STORE8::9'RFF+API:D8'UNT+6+1'";
+    for (query, matches) in [
+        ("party.DP = 'STORE8' AND party.BY = 'BUYER1'", true),
+        ("party.DP = 'STORE7'", true),
+        ("party.DP = 'STORE8' AND party.DP.ref.API = 'D8'", true),
+        ("party.DP = 'STORE7' AND party.DP.ref.API = 'D8'", false),
+    ] {
+        let expr = QueryParser::new(query).parse();
+        let mut output = Vec::new();
+        {
+            let mut sink = FileDataSink::new(&mut output);
+            process(
+                input.as_bytes(),
+                &mut sink,
+                FileFormat::Edifact,
+                Some(&expr),
+            )
+            .await
+            .expect("Failed to process message");
+            sink.flush().await.expect("Failed to flush");
+        }
+        assert_eq!(!output.is_empty(), matches, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn test_parties_references_and_dates_by_qualifier() {
+    let docs = process_sample("test/samples/invoice.edi").await;
+    let doc = &docs[0];
+    assert_eq!(doc["parties"]["BY"][0]["id"], "BUYER01");
+    assert_eq!(doc["parties"]["SU"][0]["agency"], "9");
+    assert_eq!(doc["parties"]["SU"][0]["references"]["VA"], "ESB12345678");
+    assert_eq!(doc["references"]["ON"], "PO12345");
+    assert_eq!(doc["document_date"], "2025-01-20");
+    assert_eq!(doc["dates"]["137"]["value"], "2025-01-20");
+    assert_eq!(doc["dates"]["137"]["raw"], "20250120");
+    assert_eq!(doc["dates"]["35"]["value"], "2025-01-18");
+    // Payment terms dates stay in their group
+    assert!(doc["dates"].get("13").is_none());
+
+    let matched = query_sample(
+        "test/samples/invoice.edi",
+        Some("party.SU.ref.VA = 'ESB12345678' AND ref.ON = 'PO12345' AND date.35 = '2025-01-18'"),
+    )
+    .await;
+    assert_eq!(matched.len(), 1);
+    let matched = query_sample("test/samples/invoice.edi", Some("party.DP = 'STORE1'")).await;
+    assert!(matched.is_empty());
+    let matched = query_sample(
+        "test/samples/invoice.edi",
+        Some("qty.47 = 4 AND uom.47 = 'PCE' AND amount.203 = 40"),
+    )
+    .await;
+    assert_eq!(matched.len(), 1);
+    // ISO dates compare in date order
+    let matched = query_sample(
+        "test/samples/invoice.edi",
+        Some("date BETWEEN '2025-01-01' AND '2025-01-31' AND date.35 < '2025-01-20'"),
+    )
+    .await;
+    assert_eq!(matched.len(), 1);
+}
+
+#[tokio::test]
+async fn test_document_stream() {
+    let input = File::open("tests/fixtures/sample.edifact").unwrap();
+    let documents: Vec<_> = EdifactProcessor::new()
+        .into_document_stream(BufReader::new(input))
+        .collect()
+        .await;
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[0].as_ref().unwrap().document_number, "ORDER001");
+}
+
+#[tokio::test]
+async fn test_sync_and_async_paths_agree() {
+    // The last message has no UNT: both paths flush it at the end of the input
+    let input = "UNA:+.? '\
+UNB+UNOC:3+SENDER+RECEIVER+250120:1030+REF1'\
+UNH+1+ORDERS:D:96A:UN'BGM+220+PO1+9'DTM+137:20250120:102'\
+LIN+1++SKU1:EN'QTY+21:5'UNT+6+1'\
+UNH+2+ORDERS:D:96A:UN'BGM+220+PO2+9'LIN+1++SKU2:EN'QTY+21:7'";
+
+    let sync: Vec<serde_json::Value> = EdifactProcessor::new()
+        .documents(input.as_bytes())
+        .map(|doc| serde_json::to_value(doc.expect("Failed to read document")).unwrap())
+        .collect();
+
+    assert_eq!(sync.len(), 2);
+    assert_eq!(sync[1]["document_number"], "PO2");
+
+    // Sequential, then split across worker threads
+    for threads in [1, 3] {
+        let options = ProcessOptions {
+            threads,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        {
+            let mut sink = FileDataSink::new(&mut output);
+            process_with_options(
+                input.as_bytes(),
+                &mut sink,
+                FileFormat::Edifact,
+                None,
+                &options,
+                ProcessOutputs::default(),
+            )
+            .await
+            .expect("Failed to process EDI input");
+            sink.flush().await.expect("Failed to flush");
+        }
+        let asynchronous: Vec<serde_json::Value> = String::from_utf8(output)
+            .expect("Invalid UTF-8")
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(sync, asynchronous, "{} threads", threads);
+    }
+}